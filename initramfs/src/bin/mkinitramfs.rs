@@ -0,0 +1,69 @@
+// Packs the files below a directory into an initramfs archive
+//   mkinitramfs <directory> <output>
+// The output file is skipped if it is inside the directory, as are dotfiles
+// and dot directories like .gitignore.
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+use initramfs::{Builder, MODE_FILE};
+
+// page aligned so executables can be mapped in place
+const ALIGN: u32 = 4096;
+
+fn collect(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+  for entry in fs::read_dir(dir)? {
+    let entry = entry?;
+    if entry.file_name().to_string_lossy().starts_with('.') {
+      continue;
+    }
+    let path = entry.path();
+    if path.is_dir() {
+      collect(&path, files)?;
+    } else if path.is_file() {
+      files.push(path);
+    }
+  }
+  Ok(())
+}
+
+fn run(root: &Path, output: &Path) -> std::io::Result<usize> {
+  let mut files = Vec::new();
+  collect(root, &mut files)?;
+  // archives do not depend on the directory order
+  files.sort();
+  let output_abs = fs::canonicalize(output).ok();
+  let mut builder = Builder::new(ALIGN);
+  let mut count = 0;
+  for path in files.iter() {
+    if output_abs.is_some() && fs::canonicalize(path).ok() == output_abs {
+      continue;
+    }
+    let name = path.strip_prefix(root).unwrap().components()
+      .map(|c| c.as_os_str().to_string_lossy().into_owned())
+      .collect::<Vec<_>>()
+      .join("/");
+    let permissions = fs::metadata(path)?.permissions().mode() & 0o7777;
+    builder.add(&name, MODE_FILE | permissions, &fs::read(path)?);
+    count += 1;
+  }
+  fs::write(output, builder.build())?;
+  Ok(count)
+}
+
+fn main() {
+  let args: Vec<String> = std::env::args().collect();
+  if args.len() != 3 {
+    eprintln!("usage: {} <directory> <output>", args[0]);
+    exit(2);
+  }
+  match run(Path::new(&args[1]), Path::new(&args[2])) {
+    Ok(count) => println!("packed {} files into {}", count, args[2]),
+    Err(e) => {
+      eprintln!("mkinitramfs: {}", e);
+      exit(1);
+    },
+  }
+}
@@ -0,0 +1,74 @@
+use crate::{ENTRY_SIZE, HEADER_SIZE, MAGIC, MIN_ALIGN, VERSION};
+
+struct File {
+  name: String,
+  mode: u32,
+  data: Vec<u8>,
+}
+
+/// Writes archives, files keep the order they were added in
+pub struct Builder {
+  align: u32,
+  files: Vec<File>,
+}
+
+fn align_up(value: usize, align: usize) -> usize {
+  (value + align - 1) & !(align - 1)
+}
+
+impl Builder {
+  /// Panics if the alignment is not a power of two of at least MIN_ALIGN
+  pub fn new(align: u32) -> Builder {
+    assert!(align >= MIN_ALIGN && align.is_power_of_two(), "invalid alignment {}", align);
+    Builder { align, files: Vec::new() }
+  }
+  /// Adds a file, a leading '/' is removed from the name
+  pub fn add(&mut self, name: &str, mode: u32, data: &[u8]) -> &mut Builder {
+    self.files.push(File {
+      name: name.trim_start_matches('/').to_string(),
+      mode,
+      data: data.to_vec(),
+    });
+    self
+  }
+  pub fn build(&self) -> Vec<u8> {
+    let align = self.align as usize;
+    let names_len: usize = self.files.iter().map(|file| file.name.len()).sum();
+    let names_start = HEADER_SIZE + self.files.len() * ENTRY_SIZE;
+    let mut offsets = Vec::with_capacity(self.files.len());
+    let mut end = names_start + names_len;
+    for file in self.files.iter() {
+      let offset = align_up(end, align);
+      offsets.push(offset);
+      end = offset + file.data.len();
+    }
+    let size = align_up(end, align);
+
+    let mut out = Vec::with_capacity(size);
+    out.extend_from_slice(&MAGIC);
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    out.extend_from_slice(&(self.files.len() as u32).to_le_bytes());
+    out.extend_from_slice(&self.align.to_le_bytes());
+    out.extend_from_slice(&(names_len as u32).to_le_bytes());
+    out.extend_from_slice(&(size as u64).to_le_bytes());
+    let mut name_offset = 0;
+    for (file, offset) in self.files.iter().zip(offsets.iter()) {
+      out.extend_from_slice(&(name_offset as u32).to_le_bytes());
+      out.extend_from_slice(&(file.name.len() as u32).to_le_bytes());
+      out.extend_from_slice(&file.mode.to_le_bytes());
+      out.extend_from_slice(&0u32.to_le_bytes());
+      out.extend_from_slice(&(*offset as u64).to_le_bytes());
+      out.extend_from_slice(&(file.data.len() as u64).to_le_bytes());
+      name_offset += file.name.len();
+    }
+    for file in self.files.iter() {
+      out.extend_from_slice(file.name.as_bytes());
+    }
+    for (file, offset) in self.files.iter().zip(offsets.iter()) {
+      out.resize(*offset, 0);
+      out.extend_from_slice(&file.data);
+    }
+    out.resize(size, 0);
+    out
+  }
+}
@@ -0,0 +1,276 @@
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
+
+//! Reader for the BoringOS initramfs archive.
+//!
+//! All integers are little endian. An archive is a header, the entry table,
+//! the name table and the file data:
+//!
+//! ```text
+//! header, 32 bytes
+//!   0  magic      "BOSINITR"
+//!   8  version    u32, 1
+//!   12 count      u32, number of entries
+//!   16 align      u32, alignment of file data, a power of two of at least 8
+//!   20 names_len  u32, length of the name table
+//!   24 size       u64, length of the whole archive
+//! entry, 32 bytes, count times
+//!   0  name_off   u32, offset into the name table
+//!   4  name_len   u32
+//!   8  mode       u32, unix file mode
+//!   12 reserved   u32, 0
+//!   16 offset     u64, offset of the data from the start of the archive
+//!   24 size       u64
+//! name table, UTF-8 paths relative to the archive root, '/' separated
+//! file data, each file starts at a multiple of align, padded with zeroes
+//! ```
+//!
+//! File data is aligned relative to the start of the archive, an archive
+//! loaded at an address aligned to `align` has all files aligned in memory.
+//! The reader validates the whole archive once and never allocates.
+
+use core::convert::TryInto;
+
+#[cfg(any(test, feature = "std"))]
+mod builder;
+#[cfg(any(test, feature = "std"))]
+pub use builder::Builder;
+
+pub const MAGIC: [u8; 8] = *b"BOSINITR";
+pub const VERSION: u32 = 1;
+pub const HEADER_SIZE: usize = 32;
+pub const ENTRY_SIZE: usize = 32;
+/// Smallest data alignment an archive may use
+pub const MIN_ALIGN: u32 = 8;
+
+/// Regular file type in the mode
+pub const MODE_FILE: u32 = 0o100000;
+const MODE_TYPE_MASK: u32 = 0o170000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+  /// The data is shorter than the header or the size it declares
+  TooShort,
+  BadMagic,
+  UnsupportedVersion(u32),
+  /// The data alignment is not a power of two of at least MIN_ALIGN
+  BadAlignment(u32),
+  /// The entry or name table does not fit into the archive
+  TableOutOfBounds,
+  /// The name of the entry is outside the name table or not UTF-8
+  BadName(usize),
+  /// The data of the entry is outside the archive or not aligned
+  BadData(usize),
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+  u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+  u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+/// Returns data[offset..offset + len] if it is in bounds
+fn range(data: &[u8], offset: u64, len: u64) -> Option<&[u8]> {
+  let end = offset.checked_add(len)?;
+  if end > data.len() as u64 {
+    return None;
+  }
+  Some(&data[offset as usize..end as usize])
+}
+
+/// A file in the archive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry<'a> {
+  pub name: &'a str,
+  pub mode: u32,
+  pub data: &'a [u8],
+}
+
+impl<'a> Entry<'a> {
+  pub fn is_file(&self) -> bool {
+    self.mode & MODE_TYPE_MASK == MODE_FILE
+  }
+  pub fn is_executable(&self) -> bool {
+    self.mode & 0o111 != 0
+  }
+}
+
+/// A validated archive
+#[derive(Debug, Clone, Copy)]
+pub struct Archive<'a> {
+  data: &'a [u8],
+  count: usize,
+  align: u32,
+  names: &'a [u8],
+}
+
+impl<'a> Archive<'a> {
+  /// Validates the archive, trailing data after the declared size is ignored
+  pub fn new(data: &'a [u8]) -> Result<Archive<'a>, Error> {
+    if data.len() < HEADER_SIZE {
+      return Err(Error::TooShort);
+    }
+    if data[0..8] != MAGIC {
+      return Err(Error::BadMagic);
+    }
+    let version = read_u32(data, 8);
+    if version != VERSION {
+      return Err(Error::UnsupportedVersion(version));
+    }
+    let count = read_u32(data, 12) as u64;
+    let align = read_u32(data, 16);
+    if align < MIN_ALIGN || !align.is_power_of_two() {
+      return Err(Error::BadAlignment(align));
+    }
+    let names_len = read_u32(data, 20) as u64;
+    let size = read_u64(data, 24);
+    let data = range(data, 0, size).ok_or(Error::TooShort)?;
+    let table_len = count * ENTRY_SIZE as u64;
+    range(data, HEADER_SIZE as u64, table_len).ok_or(Error::TableOutOfBounds)?;
+    let names = range(data, HEADER_SIZE as u64 + table_len, names_len).ok_or(Error::TableOutOfBounds)?;
+    let archive = Archive { data, count: count as usize, align, names };
+    for index in 0..archive.count {
+      archive.parse_entry(index)?;
+    }
+    Ok(archive)
+  }
+  fn parse_entry(&self, index: usize) -> Result<Entry<'a>, Error> {
+    let raw = &self.data[HEADER_SIZE + index * ENTRY_SIZE..HEADER_SIZE + (index + 1) * ENTRY_SIZE];
+    let name = range(self.names, read_u32(raw, 0) as u64, read_u32(raw, 4) as u64)
+      .and_then(|name| core::str::from_utf8(name).ok())
+      .ok_or(Error::BadName(index))?;
+    let offset = read_u64(raw, 16);
+    if offset % self.align as u64 != 0 {
+      return Err(Error::BadData(index));
+    }
+    let data = range(self.data, offset, read_u64(raw, 24)).ok_or(Error::BadData(index))?;
+    Ok(Entry { name, mode: read_u32(raw, 8), data })
+  }
+  pub fn len(&self) -> usize {
+    self.count
+  }
+  pub fn is_empty(&self) -> bool {
+    self.count == 0
+  }
+  /// Alignment of the file data relative to the start of the archive
+  pub fn align(&self) -> u32 {
+    self.align
+  }
+  /// Returns the nth entry
+  pub fn get(&self, index: usize) -> Option<Entry<'a>> {
+    if index < self.count {
+      // validated in new
+      self.parse_entry(index).ok()
+    } else {
+      None
+    }
+  }
+  pub fn entries(&self) -> Entries<'a> {
+    Entries { archive: *self, index: 0 }
+  }
+  /// Finds an entry by its path, a leading '/' is ignored
+  pub fn find(&self, name: &str) -> Option<Entry<'a>> {
+    let name = name.trim_start_matches('/');
+    self.entries().find(|entry| entry.name == name)
+  }
+}
+
+pub struct Entries<'a> {
+  archive: Archive<'a>,
+  index: usize,
+}
+
+impl<'a> Iterator for Entries<'a> {
+  type Item = Entry<'a>;
+
+  fn next(&mut self) -> Option<Entry<'a>> {
+    let entry = self.archive.get(self.index)?;
+    self.index += 1;
+    Some(entry)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn sample() -> Vec<u8> {
+    let mut builder = Builder::new(4096);
+    builder.add("sbin/scheduler", MODE_FILE | 0o755, b"\x7fELF scheduler");
+    builder.add("etc/config", MODE_FILE | 0o644, b"key=value\n");
+    builder.add("empty", MODE_FILE | 0o644, b"");
+    builder.build()
+  }
+
+  #[test]
+  fn reads_built_archive() {
+    let data = sample();
+    let archive = Archive::new(&data).unwrap();
+    assert_eq!(archive.len(), 3);
+    assert_eq!(archive.align(), 4096);
+    let scheduler = archive.find("/sbin/scheduler").unwrap();
+    assert_eq!(scheduler.data, b"\x7fELF scheduler");
+    assert!(scheduler.is_file() && scheduler.is_executable());
+    let config = archive.find("etc/config").unwrap();
+    assert_eq!(config.data, b"key=value\n");
+    assert!(!config.is_executable());
+    assert_eq!(archive.find("empty").unwrap().data.len(), 0);
+    assert!(archive.find("missing").is_none());
+    let names: Vec<&str> = archive.entries().map(|entry| entry.name).collect();
+    assert_eq!(names, ["sbin/scheduler", "etc/config", "empty"]);
+  }
+
+  #[test]
+  fn data_is_aligned() {
+    let data = sample();
+    let archive = Archive::new(&data).unwrap();
+    for entry in archive.entries() {
+      let offset = entry.data.as_ptr() as usize - data.as_ptr() as usize;
+      assert_eq!(offset % 4096, 0, "{} is not aligned", entry.name);
+    }
+  }
+
+  #[test]
+  fn empty_archive() {
+    let data = Builder::new(8).build();
+    let archive = Archive::new(&data).unwrap();
+    assert!(archive.is_empty());
+    assert_eq!(archive.entries().count(), 0);
+  }
+
+  #[test]
+  fn rejects_corrupt_archives() {
+    let data = sample();
+    assert_eq!(Archive::new(&data[..16]).err(), Some(Error::TooShort));
+    assert_eq!(Archive::new(&data[..data.len() - 1]).err(), Some(Error::TooShort));
+    let mut bad = data.clone();
+    bad[0] = b'X';
+    assert_eq!(Archive::new(&bad).err(), Some(Error::BadMagic));
+    let mut bad = data.clone();
+    bad[8] = 2;
+    assert_eq!(Archive::new(&bad).err(), Some(Error::UnsupportedVersion(2)));
+    let mut bad = data.clone();
+    bad[16..20].copy_from_slice(&12u32.to_le_bytes());
+    assert_eq!(Archive::new(&bad).err(), Some(Error::BadAlignment(12)));
+    let mut bad = data.clone();
+    bad[12..16].copy_from_slice(&1000u32.to_le_bytes());
+    assert_eq!(Archive::new(&bad).err(), Some(Error::TableOutOfBounds));
+    // second entry's data offset, moved off the alignment
+    let mut bad = data.clone();
+    let offset = HEADER_SIZE + ENTRY_SIZE + 16;
+    bad[offset] += 1;
+    assert_eq!(Archive::new(&bad).err(), Some(Error::BadData(1)));
+    // first entry's name length, past the name table
+    let mut bad = data.clone();
+    bad[HEADER_SIZE + 4..HEADER_SIZE + 8].copy_from_slice(&1000u32.to_le_bytes());
+    assert_eq!(Archive::new(&bad).err(), Some(Error::BadName(0)));
+  }
+
+  #[test]
+  fn ignores_trailing_data() {
+    let mut data = sample();
+    data.extend_from_slice(&[0xff; 100]);
+    assert_eq!(Archive::new(&data).unwrap().len(), 3);
+  }
+}
@@ -0,0 +1,52 @@
+#!/bin/bash
+# Embeds the function and line table of the kernel into its .ksyms section,
+# used to symbolize backtraces on panic. Run after every link.
+set -e
+
+KERNEL=${1:-target/x86_64-boringoscore/debug/boringos}
+TABLE=$(mktemp)
+trap 'rm -f "$TABLE" "$TABLE.funcs"' EXIT
+
+SIZE=$(objdump -h "$KERNEL" | awk '$2 == ".ksyms" { print $3 }')
+if [ -z "$SIZE" ]; then
+  echo "$KERNEL has no .ksyms section" >&2
+  exit 1
+fi
+SIZE=$((16#$SIZE))
+
+# F <address> <size> <function>, with the hash suffix of rust symbols removed
+nm --demangle --print-size --defined-only "$KERNEL" \
+  | awk 'NF >= 4 && $3 ~ /^[tTwW]$/ {
+      name = $4
+      for (i = 5; i <= NF; i++) name = name " " $i
+      sub(/::h[0-9a-f]+$/, "", name)
+      print "F", $1, $2, name
+    }' \
+  | sort -k2,2 > "$TABLE.funcs"
+
+{
+  echo "KSYMS 1"
+  cat "$TABLE.funcs"
+  # L <address> <line> <file>, only where the line changes
+  objdump --dwarf=decodedline "$KERNEL" \
+    | awk '
+      /:$/ && NF == 1 { path = $1; sub(/:$/, "", path) }
+      /^CU: / { path = $2; sub(/:$/, "", path) }
+      $2 ~ /^[0-9]+$/ && $3 ~ /^0x/ {
+        file = $1
+        if (length(path) > length(file) && substr(path, length(path) - length(file)) == "/" file) file = path
+        addr = substr($3, 3)
+        while (length(addr) < 16) addr = "0" addr
+        print "L", addr, $2, file
+      }' \
+    | sort -k2,2 \
+    | awk '{ key = $3 " " $4 } key != last { print; last = key }'
+} > "$TABLE"
+
+if [ $(($(stat -c %s "$TABLE") + 1)) -gt "$SIZE" ]; then
+  echo "symbol table exceeds .ksyms ($SIZE bytes), leaving out line numbers" >&2
+  { echo "KSYMS 1"; cat "$TABLE.funcs"; } > "$TABLE"
+fi
+
+truncate -s "$SIZE" "$TABLE"
+objcopy --update-section .ksyms="$TABLE" "$KERNEL"
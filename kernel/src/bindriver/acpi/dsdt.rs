@@ -0,0 +1,56 @@
+use super::AcpiTable;
+
+// Only the \_S5_ object is read from the DSDT, it is a package like
+//   Name (\_S5, Package () { SLP_TYPa, SLP_TYPb, ... })
+// which firmware emits as NameOp "_S5_" PackageOp PkgLength NumElements
+// followed by the integers. A full AML interpreter is not needed for this.
+
+const NAME_OP: u8 = 0x08;
+const ROOT_PREFIX: u8 = b'\\';
+const PACKAGE_OP: u8 = 0x12;
+
+/// Decodes an AML integer constant, returns the value and its encoded length
+fn integer(aml: &[u8]) -> Option<(u64, usize)> {
+  match *aml.first()? {
+    // ZeroOp, OneOp
+    0x00 => Some((0, 1)),
+    0x01 => Some((1, 1)),
+    // BytePrefix, WordPrefix, DWordPrefix
+    0x0A => Some((*aml.get(1)? as u64, 2)),
+    0x0B => Some((u16::from_le_bytes([*aml.get(1)?, *aml.get(2)?]) as u64, 3)),
+    0x0C => {
+      let bytes = aml.get(1..5)?;
+      Some((u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as u64, 5))
+    },
+    _ => None,
+  }
+}
+
+/// Decodes the sleep types of a package following "_S5_"
+fn package_sleep_types(package: &[u8]) -> Option<(u8, u8)> {
+  if *package.first()? != PACKAGE_OP {
+    return None;
+  }
+  // the top two bits of the PkgLength lead byte count the bytes following it
+  let pkg_length_bytes = (*package.get(1)? >> 6) as usize;
+  // skip PackageOp, PkgLength and NumElements
+  let elements = package.get(2 + pkg_length_bytes + 1..)?;
+  let (slp_typ_a, len) = integer(elements)?;
+  let (slp_typ_b, _) = integer(elements.get(len..)?)?;
+  Some((slp_typ_a as u8 & 0x7, slp_typ_b as u8 & 0x7))
+}
+
+/// Returns the sleep types for PM1a and PM1b that enter the S5 (soft off) state,
+/// "_S5_" also appears in methods, conditions and scope paths which are skipped
+pub fn s5_sleep_types(dsdt: &AcpiTable) -> Option<(u8, u8)> {
+  let aml = dsdt.body();
+  aml.windows(4).enumerate()
+    .filter(|(_, name)| *name == b"_S5_")
+    .filter(|&(p, _)| match p {
+      p if p >= 1 && aml[p - 1] == NAME_OP => true,
+      p if p >= 2 && aml[p - 1] == ROOT_PREFIX && aml[p - 2] == NAME_OP => true,
+      _ => false,
+    })
+    .filter_map(|(p, _)| package_sleep_types(aml.get(p + 4..)?))
+    .next()
+}
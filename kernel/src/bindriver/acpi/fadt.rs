@@ -0,0 +1,82 @@
+use super::AcpiTable;
+
+/// Location of a register as described by ACPI
+#[derive(Debug, Clone, Copy)]
+pub struct GenericAddress {
+  /// 0 = system memory, 1 = system I/O
+  pub address_space: u8,
+  pub bit_width: u8,
+  pub bit_offset: u8,
+  pub access_size: u8,
+  pub address: u64,
+}
+
+impl GenericAddress {
+  pub const SYSTEM_MEMORY: u8 = 0;
+  pub const SYSTEM_IO: u8 = 1;
+
+  fn parse(table: &AcpiTable, offset: usize) -> Option<GenericAddress> {
+    let address = GenericAddress {
+      address_space: table.read_u8(offset)?,
+      bit_width: table.read_u8(offset + 1)?,
+      bit_offset: table.read_u8(offset + 2)?,
+      access_size: table.read_u8(offset + 3)?,
+      address: table.read_u64(offset + 4)?,
+    };
+    if address.address == 0 { None } else { Some(address) }
+  }
+}
+
+const FLAG_RESET_REG_SUPPORTED: u32 = 1 << 10;
+
+/// Fixed ACPI Description Table, only the power management parts
+#[derive(Debug, Clone)]
+pub struct Fadt {
+  pub sci_interrupt: u16,
+  pub smi_command_port: u32,
+  pub acpi_enable: u8,
+  pub acpi_disable: u8,
+  pub pm1a_event_block: u32,
+  pub pm1b_event_block: u32,
+  pub pm1a_control_block: u32,
+  pub pm1b_control_block: u32,
+  pub pm_timer_block: u32,
+  pub century: u8,
+  pub flags: u32,
+  pub reset_register: Option<GenericAddress>,
+  pub reset_value: u8,
+  /// physical address of the DSDT
+  pub dsdt: u64,
+}
+
+impl Fadt {
+  pub fn parse(table: &AcpiTable) -> Option<Fadt> {
+    let flags = table.read_u32(112).unwrap_or(0);
+    let reset_register = if flags & FLAG_RESET_REG_SUPPORTED != 0 {
+      GenericAddress::parse(table, 116)
+    } else {
+      None
+    };
+    // the 64bit DSDT pointer takes precedence if the table is long enough
+    let dsdt = match table.read_u64(140) {
+      Some(x_dsdt) if x_dsdt != 0 => x_dsdt,
+      _ => table.read_u32(40)? as u64,
+    };
+    Some(Fadt {
+      sci_interrupt: table.read_u16(46)?,
+      smi_command_port: table.read_u32(48)?,
+      acpi_enable: table.read_u8(52)?,
+      acpi_disable: table.read_u8(53)?,
+      pm1a_event_block: table.read_u32(56)?,
+      pm1b_event_block: table.read_u32(60)?,
+      pm1a_control_block: table.read_u32(64)?,
+      pm1b_control_block: table.read_u32(68)?,
+      pm_timer_block: table.read_u32(76)?,
+      century: table.read_u8(108).unwrap_or(0),
+      flags,
+      reset_register,
+      reset_value: table.read_u8(128).unwrap_or(0),
+      dsdt,
+    })
+  }
+}
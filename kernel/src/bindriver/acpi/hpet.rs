@@ -0,0 +1,23 @@
+use super::AcpiTable;
+
+/// High Precision Event Timer description
+#[derive(Debug, Clone, Copy)]
+pub struct Hpet {
+  pub event_timer_block_id: u32,
+  /// physical address of the HPET registers
+  pub base_address: u64,
+  pub hpet_number: u8,
+  /// minimum clock ticks for periodic mode
+  pub minimum_tick: u16,
+}
+
+impl Hpet {
+  pub fn parse(table: &AcpiTable) -> Option<Hpet> {
+    Some(Hpet {
+      event_timer_block_id: table.read_u32(36)?,
+      base_address: table.read_u64(44)?,
+      hpet_number: table.read_u8(52)?,
+      minimum_tick: table.read_u16(53)?,
+    })
+  }
+}
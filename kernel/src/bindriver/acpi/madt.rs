@@ -0,0 +1,107 @@
+use alloc::vec::Vec;
+use super::AcpiTable;
+
+const ENTRY_LOCAL_APIC: u8 = 0;
+const ENTRY_IO_APIC: u8 = 1;
+const ENTRY_INTERRUPT_OVERRIDE: u8 = 2;
+const ENTRY_LOCAL_APIC_ADDRESS: u8 = 5;
+
+const PROCESSOR_ENABLED: u32 = 1 << 0;
+const PROCESSOR_ONLINE_CAPABLE: u32 = 1 << 1;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Processor {
+  pub acpi_id: u8,
+  pub apic_id: u8,
+  /// false if the processor can not be started
+  pub usable: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct IoApic {
+  pub id: u8,
+  pub address: u64,
+  /// global system interrupt of the first input
+  pub gsi_base: u32,
+}
+
+/// Maps an ISA interrupt to a global system interrupt
+#[derive(Debug, Clone, Copy)]
+pub struct InterruptOverride {
+  pub source: u8,
+  pub gsi: u32,
+  pub level_triggered: bool,
+  pub active_low: bool,
+}
+
+/// Multiple APIC Description Table
+#[derive(Debug, Clone)]
+pub struct Madt {
+  pub local_apic_address: u64,
+  /// the system also has 8259 PICs
+  pub has_pic: bool,
+  pub processors: Vec<Processor>,
+  pub io_apics: Vec<IoApic>,
+  pub overrides: Vec<InterruptOverride>,
+}
+
+impl Madt {
+  pub fn parse(table: &AcpiTable) -> Option<Madt> {
+    let mut madt = Madt {
+      local_apic_address: table.read_u32(36)? as u64,
+      has_pic: table.read_u32(40)? & 1 != 0,
+      processors: Vec::new(),
+      io_apics: Vec::new(),
+      overrides: Vec::new(),
+    };
+    let mut offset = 44;
+    while offset + 2 <= table.bytes().len() {
+      let kind = table.read_u8(offset)?;
+      let len = table.read_u8(offset + 1)? as usize;
+      if len < 2 {
+        warn!("malformed MADT entry at offset {}", offset);
+        break;
+      }
+      match kind {
+        ENTRY_LOCAL_APIC => {
+          let flags = table.read_u32(offset + 4)?;
+          madt.processors.push(Processor {
+            acpi_id: table.read_u8(offset + 2)?,
+            apic_id: table.read_u8(offset + 3)?,
+            usable: flags & (PROCESSOR_ENABLED | PROCESSOR_ONLINE_CAPABLE) != 0,
+          });
+        },
+        ENTRY_IO_APIC => madt.io_apics.push(IoApic {
+          id: table.read_u8(offset + 2)?,
+          address: table.read_u32(offset + 4)? as u64,
+          gsi_base: table.read_u32(offset + 8)?,
+        }),
+        ENTRY_INTERRUPT_OVERRIDE => {
+          let flags = table.read_u16(offset + 8)?;
+          madt.overrides.push(InterruptOverride {
+            source: table.read_u8(offset + 3)?,
+            gsi: table.read_u32(offset + 4)?,
+            active_low: flags & 0b11 == 0b11,
+            level_triggered: (flags >> 2) & 0b11 == 0b11,
+          });
+        },
+        ENTRY_LOCAL_APIC_ADDRESS => {
+          madt.local_apic_address = table.read_u64(offset + 4)?;
+        },
+        _ => (),
+      }
+      offset += len;
+    }
+    Some(madt)
+  }
+
+  /// Returns the number of processors that can be started
+  pub fn usable_processors(&self) -> usize {
+    self.processors.iter().filter(|p| p.usable).count()
+  }
+
+  /// Returns the override for the ISA interrupt, if any
+  pub fn isa_override(&self, irq: u8) -> Option<&InterruptOverride> {
+    self.overrides.iter().find(|o| o.source == irq)
+  }
+}
@@ -0,0 +1,218 @@
+pub mod dsdt;
+pub mod fadt;
+pub mod hpet;
+pub mod madt;
+
+use alloc::vec::Vec;
+use core::convert::TryInto;
+use spin::Once;
+
+pub use fadt::{Fadt, GenericAddress};
+pub use hpet::Hpet;
+pub use madt::{InterruptOverride, IoApic, Madt, Processor};
+
+const SDT_HEADER_SIZE: usize = 36;
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+// pointer to the extended BIOS data area segment
+const EBDA_POINTER: u64 = 0x40E;
+const BIOS_AREA_START: u64 = 0xE_0000;
+const BIOS_AREA_END: u64 = 0x10_0000;
+
+#[derive(Debug, Clone)]
+pub enum AcpiError {
+  /// No RSDP was found in the BIOS memory areas
+  NoRsdp,
+  /// A table has an invalid checksum
+  InvalidChecksum([u8; 4]),
+  /// A table is shorter than its header or contents require
+  TableTooShort([u8; 4]),
+}
+
+/// A copy of an ACPI table including its header
+#[derive(Debug, Clone)]
+pub struct AcpiTable {
+  data: Vec<u8>,
+}
+
+impl AcpiTable {
+  /// Wraps a table read elsewhere, the checksum is not verified
+  pub fn from_bytes(data: Vec<u8>) -> Option<AcpiTable> {
+    if data.len() < SDT_HEADER_SIZE { None } else { Some(AcpiTable { data }) }
+  }
+  pub fn signature(&self) -> [u8; 4] {
+    let mut sig = [0; 4];
+    sig.copy_from_slice(&self.data[0..4]);
+    sig
+  }
+  pub fn revision(&self) -> u8 {
+    self.data[8]
+  }
+  /// Returns the whole table including the header
+  pub fn bytes(&self) -> &[u8] {
+    &self.data
+  }
+  /// Returns the table contents following the header
+  pub fn body(&self) -> &[u8] {
+    &self.data[SDT_HEADER_SIZE..]
+  }
+  fn read_u8(&self, offset: usize) -> Option<u8> {
+    self.data.get(offset).cloned()
+  }
+  fn read_u16(&self, offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(self.data.get(offset..offset + 2)?.try_into().ok()?))
+  }
+  fn read_u32(&self, offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(self.data.get(offset..offset + 4)?.try_into().ok()?))
+  }
+  fn read_u64(&self, offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(self.data.get(offset..offset + 8)?.try_into().ok()?))
+  }
+}
+
+/// ACPI information collected during boot
+pub struct Acpi {
+  pub revision: u8,
+  tables: Vec<AcpiTable>,
+  pub madt: Option<Madt>,
+  pub fadt: Option<Fadt>,
+  pub hpet: Option<Hpet>,
+}
+
+impl Acpi {
+  /// Returns the nth table with the given signature
+  pub fn table(&self, signature: &[u8], index: usize) -> Option<&AcpiTable> {
+    self.tables.iter()
+      .filter(|t| &t.signature()[..] == signature)
+      .nth(index)
+  }
+  pub fn tables(&self) -> &[AcpiTable] {
+    &self.tables
+  }
+  /// Returns the PM1a and PM1b sleep types of the S5 (soft off) state
+  pub fn s5_sleep_types(&self) -> Option<(u8, u8)> {
+    dsdt::s5_sleep_types(self.table(b"DSDT", 0)?)
+  }
+}
+
+static ACPI: Once<Option<Acpi>> = Once::new();
+
+/// Returns the ACPI information, None if there is no ACPI or init was not called
+pub fn acpi() -> Option<&'static Acpi> {
+  ACPI.r#try().and_then(|acpi| acpi.as_ref())
+}
+
+pub fn madt() -> Option<&'static Madt> {
+  acpi()?.madt.as_ref()
+}
+
+pub fn fadt() -> Option<&'static Fadt> {
+  acpi()?.fadt.as_ref()
+}
+
+pub fn hpet() -> Option<&'static Hpet> {
+  acpi()?.hpet.as_ref()
+}
+
+fn phys_slice<'a>(pa: u64, len: usize) -> &'a [u8] {
+  let vaddr = crate::kinfo().get_pmo() + pa;
+  unsafe { core::slice::from_raw_parts(vaddr.as_ptr::<u8>(), len) }
+}
+
+fn checksum_ok(data: &[u8]) -> bool {
+  data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) == 0
+}
+
+fn find_rsdp_in(start: u64, end: u64) -> Option<u64> {
+  (start..end).step_by(16).find(|addr| {
+    let candidate = phys_slice(*addr, 20);
+    &candidate[0..8] == RSDP_SIGNATURE && checksum_ok(candidate)
+  })
+}
+
+fn find_rsdp() -> Option<u64> {
+  let ebda = (u16::from_le_bytes(phys_slice(EBDA_POINTER, 2).try_into().ok()?) as u64) << 4;
+  if ebda >= 0x8_0000 && ebda < BIOS_AREA_START {
+    if let Some(rsdp) = find_rsdp_in(ebda, ebda + 1024) {
+      return Some(rsdp);
+    }
+  }
+  find_rsdp_in(BIOS_AREA_START, BIOS_AREA_END)
+}
+
+/// Copies the table at the physical address to the kernel heap
+fn copy_table(pa: u64) -> Result<AcpiTable, AcpiError> {
+  let header = phys_slice(pa, SDT_HEADER_SIZE);
+  let mut signature = [0; 4];
+  signature.copy_from_slice(&header[0..4]);
+  let length = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+  if length < SDT_HEADER_SIZE {
+    return Err(AcpiError::TableTooShort(signature));
+  }
+  let data = phys_slice(pa, length);
+  if !checksum_ok(data) {
+    return Err(AcpiError::InvalidChecksum(signature));
+  }
+  Ok(AcpiTable { data: data.to_vec() })
+}
+
+fn discover() -> Result<Acpi, AcpiError> {
+  let rsdp_addr = find_rsdp().ok_or(AcpiError::NoRsdp)?;
+  let rsdp = phys_slice(rsdp_addr, 36);
+  let revision = rsdp[15];
+  let rsdt_addr = u32::from_le_bytes(rsdp[16..20].try_into().unwrap()) as u64;
+  // ACPI 2.0 and later provide the XSDT with 64bit pointers
+  let (root, entry_size) = if revision >= 2 && checksum_ok(phys_slice(rsdp_addr, 36)) {
+    (copy_table(u64::from_le_bytes(rsdp[24..32].try_into().unwrap()))?, 8)
+  } else {
+    (copy_table(rsdt_addr)?, 4)
+  };
+  debug!("ACPI revision {} root table {:?} at {:#x}",
+    revision, core::str::from_utf8(&root.signature()).unwrap_or("????"), rsdp_addr);
+  let mut tables = Vec::new();
+  for entry in root.body().chunks_exact(entry_size) {
+    let pa = if entry_size == 8 {
+      u64::from_le_bytes(entry.try_into().unwrap())
+    } else {
+      u32::from_le_bytes(entry.try_into().unwrap()) as u64
+    };
+    match copy_table(pa) {
+      Ok(table) => tables.push(table),
+      Err(e) => warn!("skipping ACPI table at {:#x}: {:?}", pa, e),
+    }
+  }
+  let fadt = tables.iter().find(|t| &t.signature() == b"FACP").and_then(Fadt::parse);
+  // the DSDT is only referenced from the FADT
+  if let Some(dsdt) = fadt.as_ref().map(|f| f.dsdt).filter(|dsdt| *dsdt != 0) {
+    match copy_table(dsdt) {
+      Ok(table) => tables.push(table),
+      Err(e) => warn!("skipping DSDT at {:#x}: {:?}", dsdt, e),
+    }
+  }
+  tables.push(root);
+  for table in tables.iter() {
+    debug!("ACPI table {} ({} bytes)",
+      core::str::from_utf8(&table.signature()).unwrap_or("????"), table.bytes().len());
+  }
+  let madt = tables.iter().find(|t| &t.signature() == b"APIC").and_then(Madt::parse);
+  let hpet = tables.iter().find(|t| &t.signature() == b"HPET").and_then(Hpet::parse);
+  Ok(Acpi { revision, tables, madt, fadt, hpet })
+}
+
+/// Locates and copies the ACPI tables, afterwards ACPI reclaimable memory
+/// is no longer needed by the kernel
+pub fn init() {
+  ACPI.call_once(|| match discover() {
+    Ok(acpi) => {
+      info!("ACPI: {} tables, {} CPUs, {} IOAPICs",
+        acpi.tables.len(),
+        acpi.madt.as_ref().map(|m| m.processors.len()).unwrap_or(0),
+        acpi.madt.as_ref().map(|m| m.io_apics.len()).unwrap_or(0),
+      );
+      Some(acpi)
+    },
+    Err(e) => {
+      warn!("no ACPI tables available: {:?}", e);
+      None
+    },
+  });
+}
@@ -0,0 +1,164 @@
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use x86_64::registers::model_specific::Msr;
+use crate::{PhysAddr, VirtAddr};
+
+const IA32_APIC_BASE: u32 = 0x1B;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+const APIC_BASE_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+const REG_ID: usize = 0x20;
+const REG_EOI: usize = 0xB0;
+const REG_SPURIOUS: usize = 0xF0;
+const REG_ICR_LOW: usize = 0x300;
+const REG_ICR_HIGH: usize = 0x310;
+const REG_LVT_TIMER: usize = 0x320;
+const REG_TIMER_INITIAL: usize = 0x380;
+const REG_TIMER_CURRENT: usize = 0x390;
+const REG_TIMER_DIVIDE: usize = 0x3E0;
+
+const LVT_MASKED: u32 = 1 << 16;
+const LVT_TIMER_PERIODIC: u32 = 1 << 17;
+// divides the bus clock by 16
+const TIMER_DIVIDE_16: u32 = 0b0011;
+
+const SPURIOUS_ENABLE: u32 = 1 << 8;
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+
+const ICR_DELIVERY_PENDING: u32 = 1 << 12;
+const ICR_LEVEL_ASSERT: u32 = 1 << 14;
+const ICR_INIT: u32 = 0b101 << 8;
+const ICR_STARTUP: u32 = 0b110 << 8;
+const ICR_ALL_EXCLUDING_SELF: u32 = 0b11 << 18;
+
+// timer ticks per millisecond at TIMER_DIVIDE_16, measured against the PIT
+static TIMER_TICKS_PER_MS: AtomicU32 = AtomicU32::new(0);
+
+// virtual address of the local APIC registers, the registers are at the same
+// physical address on every CPU
+static LAPIC_BASE: AtomicU64 = AtomicU64::new(0);
+
+fn reg(offset: usize) -> *mut u32 {
+  let base = LAPIC_BASE.load(Ordering::Relaxed);
+  assert!(base != 0, "local APIC not initialized");
+  (base as usize + offset) as *mut u32
+}
+
+fn read(offset: usize) -> u32 {
+  unsafe { core::ptr::read_volatile(reg(offset)) }
+}
+
+fn write(offset: usize, value: u32) {
+  unsafe { core::ptr::write_volatile(reg(offset), value) }
+}
+
+pub fn base_address() -> PhysAddr {
+  let msr = Msr::new(IA32_APIC_BASE);
+  PhysAddr::new(unsafe { msr.read() } & APIC_BASE_MASK)
+}
+
+pub fn is_initialized() -> bool {
+  LAPIC_BASE.load(Ordering::Relaxed) != 0
+}
+
+/// Maps the local APIC registers and enables the local APIC of the calling CPU
+pub fn init() {
+  let pa = base_address();
+  let vaddr: VirtAddr = crate::vmem::map_mmio(pa, crate::vmem::PAGE_SIZE);
+  LAPIC_BASE.store(vaddr.as_u64(), Ordering::SeqCst);
+  debug!("local APIC at {:?} mapped to {:?}", pa, vaddr);
+  enable();
+}
+
+/// Enables the local APIC of the calling CPU, the registers must be mapped
+pub fn enable() {
+  let mut msr = Msr::new(IA32_APIC_BASE);
+  unsafe { msr.write(msr.read() | APIC_BASE_ENABLE) };
+  write(REG_SPURIOUS, SPURIOUS_ENABLE | SPURIOUS_VECTOR as u32);
+}
+
+/// Returns the APIC ID of the calling CPU
+pub fn id() -> u8 {
+  (read(REG_ID) >> 24) as u8
+}
+
+pub fn end_of_interrupt() {
+  write(REG_EOI, 0);
+}
+
+fn wait_for_delivery() {
+  while read(REG_ICR_LOW) & ICR_DELIVERY_PENDING != 0 {
+    core::sync::atomic::spin_loop_hint();
+  }
+}
+
+fn send_ipi(destination: u8, command: u32) {
+  write(REG_ICR_HIGH, (destination as u32) << 24);
+  write(REG_ICR_LOW, command);
+  wait_for_delivery();
+}
+
+/// Sends an INIT IPI to all other CPUs
+pub fn broadcast_init() {
+  send_ipi(0, ICR_ALL_EXCLUDING_SELF | ICR_INIT | ICR_LEVEL_ASSERT);
+}
+
+/// Sends a STARTUP IPI to all other CPUs, they start executing in real mode
+/// at the physical page with the given number
+pub fn broadcast_startup(page: u8) {
+  send_ipi(0, ICR_ALL_EXCLUDING_SELF | ICR_STARTUP | page as u32);
+}
+
+/// Sends a fixed interrupt to the CPU with the given APIC ID
+pub fn send_interrupt(destination: u8, vector: u8) {
+  send_ipi(destination, vector as u32);
+}
+
+/// Measures the local APIC timer frequency against the PIT
+pub fn calibrate_timer() {
+  const CALIBRATION_MS: u32 = 10;
+  write(REG_TIMER_DIVIDE, TIMER_DIVIDE_16);
+  write(REG_LVT_TIMER, LVT_MASKED);
+  write(REG_TIMER_INITIAL, u32::max_value());
+  crate::bindriver::cpu::pit::wait_ms(CALIBRATION_MS);
+  let elapsed = u32::max_value() - read(REG_TIMER_CURRENT);
+  write(REG_TIMER_INITIAL, 0);
+  let ticks = core::cmp::max(elapsed / CALIBRATION_MS, 1);
+  TIMER_TICKS_PER_MS.store(ticks, Ordering::SeqCst);
+  debug!("local APIC timer runs at {} ticks per ms", ticks);
+}
+
+fn timer_ticks(us: u64) -> u32 {
+  let per_ms = TIMER_TICKS_PER_MS.load(Ordering::SeqCst);
+  assert!(per_ms != 0, "local APIC timer is not calibrated");
+  let ticks = per_ms as u64 * us / 1000;
+  core::cmp::min(core::cmp::max(ticks, 1), u32::max_value() as u64) as u32
+}
+
+/// Fires the timer vector every period on the calling CPU
+pub fn start_timer_periodic(vector: u8, period_us: u64) {
+  write(REG_TIMER_DIVIDE, TIMER_DIVIDE_16);
+  write(REG_LVT_TIMER, LVT_TIMER_PERIODIC | vector as u32);
+  write(REG_TIMER_INITIAL, timer_ticks(period_us));
+}
+
+/// Fires the timer vector once after the delay on the calling CPU
+pub fn start_timer_oneshot(vector: u8, delay_us: u64) {
+  write(REG_TIMER_DIVIDE, TIMER_DIVIDE_16);
+  write(REG_LVT_TIMER, vector as u32);
+  write(REG_TIMER_INITIAL, timer_ticks(delay_us));
+}
+
+pub fn stop_timer() {
+  write(REG_LVT_TIMER, LVT_MASKED);
+  write(REG_TIMER_INITIAL, 0);
+}
+
+/// Address a device writes to for a message signaled interrupt to the CPU
+pub fn msi_address(destination: u8) -> u64 {
+  0xFEE0_0000 | ((destination as u64) << 12)
+}
+
+/// Data a device writes for a message signaled, edge triggered interrupt
+pub fn msi_data(vector: u8) -> u32 {
+  vector as u32
+}
@@ -0,0 +1,192 @@
+use core::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use alloc::boxed::Box;
+
+// The kernel itself is built with soft-float and never touches the FPU or
+// SSE registers, their content belongs to the running task. Tasks built
+// against a target without soft-float need this feature enabled.
+
+const CR0_MONITOR_COPROCESSOR: u64 = 1 << 1;
+const CR0_EMULATION: u64 = 1 << 2;
+const CR0_TASK_SWITCHED: u64 = 1 << 3;
+const CR0_NUMERIC_ERROR: u64 = 1 << 5;
+const CR4_OSFXSR: u64 = 1 << 9;
+const CR4_OSXMMEXCPT: u64 = 1 << 10;
+const CR4_OSXSAVE: u64 = 1 << 18;
+
+const XCR0_X87: u64 = 1 << 0;
+const XCR0_SSE: u64 = 1 << 1;
+const XCR0_AVX: u64 = 1 << 2;
+const XCR0_OPMASK: u64 = 1 << 5;
+const XCR0_ZMM_HI256: u64 = 1 << 6;
+const XCR0_HI16_ZMM: u64 = 1 << 7;
+// AVX-512 state is only usable if all three components are enabled
+const XCR0_AVX512: u64 = XCR0_OPMASK | XCR0_ZMM_HI256 | XCR0_HI16_ZMM;
+
+const DEFAULT_FCW: u16 = 0x037F;
+const DEFAULT_MXCSR: u32 = 0x1F80;
+
+/// Size of the per task save area, large enough for x87, SSE, AVX and AVX-512
+pub const AREA_SIZE: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum SaveMode {
+  /// The FPU is not enabled, there is no state to save
+  None = 0,
+  /// x87 and SSE state through FXSAVE/FXRSTOR
+  Fxsave = 1,
+  /// All enabled components through XSAVE/XRSTOR
+  Xsave = 2,
+}
+
+static MODE: AtomicU8 = AtomicU8::new(SaveMode::None as u8);
+static XCR0: AtomicU64 = AtomicU64::new(0);
+
+pub fn mode() -> SaveMode {
+  match MODE.load(Ordering::SeqCst) {
+    1 => SaveMode::Fxsave,
+    2 => SaveMode::Xsave,
+    _ => SaveMode::None,
+  }
+}
+
+/// Returns the state components enabled in XCR0, 0 without XSAVE
+pub fn enabled_components() -> u64 {
+  XCR0.load(Ordering::SeqCst)
+}
+
+unsafe fn read_cr4() -> u64 {
+  let cr4: u64;
+  asm!("mov $0, cr4" : "=r"(cr4) ::: "intel", "volatile");
+  cr4
+}
+
+unsafe fn write_cr4(cr4: u64) {
+  asm!("mov cr4, $0" :: "r"(cr4) : "memory" : "intel", "volatile");
+}
+
+unsafe fn xsetbv(xcr0: u64) {
+  asm!("xsetbv" :: "{ecx}"(0u32), "{eax}"(xcr0 as u32), "{edx}"((xcr0 >> 32) as u32) :: "volatile");
+}
+
+/// Enables x87, SSE and, if available, AVX and AVX-512 on the calling CPU
+/// The bootstrap processor selects the save mode, application processors
+/// must be initialized afterwards and use the same mode.
+pub fn init() {
+  use x86_64::registers::control::Cr0;
+  let info = match super::cpuid().get_feature_info() {
+    Some(info) => info,
+    None => {
+      warn!("no CPUID feature information, FPU stays disabled");
+      return;
+    }
+  };
+  if !info.has_fpu() || !info.has_fxsave_fxstor() || !info.has_sse() {
+    warn!("CPU lacks FXSAVE or SSE, FPU stays disabled");
+    return;
+  }
+  unsafe {
+    let cr0 = Cr0::read_raw() & !(CR0_EMULATION | CR0_TASK_SWITCHED);
+    Cr0::write_raw(cr0 | CR0_MONITOR_COPROCESSOR | CR0_NUMERIC_ERROR);
+    let mut cr4 = read_cr4() | CR4_OSFXSR | CR4_OSXMMEXCPT;
+    if info.has_xsave() {
+      cr4 |= CR4_OSXSAVE;
+    }
+    write_cr4(cr4);
+  }
+  let mode = if info.has_xsave() {
+    let supported = {
+      let res = raw_cpuid::cpuid!(0xD, 0);
+      (res.edx as u64) << 32 | res.eax as u64
+    };
+    let mut xcr0 = XCR0_X87 | XCR0_SSE;
+    if info.has_avx() && supported & XCR0_AVX != 0 {
+      xcr0 |= XCR0_AVX;
+      if supported & XCR0_AVX512 == XCR0_AVX512 {
+        xcr0 |= XCR0_AVX512;
+      }
+    }
+    unsafe { xsetbv(xcr0) };
+    // EBX reports the size required for the components enabled in XCR0
+    let size = raw_cpuid::cpuid!(0xD, 0).ebx as usize;
+    assert!(size <= AREA_SIZE, "XSAVE area of {} bytes exceeds save area", size);
+    XCR0.store(xcr0, Ordering::SeqCst);
+    SaveMode::Xsave
+  } else {
+    SaveMode::Fxsave
+  };
+  unsafe { asm!("fninit" :::: "volatile") };
+  let prev = MODE.swap(mode as u8, Ordering::SeqCst);
+  assert!(prev == SaveMode::None as u8 || prev == mode as u8, "CPUs disagree on FPU save mode");
+  debug!("FPU enabled, saving with {:?}, XCR0 = {:#x}", mode, enabled_components());
+}
+
+#[repr(C, align(64))]
+struct Area([u8; AREA_SIZE]);
+
+/// Extended register state of a task
+pub struct FpuState {
+  area: Box<Area>,
+}
+
+impl FpuState {
+  /// Creates the state of a freshly initialized FPU
+  pub fn new() -> FpuState {
+    let mut area = Box::new(Area([0; AREA_SIZE]));
+    // the legacy region holds the control words, a zero MXCSR would
+    // unmask all SIMD exceptions
+    area.0[0..2].copy_from_slice(&DEFAULT_FCW.to_le_bytes());
+    area.0[24..28].copy_from_slice(&DEFAULT_MXCSR.to_le_bytes());
+    FpuState { area }
+  }
+
+  fn ptr(&mut self) -> *mut u8 {
+    self.area.0.as_mut_ptr()
+  }
+
+  /// Stores the registers of the calling CPU into the state
+  pub fn save(&mut self) {
+    let ptr = self.ptr();
+    match mode() {
+      SaveMode::None => (),
+      SaveMode::Fxsave => unsafe {
+        asm!("fxsave64 [$0]" :: "r"(ptr) : "memory" : "intel", "volatile");
+      },
+      SaveMode::Xsave => unsafe {
+        let mask = enabled_components();
+        asm!("xsave64 [$0]" :: "r"(ptr), "{eax}"(mask as u32), "{edx}"((mask >> 32) as u32)
+          : "memory" : "intel", "volatile");
+      },
+    }
+  }
+
+  /// Loads the registers of the calling CPU from the state
+  pub fn restore(&mut self) {
+    let ptr = self.ptr();
+    match mode() {
+      SaveMode::None => (),
+      SaveMode::Fxsave => unsafe {
+        asm!("fxrstor64 [$0]" :: "r"(ptr) : "memory" : "intel", "volatile");
+      },
+      SaveMode::Xsave => unsafe {
+        let mask = enabled_components();
+        asm!("xrstor64 [$0]" :: "r"(ptr), "{eax}"(mask as u32), "{edx}"((mask >> 32) as u32)
+          : "memory" : "intel", "volatile");
+      },
+    }
+  }
+}
+
+impl Clone for FpuState {
+  fn clone(&self) -> FpuState {
+    let mut area = Box::new(Area([0; AREA_SIZE]));
+    area.0.copy_from_slice(&self.area.0);
+    FpuState { area }
+  }
+}
+
+impl core::fmt::Debug for FpuState {
+  fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
+    write!(fmt, "FpuState {{ mode: {:?} }}", mode())
+  }
+}
@@ -7,9 +7,16 @@ pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
 pub const INTR_IST_INDEX: u16 = 2;
 
 
+const IST_STACK_SIZE: usize = 4096 * 16;
+// with hardening, every stack has an additional guard page below it
+#[cfg(feature = "hardening")]
+const STACK_GUARD_SIZE: usize = 4096;
+#[cfg(not(feature = "hardening"))]
+const STACK_GUARD_SIZE: usize = 0;
+
 macro_rules! make_stack {
   ($size:expr) => {{
-      const STACK_SIZE: usize = ($size);
+      const STACK_SIZE: usize = ($size) + STACK_GUARD_SIZE;
       #[repr(align(4096))]
       struct StackContainer([u8; STACK_SIZE]);
       static mut STACK: StackContainer = StackContainer([0; STACK_SIZE]);
@@ -21,13 +28,13 @@ lazy_static! {
   static ref TSS: TaskStateSegment = {
     let mut tss = TaskStateSegment::new();
     tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-      make_stack!(4096 * 16)
+      make_stack!(IST_STACK_SIZE)
     };
     /*tss.interrupt_stack_table[SCHEDULER_IST_INDEX as usize] = {
       make_stack!(8192)
     };*/
     tss.interrupt_stack_table[INTR_IST_INDEX as usize] = {
-      make_stack!(4096 * 16)
+      make_stack!(IST_STACK_SIZE)
     };
     tss
   };
@@ -45,12 +52,69 @@ struct Selectors {
   tss_selector: SegmentSelector,
 }
 
-pub fn init() {
+fn load(gdt: &'static (GlobalDescriptorTable, Selectors)) {
   use ::x86_64::instructions::segmentation::set_cs;
   use ::x86_64::instructions::tables::load_tss;
-  GDT.0.load();
+  gdt.0.load();
   unsafe {
-    set_cs(GDT.1.code_selector);
-    load_tss(GDT.1.tss_selector);
+    set_cs(gdt.1.code_selector);
+    load_tss(gdt.1.tss_selector);
   }
-}
\ No newline at end of file
+}
+
+pub fn init() {
+  load(&GDT);
+}
+
+use crate::common::MAX_CPUS;
+use spin::Mutex;
+
+// GDTs of the application processors, index 0 is unused as the
+// bootstrap processor uses the static GDT
+static AP_GDT: Mutex<[Option<&'static (GlobalDescriptorTable, Selectors)>; MAX_CPUS]> =
+  Mutex::new([None; MAX_CPUS]);
+
+/// Allocates a stack on the kernel heap and returns its top.
+/// The stack is mapped before this returns so it can be used before the
+/// page fault handler is available.
+pub fn alloc_stack(size: usize) -> VirtAddr {
+  use alloc::alloc::{alloc_zeroed, Layout};
+  let layout = Layout::from_size_align(size + STACK_GUARD_SIZE, 4096)
+    .expect("invalid stack layout");
+  let bottom = VirtAddr::from_ptr(unsafe { alloc_zeroed(layout) });
+  assert!(!bottom.is_null(), "could not allocate stack");
+  #[cfg(feature = "hardening")]
+  crate::vmem::install_guard_page(bottom);
+  bottom + size + STACK_GUARD_SIZE
+}
+
+/// Creates the GDT and TSS of an application processor,
+/// this must be called on the bootstrap processor before the CPU is started
+pub fn prepare_ap(cpu: usize) {
+  use alloc::boxed::Box;
+  assert!(cpu > 0 && cpu < MAX_CPUS, "invalid application processor {}", cpu);
+  let mut tss = TaskStateSegment::new();
+  tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = alloc_stack(IST_STACK_SIZE);
+  tss.interrupt_stack_table[INTR_IST_INDEX as usize] = alloc_stack(IST_STACK_SIZE);
+  let tss: &'static TaskStateSegment = Box::leak(Box::new(tss));
+  let mut gdt = GlobalDescriptorTable::new();
+  let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
+  let tss_selector = gdt.add_entry(Descriptor::tss_segment(tss));
+  AP_GDT.lock()[cpu] = Some(Box::leak(Box::new((gdt, Selectors { code_selector, tss_selector }))));
+}
+
+/// Loads the GDT and TSS prepared for the calling application processor
+pub fn init_ap(cpu: usize) {
+  let gdt = AP_GDT.lock()[cpu].expect("application processor has no GDT");
+  load(gdt);
+}
+
+/// Turns the page below each interrupt stack into a guard page,
+/// requires the kernel memory to be initialized
+#[cfg(feature = "hardening")]
+pub fn protect_stacks() {
+  for idx in [DOUBLE_FAULT_IST_INDEX, INTR_IST_INDEX].iter() {
+    let top = TSS.interrupt_stack_table[*idx as usize];
+    crate::vmem::install_guard_page(top - IST_STACK_SIZE - STACK_GUARD_SIZE);
+  }
+}
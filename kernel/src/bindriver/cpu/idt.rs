@@ -6,6 +6,8 @@ use crate::*;
 
 fn crack_locks() {
     unsafe { crate::bindriver::serial::SERIAL1.force_unlock() }
+    #[cfg(feature = "vga")]
+    unsafe { crate::bindriver::vga_buffer::WRITER.force_unlock() }
 }
 
 macro_rules! busy_intr_handler {
@@ -52,6 +54,78 @@ macro_rules! busy_intr_handle_errcode {
     };
 }
 
+// Exceptions raised by task code are delivered to the task. The handler
+// redirects the return from the exception into the trampoline, which runs on
+// the stack of the task with interrupts as they were when the task faulted.
+global_asm!(r#"
+.intel_syntax noprefix
+.global task_exception_trampoline
+task_exception_trampoline:
+    and rsp, -16
+    call deliver_task_exception
+    mov rsp, rdx
+    jmp rax
+.att_syntax prefix
+"#);
+
+extern "C" {
+    fn task_exception_trampoline();
+}
+
+/// Hands the exception to the exception trampoline if it was raised by task
+/// code, returns false if the kernel itself faulted
+fn deliver_to_task(stack_frame: &mut InterruptStackFrame, vector: u8, error_code: u64, address: VirtAddr) -> bool {
+    let rip = stack_frame.instruction_pointer;
+    if !page_range!(CODE).contains(&rip) {
+        return false;
+    }
+    kinfo().local().set_exception(crate::process_manager::TaskException {
+        vector,
+        error_code,
+        address: address.as_u64(),
+        rip: rip.as_u64(),
+        rsp: stack_frame.stack_pointer.as_u64(),
+    });
+    unsafe {
+        let frame = stack_frame.as_mut();
+        frame.instruction_pointer = VirtAddr::new(task_exception_trampoline as usize as u64);
+    }
+    true
+}
+
+macro_rules! task_intr_handler {
+    ($name:ident, $vector:expr) => {
+        extern "x86-interrupt" fn $name(stack_frame: &mut InterruptStackFrame) {
+            let rip = stack_frame.instruction_pointer;
+            if deliver_to_task(stack_frame, $vector, 0, rip) {
+                return;
+            }
+            crack_locks();
+            debug!("Interrupt {}:\n{:?}", stringify!($name), stack_frame);
+            hlt_cpu!();
+        }
+    };
+}
+
+macro_rules! task_intr_handle_errcode {
+    ($name:ident, $vector:expr) => {
+        extern "x86-interrupt" fn $name(stack_frame: &mut InterruptStackFrame, err: u64) {
+            let rip = stack_frame.instruction_pointer;
+            if deliver_to_task(stack_frame, $vector, err, rip) {
+                return;
+            }
+            crack_locks();
+            debug!(
+                "Interrupt {} ({:#018x}):\n{:?}",
+                stringify!($name),
+                err,
+                stack_frame
+            );
+            hlt_cpu!();
+        }
+    };
+}
+
 macro_rules! intr {
     ($idt:ident, $name:ident) => {
         unsafe {
@@ -67,6 +141,7 @@ lazy_static! {
         let mut idt = InterruptDescriptorTable::new();
         intr!(idt, divide_error);
         intr!(idt, non_maskable_interrupt);
+        #[cfg(not(feature = "gdbstub"))]
         intr!(idt, breakpoint);
         intr!(idt, overflow);
         intr!(idt, bound_range_exceeded);
@@ -93,6 +168,11 @@ lazy_static! {
             .set_stack_index(crate::bindriver::cpu::gdt::INTR_IST_INDEX)};
         intr!(idt, machine_check);
         idt[usize::from(TIMER_INTERRUPT_ID)].set_handler_fn(timer_interrupt);
+        idt[usize::from(crate::bindriver::cpu::smp::WAKEUP_VECTOR)].set_handler_fn(wakeup_interrupt);
+        idt[usize::from(crate::bindriver::cpu::apic::SPURIOUS_VECTOR)].set_handler_fn(spurious_interrupt);
+        idt[usize::from(console_vector())].set_handler_fn(console_interrupt);
+        #[cfg(feature = "gdbstub")]
+        crate::bindriver::gdbstub::install(&mut idt);
         idt
     };
 }
@@ -101,17 +181,17 @@ pub fn init() {
     IDT.load();
 }
 
-busy_intr_handler!(divide_error, ret);
+task_intr_handler!(divide_error, 0);
 busy_intr_handler!(non_maskable_interrupt, ret);
-busy_intr_handler!(overflow, ret);
-busy_intr_handler!(bound_range_exceeded, ret);
-busy_intr_handler!(invalid_opcode, ret);
-busy_intr_handler!(device_not_available, ret);
+task_intr_handler!(overflow, 4);
+task_intr_handler!(bound_range_exceeded, 5);
+task_intr_handler!(invalid_opcode, 6);
+task_intr_handler!(device_not_available, 7);
 busy_intr_handler!(machine_check);
 busy_intr_handle_errcode!(invalid_tss, ret);
-busy_intr_handle_errcode!(segment_not_present, ret);
-busy_intr_handle_errcode!(stack_segment_fault, ret);
-busy_intr_handle_errcode!(general_protection_fault, ret);
+task_intr_handle_errcode!(segment_not_present, 11);
+task_intr_handle_errcode!(stack_segment_fault, 12);
+task_intr_handle_errcode!(general_protection_fault, 13);
 
 extern "x86-interrupt" fn breakpoint(stack_frame: &mut InterruptStackFrame) {
     debug!("BREAKPOINT\n{:#?}\n", stack_frame);
@@ -121,6 +201,11 @@ extern "x86-interrupt" fn double_fault(stack_frame: &mut InterruptStackFrame, er
     crack_locks();
     error!("Double Fault, Kernel Halting...");
     error!("Error: {:x}", error_code);
+    // the saved frame pointer of the handler belongs to the faulting code
+    let rbp: u64;
+    unsafe { asm!("mov $0, [rbp]" : "=r"(rbp) ::: "intel", "volatile") };
+    crate::common::backtrace::print_backtrace_from(
+        Some(stack_frame.instruction_pointer.as_u64()), rbp);
     #[cfg(feature = "vga")]
     vga_println!("EXCEPTION: DOUBLE FAULT\n{:#?}\n\nBUSY LOOPING CORE", stack_frame);
     hlt_cpu!();
@@ -149,13 +234,39 @@ extern "x86-interrupt" fn page_fault(
     let pfc = PageFaultContext::new(vaddr, error_code, stack_frame.instruction_pointer);
     match crate::vmem::faulth::handle(pfc) {
         Ok(res) => debug!("Handler returned Ok: {:?}", res),
-        Err(res) => panic!("Handler returned Error: {:?}", res)
+        Err(res) => {
+            if !deliver_to_task(stack_frame, 14, error_code.bits(), vaddr) {
+                panic!("Handler returned Error: {:?}", res)
+            }
+        }
     }
     
 }
 
 extern "x86-interrupt" fn timer_interrupt(_stack_frame: &mut InterruptStackFrame) {
     trace!("timer interrupt");
+    crate::bindriver::cpu::rng::add_interrupt_timing(TIMER_INTERRUPT_ID);
     //TODO: dispatch all registered event handlers
-    crate::bindriver::cpu::pic::end_of_interrupt(TIMER_INTERRUPT_ID);
+    crate::bindriver::cpu::interrupts::end_of_interrupt(TIMER_INTERRUPT_ID);
+}
+
+extern "x86-interrupt" fn wakeup_interrupt(_stack_frame: &mut InterruptStackFrame) {
+    trace!("wakeup interrupt");
+    crate::bindriver::cpu::rng::add_interrupt_timing(crate::bindriver::cpu::smp::WAKEUP_VECTOR);
+    crate::bindriver::cpu::apic::end_of_interrupt();
+}
+
+fn console_vector() -> u8 {
+    crate::bindriver::cpu::interrupts::isa_vector(crate::bindriver::serial::console::COM1_IRQ)
+}
+
+extern "x86-interrupt" fn console_interrupt(_stack_frame: &mut InterruptStackFrame) {
+    crate::bindriver::serial::console::receive();
+    crate::bindriver::cpu::rng::add_interrupt_timing(console_vector());
+    crate::bindriver::cpu::interrupts::end_of_interrupt(console_vector());
+}
+
+// spurious interrupts of the local APIC must not be acknowledged
+extern "x86-interrupt" fn spurious_interrupt(_stack_frame: &mut InterruptStackFrame) {
+    trace!("spurious interrupt");
 }
@@ -0,0 +1,114 @@
+use atomic::Atomic;
+use core::sync::atomic::Ordering;
+use crate::bindriver::cpu::{apic, ioapic, pic};
+use crate::bindriver::cpu::pic::PIC_1_OFFSET;
+use crate::PhysAddr;
+use spin::Mutex;
+
+/// Interrupt controller delivering the device interrupts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Controller {
+  /// Legacy 8259 PIC pair
+  Pic,
+  /// Local APIC with IOAPIC
+  Apic,
+}
+
+/// Period of the timer interrupt when driven by the local APIC timer
+pub const TIMER_PERIOD_US: u64 = 10_000;
+
+static CONTROLLER: Atomic<Controller> = Atomic::new(Controller::Pic);
+
+pub fn controller() -> Controller {
+  CONTROLLER.load(Ordering::SeqCst)
+}
+
+/// Returns the vector ISA interrupt lines are delivered on,
+/// this is the same for the PIC and the IOAPIC
+pub fn isa_vector(irq: u8) -> u8 {
+  PIC_1_OFFSET + irq
+}
+
+// global system interrupt each ISA interrupt is connected to, None if the
+// line is not routed
+static ISA_GSI: Mutex<[Option<u8>; 16]> = Mutex::new([None; 16]);
+
+/// Switches from the 8259 PIC to the local APIC and IOAPIC if the CPU
+/// has a local APIC, requires kernel memory to be initialized.
+/// The IOAPIC is taken from the MADT, only the first IOAPIC is used.
+pub fn init() {
+  if !crate::bindriver::cpu::has_apic() {
+    info!("no local APIC, using 8259 PIC");
+    return;
+  }
+  let madt = crate::bindriver::acpi::madt();
+  pic::disable();
+  apic::init();
+  let (ioapic_addr, gsi_base) = madt
+    .and_then(|madt| madt.io_apics.first())
+    .map(|ioapic| (ioapic.address, ioapic.gsi_base))
+    .unwrap_or((ioapic::DEFAULT_IOAPIC_ADDR, 0));
+  assert!(gsi_base < 256, "IOAPIC interrupt base {} out of range", gsi_base);
+  ioapic::init(PhysAddr::new(ioapic_addr), gsi_base as u8);
+  // ISA interrupts keep the vectors they had on the PIC
+  let mut isa_gsi = ISA_GSI.lock();
+  for irq in 0..16 {
+    let (gsi, trigger) = match madt.and_then(|madt| madt.isa_override(irq)) {
+      Some(o) => (o.gsi, ioapic::Trigger { level: o.level_triggered, active_low: o.active_low }),
+      None => (irq as u32, ioapic::Trigger::ISA),
+    };
+    // an overridden line takes the interrupt, e.g. IRQ0 on GSI 2 under QEMU
+    let taken = madt.map(|madt| madt.overrides.iter().any(|o| o.gsi == gsi && o.source != irq))
+      .unwrap_or(false);
+    if taken {
+      debug!("ISA interrupt {} is not routed, interrupt {} is taken by an override", irq, gsi);
+      continue;
+    }
+    if !ioapic::handles(gsi) {
+      warn!("ISA interrupt {} is connected to interrupt {}, which the IOAPIC does not handle", irq, gsi);
+      continue;
+    }
+    isa_gsi[irq as usize] = Some(gsi as u8);
+    ioapic::route(gsi as u8, isa_vector(irq), apic::id(), trigger);
+  }
+  drop(isa_gsi);
+  CONTROLLER.store(Controller::Apic, Ordering::SeqCst);
+  apic::calibrate_timer();
+  // the APIC timer replaces the PIT on the timer vector
+  apic::start_timer_periodic(crate::bindriver::cpu::idt::TIMER_INTERRUPT_ID, TIMER_PERIOD_US);
+  info!("using local APIC and IOAPIC");
+}
+
+fn isa_gsi(irq: u8) -> Option<u8> {
+  ISA_GSI.lock()[irq as usize]
+}
+
+/// Enables delivery of the ISA interrupt line
+pub fn unmask_isa(irq: u8) {
+  match controller() {
+    Controller::Pic => pic::unmask(irq),
+    Controller::Apic => match isa_gsi(irq) {
+      Some(gsi) => ioapic::unmask(gsi),
+      None => warn!("ISA interrupt {} is not routed, cannot unmask it", irq),
+    },
+  }
+}
+
+pub fn mask_isa(irq: u8) {
+  match controller() {
+    Controller::Pic => pic::mask(irq),
+    Controller::Apic => {
+      if let Some(gsi) = isa_gsi(irq) {
+        ioapic::mask(gsi)
+      }
+    },
+  }
+}
+
+/// Acknowledges the interrupt on the active controller
+pub fn end_of_interrupt(id: u8) {
+  match controller() {
+    Controller::Pic => pic::end_of_interrupt(id),
+    Controller::Apic => apic::end_of_interrupt(),
+  }
+}
@@ -0,0 +1,115 @@
+use core::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use crate::PhysAddr;
+
+/// Address of the first IOAPIC if the firmware does not report one
+pub const DEFAULT_IOAPIC_ADDR: u64 = 0xFEC0_0000;
+
+const REG_SELECT: usize = 0x00;
+const REG_WINDOW: usize = 0x10;
+
+const IOAPIC_VERSION: u32 = 0x01;
+const IOAPIC_REDIRECTION: u32 = 0x10;
+
+const REDIRECTION_MASKED: u64 = 1 << 16;
+const REDIRECTION_LEVEL: u64 = 1 << 15;
+const REDIRECTION_ACTIVE_LOW: u64 = 1 << 13;
+
+// virtual address of the IOAPIC registers
+static IOAPIC_BASE: AtomicU64 = AtomicU64::new(0);
+// global system interrupt of the first redirection entry
+static GSI_BASE: AtomicU8 = AtomicU8::new(0);
+static ENTRIES: AtomicU8 = AtomicU8::new(0);
+
+/// Polarity and trigger mode of an interrupt line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Trigger {
+  pub level: bool,
+  pub active_low: bool,
+}
+
+impl Trigger {
+  /// ISA interrupts are edge triggered and active high
+  pub const ISA: Trigger = Trigger { level: false, active_low: false };
+}
+
+fn read(reg: u32) -> u32 {
+  let base = IOAPIC_BASE.load(Ordering::Relaxed) as usize;
+  assert!(base != 0, "IOAPIC not initialized");
+  unsafe {
+    core::ptr::write_volatile((base + REG_SELECT) as *mut u32, reg);
+    core::ptr::read_volatile((base + REG_WINDOW) as *const u32)
+  }
+}
+
+fn write(reg: u32, value: u32) {
+  let base = IOAPIC_BASE.load(Ordering::Relaxed) as usize;
+  assert!(base != 0, "IOAPIC not initialized");
+  unsafe {
+    core::ptr::write_volatile((base + REG_SELECT) as *mut u32, reg);
+    core::ptr::write_volatile((base + REG_WINDOW) as *mut u32, value);
+  }
+}
+
+fn read_entry(gsi: u8) -> u64 {
+  let reg = IOAPIC_REDIRECTION + 2 * entry_index(gsi) as u32;
+  read(reg) as u64 | (read(reg + 1) as u64) << 32
+}
+
+fn write_entry(gsi: u8, entry: u64) {
+  let reg = IOAPIC_REDIRECTION + 2 * entry_index(gsi) as u32;
+  // mask while the destination is updated
+  write(reg, REDIRECTION_MASKED as u32);
+  write(reg + 1, (entry >> 32) as u32);
+  write(reg, entry as u32);
+}
+
+fn entry_index(gsi: u8) -> u8 {
+  let index = gsi.checked_sub(GSI_BASE.load(Ordering::Relaxed))
+    .expect("interrupt is not handled by this IOAPIC");
+  assert!(index < ENTRIES.load(Ordering::Relaxed), "interrupt {} is not handled by this IOAPIC", gsi);
+  index
+}
+
+/// Returns true if the interrupt has a redirection entry on this IOAPIC
+pub fn handles(gsi: u32) -> bool {
+  let base = GSI_BASE.load(Ordering::Relaxed) as u32;
+  gsi >= base && gsi < base + ENTRIES.load(Ordering::Relaxed) as u32
+}
+
+pub fn is_initialized() -> bool {
+  IOAPIC_BASE.load(Ordering::Relaxed) != 0
+}
+
+/// Maps the IOAPIC registers and masks all redirection entries
+pub fn init(pa: PhysAddr, gsi_base: u8) {
+  let vaddr = crate::vmem::map_mmio(pa, 0x20);
+  IOAPIC_BASE.store(vaddr.as_u64(), Ordering::SeqCst);
+  GSI_BASE.store(gsi_base, Ordering::SeqCst);
+  let entries = ((read(IOAPIC_VERSION) >> 16) & 0xff) as u8 + 1;
+  ENTRIES.store(entries, Ordering::SeqCst);
+  debug!("IOAPIC at {:?} handles interrupts {}..{}", pa, gsi_base, gsi_base as u32 + entries as u32);
+  for gsi in gsi_base..gsi_base + entries {
+    write_entry(gsi, REDIRECTION_MASKED);
+  }
+}
+
+/// Routes the interrupt to the vector on the CPU with the given APIC ID,
+/// the entry stays masked until it is unmasked
+pub fn route(gsi: u8, vector: u8, destination: u8, trigger: Trigger) {
+  let mut entry = vector as u64 | REDIRECTION_MASKED | (destination as u64) << 56;
+  if trigger.level {
+    entry |= REDIRECTION_LEVEL;
+  }
+  if trigger.active_low {
+    entry |= REDIRECTION_ACTIVE_LOW;
+  }
+  write_entry(gsi, entry);
+}
+
+pub fn mask(gsi: u8) {
+  write_entry(gsi, read_entry(gsi) | REDIRECTION_MASKED);
+}
+
+pub fn unmask(gsi: u8) {
+  write_entry(gsi, read_entry(gsi) & !REDIRECTION_MASKED);
+}
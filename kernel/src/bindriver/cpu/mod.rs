@@ -1,8 +1,16 @@
+pub mod apic;
+#[cfg(feature = "fpu")]
+pub mod fpu;
 pub mod gdt;
 pub mod idt;
+pub mod interrupts;
+pub mod ioapic;
 pub mod pic;
+pub mod pit;
 pub mod rng;
 pub mod qemu;
+pub mod smp;
+pub mod tsc;
 use raw_cpuid::{CpuId, FeatureInfo};
 use x86_64::VirtAddr;
 use x86_64::structures::idt::PageFaultErrorCode;
@@ -23,6 +31,25 @@ pub fn has_rdrand() -> bool {
   }
 }
 
+pub fn has_rdseed() -> bool {
+  // structured extended feature flags, EBX bit 18
+  let max_leaf = raw_cpuid::cpuid!(0).eax;
+  max_leaf >= 7 && raw_cpuid::cpuid!(7, 0).ebx & (1 << 18) != 0
+}
+
+/// Returns true if running in a virtual machine
+pub fn has_hypervisor() -> bool {
+  raw_cpuid::cpuid!(1).ecx & (1 << 31) != 0
+}
+
+pub fn has_apic() -> bool {
+  if let Some(info) = feature_info() {
+    info.has_apic()
+  } else {
+    false
+  }
+}
+
 pub fn has_acpi() -> bool {
   if let Some(info) = feature_info() {
     info.has_acpi()
@@ -40,6 +67,12 @@ pub fn enable_nxe_bit() {
   }
 }
 
+/// Makes supervisor writes to read-only pages fault
+pub fn enable_write_protect() {
+  use x86_64::registers::control::{Cr0, Cr0Flags};
+  unsafe { Cr0::update(|flags| flags.insert(Cr0Flags::WRITE_PROTECT)) };
+}
+
 pub struct PageFaultContext {
   // Page Fault Address
   fault_address: VirtAddr,
@@ -16,4 +16,42 @@ pub fn init() {
 
 pub fn end_of_interrupt(id: u8) {
   unsafe { PICS.lock().notify_end_of_interrupt(id) }
-}
\ No newline at end of file
+}
+
+const PIC_1_DATA: u16 = 0x21;
+const PIC_2_DATA: u16 = 0xA1;
+
+fn data_port(irq: u8) -> (x86_64::instructions::port::Port<u8>, u8) {
+  use x86_64::instructions::port::Port;
+  if irq < 8 {
+    (Port::new(PIC_1_DATA), irq)
+  } else {
+    (Port::new(PIC_2_DATA), irq - 8)
+  }
+}
+
+pub fn mask(irq: u8) {
+  let _pics = PICS.lock();
+  let (mut port, line) = data_port(irq);
+  unsafe {
+    let value = port.read();
+    port.write(value | (1 << line));
+  }
+}
+
+pub fn unmask(irq: u8) {
+  let _pics = PICS.lock();
+  let (mut port, line) = data_port(irq);
+  unsafe {
+    let value = port.read();
+    port.write(value & !(1 << line));
+  }
+}
+
+/// Masks every line of both PICs, the PICs stay remapped so spurious
+/// interrupts do not collide with exceptions
+pub fn disable() {
+  for irq in 0..16 {
+    mask(irq);
+  }
+}
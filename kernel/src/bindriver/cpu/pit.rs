@@ -0,0 +1,33 @@
+use x86_64::instructions::port::Port;
+
+const PIT_FREQUENCY: u32 = 1_193_182;
+const PIT_CHANNEL_2: u16 = 0x42;
+const PIT_COMMAND: u16 = 0x43;
+// gate and output of PIT channel 2 and the speaker enable
+const PIT_CONTROL: u16 = 0x61;
+
+const CONTROL_GATE: u8 = 1 << 0;
+const CONTROL_SPEAKER: u8 = 1 << 1;
+const CONTROL_OUTPUT: u8 = 1 << 5;
+
+/// Busy waits using PIT channel 2, at most 54ms can be waited at once
+pub fn wait_ms(ms: u32) {
+  assert!(ms > 0 && ms <= 54, "PIT can only wait up to 54ms");
+  let count = PIT_FREQUENCY * ms / 1000;
+  let mut control: Port<u8> = Port::new(PIT_CONTROL);
+  let mut command: Port<u8> = Port::new(PIT_COMMAND);
+  let mut channel: Port<u8> = Port::new(PIT_CHANNEL_2);
+  unsafe {
+    let gate = control.read() & !(CONTROL_SPEAKER | CONTROL_GATE);
+    control.write(gate);
+    // channel 2, low and high byte, mode 0 (interrupt on terminal count)
+    command.write(0b1011_0000);
+    channel.write(count as u8);
+    channel.write((count >> 8) as u8);
+    control.write(gate | CONTROL_GATE);
+    while control.read() & CONTROL_OUTPUT == 0 {
+      core::sync::atomic::spin_loop_hint();
+    }
+    control.write(gate);
+  }
+}
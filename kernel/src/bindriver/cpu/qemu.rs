@@ -6,10 +6,16 @@ pub enum QemuExitCode {
 }
 
 pub fn exit_qemu(exit_code: QemuExitCode) {
+    exit_qemu_with(exit_code as u32)
+}
+
+/// Exits QEMU through the isa-debug-exit device, QEMU exits with the
+/// status (code << 1) | 1. Returns if there is no such device
+pub fn exit_qemu_with(code: u32) {
     use x86_64::instructions::port::Port;
 
     unsafe {
         let mut port = Port::new(0xf4);
-        port.write(exit_code as u32);
+        port.write(code);
     }
 }
\ No newline at end of file
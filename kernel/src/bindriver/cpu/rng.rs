@@ -1,32 +1,228 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
 
-pub fn get_u128() -> u128 {
-  let mut slice: [u8; 16] = [0; 16];
-  for x in 0..16 {
-    slice[x] = (get_u64() & 0xFF) as u8;
+// The kernel entropy pool is a ChaCha20 key. Entropy from RDSEED, RDRAND,
+// TSC jitter and interrupt timing is mixed into the key on reseed, output is
+// ChaCha20 keystream and the key is replaced after every request so earlier
+// output can not be reconstructed from the pool.
+
+/// Output bytes after which the pool is reseeded
+const RESEED_INTERVAL: u64 = 1 << 20;
+/// Output bytes generated with one key before it is replaced
+const REKEY_INTERVAL: usize = 4096;
+const HW_RETRIES: usize = 10;
+
+const CHACHA_CONSTANTS: [u32; 4] = [0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574];
+// nonce of the block deriving the next key, output blocks use a zero nonce
+const REKEY_NONCE: [u32; 3] = [0, 0, 1];
+const RESEED_NONCE: [u32; 3] = [0, 0, 2];
+
+struct Pool {
+  key: [u32; 8],
+  seeded: bool,
+  deterministic: bool,
+  generated: u64,
+}
+
+static POOL: Mutex<Pool> = Mutex::new(Pool {
+  key: [0; 8],
+  seeded: false,
+  deterministic: false,
+  generated: 0,
+});
+
+// interrupt handlers must not take the pool lock, their timing is
+// collected here and mixed in on the next reseed
+static INTERRUPT_ENTROPY: AtomicU64 = AtomicU64::new(0);
+static INTERRUPT_EVENTS: AtomicU64 = AtomicU64::new(0);
+
+fn quarter_round(s: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+  s[a] = s[a].wrapping_add(s[b]); s[d] ^= s[a]; s[d] = s[d].rotate_left(16);
+  s[c] = s[c].wrapping_add(s[d]); s[b] ^= s[c]; s[b] = s[b].rotate_left(12);
+  s[a] = s[a].wrapping_add(s[b]); s[d] ^= s[a]; s[d] = s[d].rotate_left(8);
+  s[c] = s[c].wrapping_add(s[d]); s[b] ^= s[c]; s[b] = s[b].rotate_left(7);
+}
+
+/// Computes a ChaCha20 block as specified in RFC 7539
+pub fn chacha20_block(key: &[u32; 8], counter: u32, nonce: &[u32; 3]) -> [u32; 16] {
+  let mut input = [0u32; 16];
+  input[0..4].copy_from_slice(&CHACHA_CONSTANTS);
+  input[4..12].copy_from_slice(key);
+  input[12] = counter;
+  input[13..16].copy_from_slice(nonce);
+  let mut state = input;
+  for _ in 0..10 {
+    quarter_round(&mut state, 0, 4, 8, 12);
+    quarter_round(&mut state, 1, 5, 9, 13);
+    quarter_round(&mut state, 2, 6, 10, 14);
+    quarter_round(&mut state, 3, 7, 11, 15);
+    quarter_round(&mut state, 0, 5, 10, 15);
+    quarter_round(&mut state, 1, 6, 11, 12);
+    quarter_round(&mut state, 2, 7, 8, 13);
+    quarter_round(&mut state, 3, 4, 9, 14);
   }
-  u128::from_le_bytes(slice)
+  for (word, init) in state.iter_mut().zip(input.iter()) {
+    *word = word.wrapping_add(*init);
+  }
+  state
 }
 
-pub fn get_u64() -> u64 {
+fn rdtsc() -> u64 {
+  unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+fn rdseed() -> Option<u64> {
+  if !crate::bindriver::cpu::has_rdseed() {
+    return None;
+  }
+  for _ in 0..HW_RETRIES {
+    let (value, ok): (u64, u8);
+    unsafe { asm!("rdseed $0; setc $1" : "=r"(value), "=r"(ok) ::: "intel", "volatile") };
+    if ok != 0 {
+      return Some(value);
+    }
+  }
+  None
+}
+
+fn rdrand() -> Option<u64> {
   if !crate::bindriver::cpu::has_rdrand() {
-    panic!("RDRAND but kernel compiled without internal RNG")
-  } else {
-    let rnd: u64;
-    let retry: u32;
-    unsafe{
-      asm!(
-        "
-        mov ecx, 1000
-        retry_handle_gen:
-          rdrand rax
-          jc .done_handle_gen
-          loop retry_handle_gen
-        .done_handle_gen:
-        ":
-        "={rax}"(rnd), "={ecx}"(retry)::"rax", "ecx":"intel", "volatile"
-      );
-    }
-    if retry == 0 { panic!("could not get random number")}
-    rnd
-  }
-}
\ No newline at end of file
+    return None;
+  }
+  for _ in 0..HW_RETRIES {
+    let (value, ok): (u64, u8);
+    unsafe { asm!("rdrand $0; setc $1" : "=r"(value), "=r"(ok) ::: "intel", "volatile") };
+    if ok != 0 {
+      return Some(value);
+    }
+  }
+  None
+}
+
+/// Collects the timing jitter of port I/O, only the low bits of each
+/// sample are expected to be unpredictable
+fn tsc_jitter() -> u64 {
+  use x86_64::instructions::port::Port;
+  let mut port: Port<u8> = Port::new(0x80);
+  let mut acc = 0u64;
+  let mut last = rdtsc();
+  for _ in 0..64 {
+    unsafe { port.write(0) };
+    let now = rdtsc();
+    acc = acc.rotate_left(7) ^ now.wrapping_sub(last);
+    last = now;
+  }
+  acc
+}
+
+/// Records the arrival time of an interrupt, safe to call from interrupt handlers
+pub fn add_interrupt_timing(vector: u8) {
+  let prev = INTERRUPT_ENTROPY.load(Ordering::Relaxed);
+  INTERRUPT_ENTROPY.store(prev.rotate_left(5) ^ rdtsc() ^ vector as u64, Ordering::Relaxed);
+  INTERRUPT_EVENTS.fetch_add(1, Ordering::Relaxed);
+}
+
+impl Pool {
+  /// Absorbs the input into the key, the old key is not recoverable
+  fn mix(&mut self, input: &[u64; 4]) {
+    for (i, word) in input.iter().enumerate() {
+      self.key[2 * i] ^= *word as u32;
+      self.key[2 * i + 1] ^= (*word >> 32) as u32;
+    }
+    let block = chacha20_block(&self.key, 0, &RESEED_NONCE);
+    self.key.copy_from_slice(&block[0..8]);
+  }
+
+  fn reseed(&mut self) {
+    if self.deterministic {
+      return;
+    }
+    let mut input = [0u64; 4];
+    let mut hw = 0;
+    for word in input.iter_mut() {
+      if let Some(value) = rdseed().or_else(rdrand) {
+        *word = value;
+        hw += 1;
+      }
+    }
+    input[0] ^= tsc_jitter();
+    input[1] ^= tsc_jitter();
+    input[2] ^= INTERRUPT_ENTROPY.swap(0, Ordering::Relaxed);
+    input[3] ^= INTERRUPT_EVENTS.swap(0, Ordering::Relaxed) ^ rdtsc();
+    self.mix(&input);
+    if !self.seeded {
+      debug!("entropy pool seeded with {} hardware words", hw);
+    }
+    self.seeded = true;
+    self.generated = 0;
+  }
+
+  fn fill(&mut self, buf: &mut [u8]) {
+    if !self.seeded || self.generated >= RESEED_INTERVAL {
+      self.reseed();
+    }
+    for chunk in buf.chunks_mut(REKEY_INTERVAL) {
+      for (counter, out) in chunk.chunks_mut(64).enumerate() {
+        let block = chacha20_block(&self.key, counter as u32 + 1, &[0; 3]);
+        for (bytes, word) in out.chunks_mut(4).zip(block.iter()) {
+          bytes.copy_from_slice(&word.to_le_bytes()[..bytes.len()]);
+        }
+      }
+      let block = chacha20_block(&self.key, 0, &REKEY_NONCE);
+      self.key.copy_from_slice(&block[0..8]);
+      self.generated += chunk.len() as u64;
+    }
+  }
+}
+
+/// Seeds the pool, with BOS_RNG_SEED set at compile time the kernel
+/// starts in deterministic mode
+pub fn init() {
+  match option_env!("BOS_RNG_SEED").map(|seed| seed.parse::<u64>()) {
+    Some(Ok(seed)) => {
+      warn!("random numbers are deterministic, seed = {}", seed);
+      set_seed(Some(seed));
+    },
+    Some(Err(_)) => panic!("BOS_RNG_SEED must be an unsigned integer"),
+    None => POOL.lock().reseed(),
+  }
+}
+
+/// With a seed, output only depends on the seed and the sequence of
+/// requests; without, the pool returns to gathering entropy
+pub fn set_seed(seed: Option<u64>) {
+  let mut pool = POOL.lock();
+  match seed {
+    Some(seed) => {
+      pool.key = [0; 8];
+      pool.deterministic = true;
+      pool.mix(&[seed, 0, 0, 0]);
+      pool.seeded = true;
+    },
+    None => {
+      pool.deterministic = false;
+      pool.reseed();
+    },
+  }
+}
+
+pub fn is_deterministic() -> bool {
+  POOL.lock().deterministic
+}
+
+/// Fills the buffer with random bytes
+pub fn fill(buf: &mut [u8]) {
+  POOL.lock().fill(buf)
+}
+
+pub fn get_u128() -> u128 {
+  let mut bytes = [0u8; 16];
+  fill(&mut bytes);
+  u128::from_le_bytes(bytes)
+}
+
+pub fn get_u64() -> u64 {
+  let mut bytes = [0u8; 8];
+  fill(&mut bytes);
+  u64::from_le_bytes(bytes)
+}
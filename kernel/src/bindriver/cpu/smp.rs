@@ -0,0 +1,258 @@
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use x86_64::registers::model_specific::Msr;
+use crate::bindriver::cpu::{apic, gdt};
+use crate::common::MAX_CPUS;
+use crate::process_manager::TaskHandle;
+use crate::vmem::PAGE_SIZE;
+use crate::*;
+
+/// Physical address the application processors start at,
+/// it must be page aligned and below 1MiB
+pub const TRAMPOLINE_ADDR: usize = 0x8000;
+const AP_STACK_SIZE: usize = 4096 * 16;
+/// Vector used to wake an idle application processor
+pub const WAKEUP_VECTOR: u8 = 0xF0;
+
+// the GS base is unused otherwise, it holds the index of the CPU
+const IA32_GS_BASE: u32 = 0xC000_0101;
+
+// The trampoline is copied to TRAMPOLINE_ADDR and started through a SIPI.
+// It switches from real mode straight into long mode using the page tables
+// of the bootstrap processor, takes the next CPU index and stack and calls
+// the entry point with the CPU index as argument.
+// The page is mapped read-only, so the CPU counter lives in kernel memory.
+global_asm!(r#"
+.pushsection .text.ap_trampoline, "ax"
+.intel_syntax noprefix
+.code16
+.global ap_trampoline_start
+ap_trampoline_start:
+  cli
+  cld
+  xor ax, ax
+  mov ds, ax
+  lgdt [0x8000 + ap_trampoline_gdt_ptr - ap_trampoline_start]
+  mov eax, dword ptr [0x8000 + ap_trampoline_cr4 - ap_trampoline_start]
+  mov cr4, eax
+  mov eax, dword ptr [0x8000 + ap_trampoline_cr3 - ap_trampoline_start]
+  mov cr3, eax
+  mov ecx, 0xC0000080
+  rdmsr
+  or eax, 0x900 # LME | NXE
+  wrmsr
+  mov eax, dword ptr [0x8000 + ap_trampoline_cr0 - ap_trampoline_start]
+  mov cr0, eax
+  # far jump with 32bit offset into the 64bit code segment
+  .byte 0x66, 0xea
+  .long 0x8000 + ap_trampoline_long - ap_trampoline_start
+  .word 0x08
+.code64
+ap_trampoline_long:
+  mov ax, 0x10
+  mov ds, ax
+  mov es, ax
+  mov ss, ax
+  xor ax, ax
+  mov fs, ax
+  mov gs, ax
+  mov rbx, qword ptr [0x8000 + ap_trampoline_counter - ap_trampoline_start]
+  mov eax, 1
+  lock xadd dword ptr [rbx], eax
+  cmp eax, dword ptr [0x8000 + ap_trampoline_max_cpus - ap_trampoline_start]
+  jae ap_trampoline_park
+  mov edi, eax
+  mov rbx, qword ptr [0x8000 + ap_trampoline_stacks - ap_trampoline_start]
+  mov rsp, qword ptr [rbx + rax * 8]
+  xor rbp, rbp
+  mov rax, qword ptr [0x8000 + ap_trampoline_entry - ap_trampoline_start]
+  call rax
+ap_trampoline_park:
+  cli
+  hlt
+  jmp ap_trampoline_park
+.align 16
+ap_trampoline_gdt:
+  .quad 0
+  .quad 0x00af9a000000ffff
+  .quad 0x00cf92000000ffff
+ap_trampoline_gdt_ptr:
+  .word ap_trampoline_gdt_ptr - ap_trampoline_gdt - 1
+  .long 0x8000 + ap_trampoline_gdt - ap_trampoline_start
+.align 8
+.global ap_trampoline_params
+ap_trampoline_params:
+ap_trampoline_cr3: .quad 0
+ap_trampoline_cr4: .quad 0
+ap_trampoline_cr0: .quad 0
+ap_trampoline_entry: .quad 0
+ap_trampoline_stacks: .quad 0
+ap_trampoline_counter: .quad 0
+ap_trampoline_max_cpus: .long 0
+.global ap_trampoline_end
+ap_trampoline_end:
+.att_syntax prefix
+.popsection
+"#);
+
+extern "C" {
+  static ap_trampoline_start: u8;
+  static ap_trampoline_params: u8;
+  static ap_trampoline_end: u8;
+}
+
+/// Layout of the parameters at the end of the trampoline
+#[repr(C)]
+struct TrampolineParams {
+  cr3: u64,
+  cr4: u64,
+  cr0: u64,
+  entry: u64,
+  stacks: u64,
+  counter: u64,
+  max_cpus: u32,
+}
+
+// index handed to the next application processor, 0 is the bootstrap processor
+static NEXT_CPU: AtomicU32 = AtomicU32::new(1);
+static ONLINE_CPUS: AtomicUsize = AtomicUsize::new(1);
+
+/// Returns the index of the calling CPU, the bootstrap processor is 0
+pub fn cpu_index() -> usize {
+  let idx = unsafe { Msr::new(IA32_GS_BASE).read() } as usize;
+  if idx < MAX_CPUS { idx } else { 0 }
+}
+
+fn set_cpu_index(idx: usize) {
+  unsafe { Msr::new(IA32_GS_BASE).write(idx as u64) }
+}
+
+pub fn online_cpus() -> usize {
+  ONLINE_CPUS.load(Ordering::SeqCst)
+}
+
+/// Waits roughly the given number of microseconds, each write to the
+/// POST port takes about one microsecond
+fn io_delay(us: usize) {
+  use x86_64::instructions::port::Port;
+  let mut port: Port<u8> = Port::new(0x80);
+  for _ in 0..us {
+    unsafe { port.write(0) };
+  }
+}
+
+fn install_trampoline(stacks: &[u64]) {
+  use x86_64::registers::control::{Cr0, Cr3};
+  use crate::vmem::mapper::{map, MapType};
+  let (start, params, end) = unsafe {(
+    &ap_trampoline_start as *const u8 as usize,
+    &ap_trampoline_params as *const u8 as usize,
+    &ap_trampoline_end as *const u8 as usize,
+  )};
+  assert!(end - start <= PAGE_SIZE, "trampoline must fit into one page");
+  let (l4, _) = Cr3::read();
+  assert!(l4.start_address().as_u64() < 0x1_0000_0000, "page tables must be below 4GiB for the trampoline");
+  let cr4: u64;
+  unsafe { asm!("mov $0, cr4" : "=r"(cr4) ::: "intel", "volatile") };
+  let target = kinfo().get_pmo() + TRAMPOLINE_ADDR;
+  unsafe {
+    core::ptr::copy_nonoverlapping(start as *const u8, target.as_mut_ptr::<u8>(), end - start);
+    let params = (target + (params - start)).as_mut_ptr::<TrampolineParams>();
+    core::ptr::write_volatile(params, TrampolineParams {
+      cr3: l4.start_address().as_u64(),
+      cr4,
+      cr0: Cr0::read_raw(),
+      entry: ap_entry as usize as u64,
+      stacks: stacks.as_ptr() as u64,
+      counter: &NEXT_CPU as *const AtomicU32 as u64,
+      max_cpus: MAX_CPUS as u32,
+    });
+  }
+  // the trampoline enables paging while running from its physical address
+  let vaddr = VirtAddr::new(TRAMPOLINE_ADDR as u64);
+  map(vaddr, &[PhysAddr::new(TRAMPOLINE_ADDR as u64)], MapType::Code);
+}
+
+/// Starts all application processors, the local APIC must be initialized
+pub fn init() {
+  use crate::vmem::mapper::{unmap, MapType};
+  if !apic::is_initialized() {
+    info!("no local APIC, application processors are not started");
+    return;
+  }
+  kinfo().local().set_online(apic::id());
+  info!("CPU 0 (APIC {}) online", apic::id());
+  // kernel heap and MMIO share page tables with task memory,
+  // create them now so all CPUs share them
+  crate::vmem::pagetable::prefill_kernel_tables(
+    VirtAddr::new(crate::vmem::KHEAP_GUARD_LOW as u64),
+    VirtAddr::new(crate::vmem::MMIO_END as u64),
+  );
+  let mut stacks = vec![0u64; MAX_CPUS];
+  for cpu in 1..MAX_CPUS {
+    stacks[cpu] = gdt::alloc_stack(AP_STACK_SIZE).as_u64();
+    gdt::prepare_ap(cpu);
+  }
+  install_trampoline(&stacks);
+  debug!("starting application processors");
+  apic::broadcast_init();
+  io_delay(10_000);
+  for _ in 0..2 {
+    apic::broadcast_startup((TRAMPOLINE_ADDR / PAGE_SIZE) as u8);
+    io_delay(200);
+  }
+  // wait for the processors listed in the MADT, without ACPI the
+  // number of processors is unknown and the full timeout is waited
+  let expected = crate::bindriver::acpi::madt()
+    .map(|madt| core::cmp::min(madt.usable_processors(), MAX_CPUS));
+  for _ in 0..100 {
+    match expected {
+      Some(n) if online_cpus() >= n => break,
+      _ => io_delay(1_000),
+    }
+  }
+  unmap(VirtAddr::new(TRAMPOLINE_ADDR as u64), 1, MapType::Code);
+  // the stacks are owned by the CPUs now
+  core::mem::forget(stacks);
+  info!("{} CPUs online", online_cpus());
+}
+
+/// Asks an idle application processor to run the given task
+pub fn run_on(cpu: usize, th: TaskHandle) -> Result<(), TaskHandle> {
+  let local = kinfo().cpu(cpu).filter(|local| local.is_online() && cpu != cpu_index()).ok_or(th)?;
+  local.post_task(th)?;
+  apic::send_interrupt(local.apic_id(), WAKEUP_VECTOR);
+  Ok(())
+}
+
+extern "C" fn ap_entry(cpu: u32) -> ! {
+  use x86_64::registers::control::{Cr3, Cr3Flags};
+  let cpu = cpu as usize;
+  set_cpu_index(cpu);
+  gdt::init_ap(cpu);
+  crate::bindriver::cpu::idt::init();
+  apic::enable();
+  #[cfg(feature = "fpu")]
+  crate::bindriver::cpu::fpu::init();
+  let l4 = crate::vmem::pagetable::clone_for_cpu();
+  unsafe { Cr3::write(l4, Cr3Flags::empty()) };
+  kinfo().local().set_online(apic::id());
+  ONLINE_CPUS.fetch_add(1, Ordering::SeqCst);
+  info!("CPU {} (APIC {}) online", cpu, apic::id());
+  idle()
+}
+
+/// Waits for tasks posted to the calling CPU with run_on, a CPU without a
+/// task to run keeps handling interrupts here
+pub fn idle() -> ! {
+  loop {
+    x86_64::instructions::interrupts::disable();
+    if let Some(th) = kinfo().local().take_task() {
+      // the task does not return here, it must not hold the userspace lock
+      let us = userspace().clone();
+      us.enter_task(th);
+    }
+    // enabling interrupts takes effect after the next instruction,
+    // a wakeup arriving between the check and hlt is not lost
+    unsafe { asm!("sti; hlt" :::: "volatile") };
+  }
+}
@@ -0,0 +1,29 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+// assumed until calibrated, a 1GHz TSC
+const DEFAULT_TICKS_PER_MS: u64 = 1_000_000;
+
+static TICKS_PER_MS: AtomicU64 = AtomicU64::new(DEFAULT_TICKS_PER_MS);
+
+pub fn read() -> u64 {
+  unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+/// Measures the TSC frequency against the PIT
+pub fn calibrate() {
+  const CALIBRATION_MS: u32 = 10;
+  let start = read();
+  crate::bindriver::cpu::pit::wait_ms(CALIBRATION_MS);
+  let ticks = core::cmp::max((read() - start) / CALIBRATION_MS as u64, 1);
+  TICKS_PER_MS.store(ticks, Ordering::SeqCst);
+  debug!("TSC runs at {} ticks per ms", ticks);
+}
+
+pub fn ticks_per_ms() -> u64 {
+  TICKS_PER_MS.load(Ordering::Relaxed)
+}
+
+/// Milliseconds since the CPU was reset
+pub fn now_ms() -> u64 {
+  read() / ticks_per_ms()
+}
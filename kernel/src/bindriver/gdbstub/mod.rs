@@ -0,0 +1,482 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use spin::Mutex;
+use crate::process_manager::TaskHandle;
+use crate::VirtAddr;
+
+mod packet;
+use packet::{Com, decode_hex, parse_hex, push_hex, receive, send};
+
+// GDB remote serial protocol stub on COM2. It is entered on breakpoint and
+// debug exceptions and when the debugger sends Ctrl-C. Tasks are reported as
+// threads, only the interrupted task has a full register set.
+// Other CPUs keep running while the stub is active.
+
+const COM2: u16 = 0x2F8;
+const COM2_IRQ: u8 = 3;
+const INTERRUPT_BYTE: u8 = 0x03;
+
+const VECTOR_DEBUG: u64 = 1;
+const VECTOR_BREAKPOINT: u64 = 3;
+const VECTOR_SERIAL: u64 = 0x100;
+
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+const RFLAGS_TRAP: u64 = 1 << 8;
+const INT3: u8 = 0xCC;
+
+/// Registers saved by the entry stubs, followed by the interrupt frame
+#[repr(C)]
+#[derive(Debug)]
+pub struct Registers {
+  r15: u64, r14: u64, r13: u64, r12: u64, r11: u64, r10: u64, r9: u64, r8: u64,
+  rbp: u64, rdi: u64, rsi: u64, rdx: u64, rcx: u64, rbx: u64, rax: u64,
+  rip: u64, cs: u64, rflags: u64, rsp: u64, ss: u64,
+}
+
+impl Registers {
+  /// The 64bit registers in the order of the GDB amd64 register set
+  fn gdb_order(&mut self) -> [&mut u64; 17] {
+    [
+      &mut self.rax, &mut self.rbx, &mut self.rcx, &mut self.rdx,
+      &mut self.rsi, &mut self.rdi, &mut self.rbp, &mut self.rsp,
+      &mut self.r8, &mut self.r9, &mut self.r10, &mut self.r11,
+      &mut self.r12, &mut self.r13, &mut self.r14, &mut self.r15,
+      &mut self.rip,
+    ]
+  }
+}
+
+// Each entry saves the general purpose registers below the interrupt frame
+// and passes them with the vector to gdbstub_handle.
+global_asm!(r#"
+.intel_syntax noprefix
+.macro GDBSTUB_ENTRY name, vector
+.global \name
+\name:
+  push rax
+  push rbx
+  push rcx
+  push rdx
+  push rsi
+  push rdi
+  push rbp
+  push r8
+  push r9
+  push r10
+  push r11
+  push r12
+  push r13
+  push r14
+  push r15
+  mov rdi, rsp
+  mov esi, \vector
+  call gdbstub_handle
+  pop r15
+  pop r14
+  pop r13
+  pop r12
+  pop r11
+  pop r10
+  pop r9
+  pop r8
+  pop rbp
+  pop rdi
+  pop rsi
+  pop rdx
+  pop rcx
+  pop rbx
+  pop rax
+  iretq
+.endm
+GDBSTUB_ENTRY gdbstub_debug_entry, 1
+GDBSTUB_ENTRY gdbstub_breakpoint_entry, 3
+GDBSTUB_ENTRY gdbstub_serial_entry, 0x100
+.att_syntax prefix
+"#);
+
+extern "C" {
+  fn gdbstub_debug_entry();
+  fn gdbstub_breakpoint_entry();
+  fn gdbstub_serial_entry();
+}
+
+struct Stub {
+  // address and original byte of every breakpoint
+  breakpoints: Vec<(u64, u8)>,
+  // breakpoints are written to memory while the debugged code runs
+  inserted: bool,
+  // single stepping over a breakpoint to reinsert it afterwards
+  step_over: bool,
+  // the debugger asked for a single step
+  stepping: bool,
+  // thread selected for register access, 0 is the stopped thread
+  selected: usize,
+}
+
+static STUB: Mutex<Stub> = Mutex::new(Stub {
+  breakpoints: Vec::new(),
+  inserted: false,
+  step_over: false,
+  stepping: false,
+  selected: 0,
+});
+
+/// Installs the stub on the breakpoint and debug exceptions and the COM2
+/// interrupt, replacing the logging breakpoint handler
+pub fn install(idt: &mut x86_64::structures::idt::InterruptDescriptorTable) {
+  use x86_64::structures::idt::InterruptStackFrame;
+  type Handler = extern "x86-interrupt" fn(&mut InterruptStackFrame);
+  let (debug, breakpoint, serial): (Handler, Handler, Handler) = unsafe {(
+    core::mem::transmute(gdbstub_debug_entry as usize),
+    core::mem::transmute(gdbstub_breakpoint_entry as usize),
+    core::mem::transmute(gdbstub_serial_entry as usize),
+  )};
+  idt.debug.set_handler_fn(debug);
+  idt.breakpoint.set_handler_fn(breakpoint);
+  let vector = crate::bindriver::cpu::interrupts::isa_vector(COM2_IRQ);
+  idt[usize::from(vector)].set_handler_fn(serial);
+}
+
+/// Configures COM2 and enables its interrupt so the debugger can interrupt
+/// the kernel, requires the interrupt controller to be initialized
+pub fn init() {
+  let mut port = unsafe { uart_16550::SerialPort::new(COM2) };
+  port.init();
+  crate::bindriver::cpu::interrupts::unmask_isa(COM2_IRQ);
+  info!("GDB stub listening on COM2");
+}
+
+/// Stops in the debugger
+pub fn breakpoint() {
+  unsafe { asm!("int3" :::: "volatile") };
+}
+
+fn mapped(addr: u64) -> bool {
+  VirtAddr::try_new(addr).is_ok()
+    && unsafe { crate::vmem::pagetable::translate_unlocked(VirtAddr::new(addr)).is_some() }
+}
+
+fn read_memory(addr: u64, len: u64) -> Option<Vec<u8>> {
+  (0..len).map(|i| {
+    let addr = addr.checked_add(i)?;
+    if mapped(addr) {
+      Some(unsafe { core::ptr::read_volatile(addr as *const u8) })
+    } else {
+      None
+    }
+  }).collect()
+}
+
+/// Writes memory even if it is mapped read-only, like kernel code
+fn write_memory(addr: u64, data: &[u8]) -> bool {
+  use x86_64::registers::control::{Cr0, Cr0Flags};
+  let end = match addr.checked_add(data.len() as u64) {
+    Some(end) => end,
+    None => return false,
+  };
+  if !(addr..end).all(mapped) {
+    return false;
+  }
+  unsafe {
+    let cr0 = Cr0::read();
+    Cr0::write(cr0 - Cr0Flags::WRITE_PROTECT);
+    for (i, byte) in data.iter().enumerate() {
+      core::ptr::write_volatile((addr + i as u64) as *mut u8, *byte);
+    }
+    Cr0::write(cr0);
+  }
+  true
+}
+
+impl Stub {
+  fn has_breakpoint(&self, addr: u64) -> bool {
+    self.breakpoints.iter().any(|(bp, _)| *bp == addr)
+  }
+  fn insert_breakpoints(&mut self) {
+    if !self.inserted {
+      for (addr, _) in self.breakpoints.iter() {
+        write_memory(*addr, &[INT3]);
+      }
+      self.inserted = true;
+    }
+  }
+  fn remove_breakpoints(&mut self) {
+    if self.inserted {
+      for (addr, original) in self.breakpoints.iter() {
+        write_memory(*addr, &[*original]);
+      }
+      self.inserted = false;
+    }
+  }
+  /// Continues the stopped code, steps over a breakpoint at rip first
+  fn resume(&mut self, regs: &mut Registers, step: bool) {
+    self.stepping = step;
+    self.step_over = self.has_breakpoint(regs.rip);
+    if step || self.step_over {
+      regs.rflags |= RFLAGS_TRAP;
+    }
+    if !self.step_over {
+      self.insert_breakpoints();
+    }
+  }
+}
+
+struct Thread {
+  th: TaskHandle,
+  name: Option<String>,
+  rip: u64,
+  rsp: u64,
+  rbp: u64,
+}
+
+/// Lists all tasks as threads, thread ids are the index plus one
+fn threads() -> Vec<Thread> {
+  let mut threads = Vec::new();
+  // the stub may be entered before userspace exists
+  if let Some(us) = crate::common::try_userspace() {
+    threads = us.in_scheduler(|sched| {
+      let mut list = Vec::new();
+      sched.try_for_each_task(|th, task| {
+        list.push(match task {
+          Some(task) => Thread { th, name: Some(task.name()), rip: task.rip(), rsp: task.rsp(), rbp: task.rbp() },
+          None => Thread { th, name: None, rip: 0, rsp: 0, rbp: 0 },
+        });
+      });
+      list
+    }).unwrap_or_default();
+  }
+  threads
+}
+
+fn current_thread(threads: &[Thread]) -> usize {
+  let current = crate::kinfo().get_current_task();
+  threads.iter().position(|thread| thread.th == current).map(|idx| idx + 1).unwrap_or(1)
+}
+
+fn push_u64_hex(out: &mut Vec<u8>, value: u64) {
+  let mut digits = false;
+  for shift in (0..16).rev() {
+    let digit = ((value >> (shift * 4)) & 0xF) as u8;
+    if digit != 0 || digits || shift == 0 {
+      out.push(packet::hex_digit(digit));
+      digits = true;
+    }
+  }
+}
+
+fn read_registers(regs: &mut Registers, threads: &[Thread], selected: usize) -> Vec<u8> {
+  let mut out = Vec::new();
+  let (values, rflags, cs, ss) = match threads.get(selected.wrapping_sub(1)) {
+    Some(thread) if selected != current_thread(threads) => {
+      // suspended tasks only keep their stack and entry point
+      let mut values = [0u64; 17];
+      values[6] = thread.rbp;
+      values[7] = thread.rsp;
+      values[16] = thread.rip;
+      (values, 0, regs.cs, regs.ss)
+    },
+    _ => {
+      let mut values = [0u64; 17];
+      for (value, reg) in values.iter_mut().zip(regs.gdb_order().iter()) {
+        *value = **reg;
+      }
+      (values, regs.rflags, regs.cs, regs.ss)
+    },
+  };
+  for value in values.iter() {
+    push_hex(&mut out, &value.to_le_bytes());
+  }
+  // eflags, cs, ss, ds, es, fs, gs
+  for value in [rflags, cs, ss, 0, 0, 0, 0].iter() {
+    push_hex(&mut out, &(*value as u32).to_le_bytes());
+  }
+  out
+}
+
+fn write_registers(regs: &mut Registers, data: &[u8]) -> bool {
+  let bytes = match decode_hex(data) {
+    Some(bytes) if bytes.len() >= 17 * 8 + 4 => bytes,
+    _ => return false,
+  };
+  let mut word = [0u8; 8];
+  for (i, reg) in regs.gdb_order().iter_mut().enumerate() {
+    word.copy_from_slice(&bytes[i * 8..i * 8 + 8]);
+    **reg = u64::from_le_bytes(word);
+  }
+  let mut eflags = [0u8; 4];
+  eflags.copy_from_slice(&bytes[17 * 8..17 * 8 + 4]);
+  regs.rflags = u32::from_le_bytes(eflags) as u64;
+  true
+}
+
+/// Splits "addr,len" and returns the remaining bytes after the separator
+fn parse_addr_len(args: &[u8], sep: u8) -> Option<(u64, u64, &[u8])> {
+  let comma = args.iter().position(|c| *c == b',')?;
+  let end = args.iter().position(|c| *c == sep).unwrap_or(args.len());
+  let addr = parse_hex(&args[..comma])?;
+  let len = parse_hex(args.get(comma + 1..end)?)?;
+  Some((addr, len, args.get(end + 1..).unwrap_or(&[])))
+}
+
+fn stop_reply(signal: u8, thread: usize) -> Vec<u8> {
+  let mut out = Vec::new();
+  out.push(b'T');
+  push_hex(&mut out, &[signal]);
+  out.extend_from_slice(b"thread:");
+  push_u64_hex(&mut out, thread as u64);
+  out.push(b';');
+  out
+}
+
+#[no_mangle]
+extern "C" fn gdbstub_handle(regs: &mut Registers, vector: u64) {
+  let mut stub = STUB.lock();
+  let mut com = Com::new(COM2);
+  let signal = match vector {
+    VECTOR_DEBUG => {
+      regs.rflags &= !RFLAGS_TRAP;
+      if stub.step_over {
+        stub.step_over = false;
+        stub.insert_breakpoints();
+        if !stub.stepping {
+          return;
+        }
+      }
+      stub.stepping = false;
+      SIGTRAP
+    },
+    VECTOR_BREAKPOINT => {
+      // the CPU reports the address after int3
+      if stub.has_breakpoint(regs.rip - 1) {
+        regs.rip -= 1;
+      }
+      SIGTRAP
+    },
+    VECTOR_SERIAL => {
+      let vector = crate::bindriver::cpu::interrupts::isa_vector(COM2_IRQ);
+      crate::bindriver::cpu::interrupts::end_of_interrupt(vector);
+      match com.try_read() {
+        Some(INTERRUPT_BYTE) => SIGINT,
+        _ => return,
+      }
+    },
+    _ => return,
+  };
+  stub.remove_breakpoints();
+  let threads = threads();
+  let current = current_thread(&threads);
+  stub.selected = current;
+  send(&mut com, &stop_reply(signal, current));
+  loop {
+    let request = receive(&mut com);
+    let (command, args) = match request.split_first() {
+      Some((command, args)) => (*command, args),
+      None => continue,
+    };
+    let reply: Vec<u8> = match command {
+      b'?' => stop_reply(signal, current),
+      b'g' => read_registers(regs, &threads, stub.selected),
+      b'G' if stub.selected == current => {
+        if write_registers(regs, args) { b"OK".to_vec() } else { b"E01".to_vec() }
+      },
+      b'G' => b"E01".to_vec(),
+      b'm' => match parse_addr_len(args, 0).and_then(|(addr, len, _)| read_memory(addr, len)) {
+        Some(bytes) => {
+          let mut out = Vec::new();
+          push_hex(&mut out, &bytes);
+          out
+        },
+        None => b"E14".to_vec(),
+      },
+      b'M' => match parse_addr_len(args, b':') {
+        Some((addr, len, data)) => match decode_hex(data) {
+          Some(ref bytes) if bytes.len() as u64 == len && write_memory(addr, bytes) => b"OK".to_vec(),
+          _ => b"E14".to_vec(),
+        },
+        None => b"E01".to_vec(),
+      },
+      b'Z' | b'z' if args.starts_with(b"0,") => match parse_addr_len(&args[2..], b';') {
+        Some((addr, _, _)) if command == b'Z' => {
+          match read_memory(addr, 1) {
+            Some(ref original) if !stub.has_breakpoint(addr) => {
+              stub.breakpoints.push((addr, original[0]));
+              b"OK".to_vec()
+            },
+            Some(_) => b"OK".to_vec(),
+            None => b"E14".to_vec(),
+          }
+        },
+        Some((addr, _, _)) => {
+          stub.breakpoints.retain(|(bp, _)| *bp != addr);
+          b"OK".to_vec()
+        },
+        None => b"E01".to_vec(),
+      },
+      b'c' | b's' => {
+        if let Some(addr) = parse_hex(args) {
+          regs.rip = addr;
+        }
+        stub.resume(regs, command == b's');
+        return;
+      },
+      b'D' => {
+        stub.breakpoints.clear();
+        send(&mut com, b"OK");
+        stub.resume(regs, false);
+        return;
+      },
+      b'k' => {
+        stub.breakpoints.clear();
+        stub.resume(regs, false);
+        return;
+      },
+      b'H' if args.len() > 1 => {
+        // thread 0 and -1 mean any thread
+        match parse_hex(&args[1..]) {
+          Some(0) | None => stub.selected = current,
+          Some(thread) if thread as usize <= threads.len() => stub.selected = thread as usize,
+          Some(_) => stub.selected = current,
+        }
+        b"OK".to_vec()
+      },
+      b'T' => match parse_hex(args) {
+        Some(thread) if thread >= 1 && thread as usize <= core::cmp::max(threads.len(), 1) => b"OK".to_vec(),
+        _ => b"E01".to_vec(),
+      },
+      b'q' if args.starts_with(b"Supported") => b"PacketSize=1000".to_vec(),
+      b'q' if args == b"Attached" => b"1".to_vec(),
+      b'q' if args == b"C" => {
+        let mut out = b"QC".to_vec();
+        push_u64_hex(&mut out, current as u64);
+        out
+      },
+      b'q' if args == b"fThreadInfo" => {
+        let mut out = b"m".to_vec();
+        for id in 1..=core::cmp::max(threads.len(), 1) {
+          if id > 1 {
+            out.push(b',');
+          }
+          push_u64_hex(&mut out, id as u64);
+        }
+        out
+      },
+      b'q' if args == b"sThreadInfo" => b"l".to_vec(),
+      b'q' if args.starts_with(b"ThreadExtraInfo,") => {
+        let info = parse_hex(&args[16..])
+          .and_then(|id| threads.get((id as usize).wrapping_sub(1)))
+          .map(|thread| match thread.name {
+            Some(ref name) => format!("{} ({})", name, thread.th),
+            None => format!("busy ({})", thread.th),
+          })
+          .unwrap_or_else(|| String::from("kernel"));
+        let mut out = Vec::new();
+        push_hex(&mut out, info.as_bytes());
+        out
+      },
+      _ => Vec::new(),
+    };
+    send(&mut com, &reply);
+  }
+}
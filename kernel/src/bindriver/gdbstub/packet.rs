@@ -0,0 +1,119 @@
+use alloc::vec::Vec;
+pub use crate::bindriver::serial::polled::Com;
+
+pub fn hex_digit(n: u8) -> u8 {
+  b"0123456789abcdef"[(n & 0xF) as usize]
+}
+
+pub fn from_hex_digit(c: u8) -> Option<u8> {
+  match c {
+    b'0'..=b'9' => Some(c - b'0'),
+    b'a'..=b'f' => Some(c - b'a' + 10),
+    b'A'..=b'F' => Some(c - b'A' + 10),
+    _ => None,
+  }
+}
+
+/// Parses a big endian hex number as used for addresses and lengths
+pub fn parse_hex(s: &[u8]) -> Option<u64> {
+  if s.is_empty() || s.len() > 16 {
+    return None;
+  }
+  s.iter().try_fold(0u64, |acc, c| Some(acc << 4 | from_hex_digit(*c)? as u64))
+}
+
+/// Decodes hex encoded bytes
+pub fn decode_hex(s: &[u8]) -> Option<Vec<u8>> {
+  if s.len() % 2 != 0 {
+    return None;
+  }
+  s.chunks(2)
+    .map(|pair| Some(from_hex_digit(pair[0])? << 4 | from_hex_digit(pair[1])?))
+    .collect()
+}
+
+pub fn push_hex(out: &mut Vec<u8>, bytes: &[u8]) {
+  for byte in bytes {
+    out.push(hex_digit(byte >> 4));
+    out.push(hex_digit(*byte));
+  }
+}
+
+fn checksum(data: &[u8]) -> u8 {
+  data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
+
+/// Receives the next packet with a valid checksum and acknowledges it
+pub fn receive(com: &mut Com) -> Vec<u8> {
+  loop {
+    while com.read() != b'$' {}
+    let mut data = Vec::new();
+    loop {
+      match com.read() {
+        b'#' => break,
+        b'$' => data.clear(),
+        byte => data.push(byte),
+      }
+    }
+    let high = from_hex_digit(com.read());
+    let low = from_hex_digit(com.read());
+    match (high, low) {
+      (Some(high), Some(low)) if high << 4 | low == checksum(&data) => {
+        com.write(b'+');
+        return data;
+      },
+      _ => com.write(b'-'),
+    }
+  }
+}
+
+/// Sends the packet until the debugger acknowledges it
+pub fn send(com: &mut Com, data: &[u8]) {
+  let sum = checksum(data);
+  loop {
+    com.write(b'$');
+    for byte in data {
+      com.write(*byte);
+    }
+    com.write(b'#');
+    com.write(hex_digit(sum >> 4));
+    com.write(hex_digit(sum));
+    match com.read() {
+      b'+' => return,
+      _ => continue,
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test_case]
+  fn test_parse_hex() {
+    assert_eq!(parse_hex(b"0"), Some(0));
+    assert_eq!(parse_hex(b"ffffffff8000beEF"), Some(0xffff_ffff_8000_beef));
+    assert_eq!(parse_hex(b""), None);
+    assert_eq!(parse_hex(b"10000000000000000"), None, "more than 64 bits");
+    assert_eq!(parse_hex(b"12g4"), None);
+  }
+
+  #[test_case]
+  fn test_decode_hex() {
+    assert_eq!(decode_hex(b""), Some(Vec::new()));
+    assert_eq!(decode_hex(b"00ff7A"), Some(alloc::vec![0x00, 0xff, 0x7a]));
+    assert_eq!(decode_hex(b"abc"), None, "odd length");
+    assert_eq!(decode_hex(b"zz"), None);
+    let mut out = Vec::new();
+    push_hex(&mut out, &[0x12, 0xab]);
+    assert_eq!(decode_hex(&out), Some(alloc::vec![0x12, 0xab]));
+  }
+
+  #[test_case]
+  fn test_checksum() {
+    // $qSupported#37 as sent by GDB
+    assert_eq!(checksum(b"qSupported"), 0x37);
+    assert_eq!(checksum(b""), 0);
+    assert_eq!(checksum(&[0xff, 0x02]), 0x01, "sum wraps modulo 256");
+  }
+}
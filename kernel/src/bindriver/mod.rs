@@ -2,13 +2,26 @@
 #[cfg(feature = "vga")]
 #[macro_use]
 pub mod vga_buffer;
+pub mod acpi;
+#[cfg(feature = "gdbstub")]
+pub mod gdbstub;
+#[cfg(feature = "monitor")]
+pub mod monitor;
+pub mod power;
 pub mod serial;
 pub mod qemu;
 pub mod cpu;
 
 pub fn init() {
   crate::bindriver::serial::init();
+  crate::bindriver::cpu::tsc::calibrate();
   crate::bindriver::cpu::enable_nxe_bit();
+  // tasks run in ring 0, their writes to the shared zero page must fault
+  // so the page is promoted instead of modified
+  crate::bindriver::cpu::enable_write_protect();
+  #[cfg(feature = "fpu")]
+  crate::bindriver::cpu::fpu::init();
+  crate::bindriver::cpu::rng::init();
   crate::bindriver::cpu::gdt::init();
   crate::bindriver::cpu::idt::init();
   crate::bindriver::cpu::pic::init();
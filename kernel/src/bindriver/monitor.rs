@@ -0,0 +1,247 @@
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, Ordering};
+use crate::bindriver::serial::polled::Com;
+use crate::VirtAddr;
+
+// The monitor is a command shell on COM1 for inspecting the kernel. It
+// polls the UART with interrupts disabled and does not depend on tasks or
+// the scheduler, other CPUs keep running while it is active.
+// It is entered with a serial break (Ctrl-A b in the QEMU console),
+// with Ctrl-] or on panic.
+
+const COM1: u16 = 0x3F8;
+const MAX_LINE: usize = 128;
+const MAX_DUMP: u64 = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+  Break,
+  Panic,
+}
+
+// the monitor is not reentrant, a panic inside a command must not enter it again
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Runs the monitor until it is resumed, returns immediately if the monitor
+/// is already active. After a panic the monitor cannot be resumed
+pub fn enter(reason: Reason) {
+  if ACTIVE.swap(true, Ordering::SeqCst) {
+    return;
+  }
+  x86_64::instructions::interrupts::without_interrupts(|| run(reason));
+  ACTIVE.store(false, Ordering::SeqCst);
+}
+
+fn run(reason: Reason) {
+  let mut com = Com::new(COM1);
+  writeln!(com, "\nkernel monitor on CPU {} ({:?}), 'help' lists commands",
+    crate::bindriver::cpu::smp::cpu_index(), reason).ok();
+  let mut line = [0u8; MAX_LINE];
+  loop {
+    write!(com, "mon> ").ok();
+    let len = read_line(&mut com, &mut line);
+    let line = core::str::from_utf8(&line[..len]).unwrap_or("");
+    let mut args = line.split_whitespace();
+    let command = match args.next() {
+      Some(command) => command,
+      None => continue,
+    };
+    match command {
+      "help" | "?" => help(&mut com),
+      "tasks" => tasks(),
+      "mem" => crate::pager().print_mem_summary(),
+      "walk" => match args.next().and_then(parse_number) {
+        Some(addr) => walk(&mut com, addr),
+        None => { writeln!(com, "usage: walk <address>").ok(); },
+      },
+      "dump" => match args.next().and_then(parse_number) {
+        Some(addr) => {
+          let len = args.next().and_then(parse_number).unwrap_or(64);
+          dump(&mut com, addr, core::cmp::min(len, MAX_DUMP));
+        },
+        None => { writeln!(com, "usage: dump <address> [length]").ok(); },
+      },
+      "kinfo" => kinfo(&mut com),
+      "resume" | "c" => {
+        if reason == Reason::Panic {
+          writeln!(com, "cannot resume after a panic").ok();
+        } else {
+          writeln!(com, "resuming").ok();
+          return;
+        }
+      },
+      _ => { writeln!(com, "unknown command '{}'", command).ok(); },
+    }
+  }
+}
+
+/// Reads a line with echo, returns its length
+fn read_line(com: &mut Com, buf: &mut [u8]) -> usize {
+  let mut len = 0;
+  loop {
+    match com.read() {
+      b'\r' | b'\n' => {
+        com.write(b'\n');
+        return len;
+      },
+      0x08 | 0x7F => {
+        if len > 0 {
+          len -= 1;
+          write!(com, "\x08 \x08").ok();
+        }
+      },
+      byte @ 0x20..=0x7E => {
+        if len < buf.len() {
+          buf[len] = byte;
+          len += 1;
+          com.write(byte);
+        }
+      },
+      _ => (),
+    }
+  }
+}
+
+/// Parses decimal or 0x prefixed hexadecimal numbers
+fn parse_number(s: &str) -> Option<u64> {
+  if s.starts_with("0x") {
+    u64::from_str_radix(&s[2..], 16).ok()
+  } else {
+    s.parse().ok()
+  }
+}
+
+fn help(com: &mut Com) {
+  writeln!(com, "  tasks                   list tasks").ok();
+  writeln!(com, "  mem                     print the memory summary to the log").ok();
+  writeln!(com, "  walk <address>          walk the page tables for the address").ok();
+  writeln!(com, "  dump <address> [length] dump memory, up to {} bytes", MAX_DUMP).ok();
+  writeln!(com, "  kinfo                   show kernel and per-CPU state").ok();
+  writeln!(com, "  resume                  leave the monitor").ok();
+}
+
+fn tasks() {
+  // in_scheduler only takes Fn closures, write through a fresh port
+  let userspace = match crate::common::try_userspace() {
+    Some(userspace) => userspace,
+    None => {
+      writeln!(Com::new(COM1), "userspace not set up").ok();
+      return;
+    },
+  };
+  let listed = userspace.in_scheduler(|sched| {
+    let mut com = Com::new(COM1);
+    let scheduler = sched.scheduler_task();
+    sched.try_for_each_task(|th, task| {
+      let cpu = (0..crate::common::MAX_CPUS).find(|cpu| {
+        let online = crate::kinfo().cpu(*cpu).map(|local| local.is_online()).unwrap_or(false);
+        online && sched.current_task_on(*cpu) == Some(th)
+      });
+      write!(com, "  {}", th).ok();
+      match task {
+        Some(task) => write!(com, " {:16} {:?} rip={:#018x}", task.name(), task.status(), task.rip()).ok(),
+        None => write!(com, " (busy)").ok(),
+      };
+      if th == scheduler {
+        write!(com, " scheduler").ok();
+      }
+      if let Some(cpu) = cpu {
+        write!(com, " running on CPU {}", cpu).ok();
+      }
+      writeln!(com).ok();
+    });
+  });
+  if listed.is_err() {
+    writeln!(Com::new(COM1), "scheduler is locked").ok();
+  }
+}
+
+fn walk(com: &mut Com, addr: u64) {
+  use x86_64::structures::paging::PageTableFlags;
+  const LEVELS: [&str; 4] = ["PML4", "PDPT", "PD", "PT"];
+  const ADDR_MASK: u64 = 0x000f_ffff_ffff_f000;
+  if VirtAddr::try_new(addr).is_err() {
+    writeln!(com, "{:#x} is not canonical", addr).ok();
+    return;
+  }
+  let pmo = crate::kinfo().get_pmo().as_u64();
+  let mut table = x86_64::registers::control::Cr3::read().0.start_address().as_u64();
+  for (level, name) in LEVELS.iter().enumerate() {
+    let shift = 39 - 9 * level as u64;
+    let index = (addr >> shift) & 0x1FF;
+    let entry = unsafe { core::ptr::read_volatile((pmo + table + index * 8) as *const u64) };
+    let flags = PageTableFlags::from_bits_truncate(entry);
+    writeln!(com, "  {:4} {:#018x}[{:3}] = {:#018x} {:?}", name, table, index, entry, flags).ok();
+    if !flags.contains(PageTableFlags::PRESENT) {
+      writeln!(com, "  not mapped").ok();
+      return;
+    }
+    if level == 3 || (level > 0 && flags.contains(PageTableFlags::HUGE_PAGE)) {
+      let offset = addr & ((1 << shift) - 1);
+      let frame = entry & ADDR_MASK & !((1 << shift) - 1);
+      writeln!(com, "  {:#x} -> {:#x}", addr, frame + offset).ok();
+      return;
+    }
+    table = entry & ADDR_MASK;
+  }
+}
+
+fn dump(com: &mut Com, addr: u64, len: u64) {
+  use crate::vmem::pagetable::translate_unlocked;
+  let mapped = |addr: u64| {
+    VirtAddr::try_new(addr).is_ok() && unsafe { translate_unlocked(VirtAddr::new(addr)).is_some() }
+  };
+  // the dump stops at the end of the address space
+  let end = addr.saturating_add(len);
+  let mut line = addr & !0xF;
+  while line < end {
+    if !mapped(line) {
+      writeln!(com, "  {:#018x} unmapped", line).ok();
+      line = match (line & !0xFFF).checked_add(0x1000) {
+        Some(next) => next,
+        None => break,
+      };
+      continue;
+    }
+    write!(com, "  {:#018x} ", line).ok();
+    let mut bytes = [0u8; 16];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+      *byte = unsafe { core::ptr::read_volatile((line + i as u64) as *const u8) };
+    }
+    for byte in bytes.iter() {
+      write!(com, " {:02x}", byte).ok();
+    }
+    write!(com, "  ").ok();
+    for byte in bytes.iter() {
+      com.write(if *byte >= 0x20 && *byte < 0x7F { *byte } else { b'.' });
+    }
+    writeln!(com).ok();
+    line = match line.checked_add(16) {
+      Some(next) => next,
+      None => break,
+    };
+  }
+}
+
+fn kinfo(com: &mut Com) {
+  let kinfo = crate::kinfo();
+  writeln!(com, "  physical memory offset {:#x}", kinfo.get_pmo().as_u64()).ok();
+  writeln!(com, "  mapping task image     {}", kinfo.is_mapping_task_image()).ok();
+  writeln!(com, "  memory pressure        {}", crate::pager().pressure() as u8).ok();
+  writeln!(com, "  online CPUs            {}", crate::bindriver::cpu::smp::online_cpus()).ok();
+  for cpu in 0..crate::common::MAX_CPUS {
+    let local = match kinfo.cpu(cpu) {
+      Some(local) if local.is_online() || cpu == 0 => local,
+      _ => continue,
+    };
+    let task = crate::common::try_userspace()
+      .and_then(|us| us.in_scheduler(|sched| sched.current_task_on(cpu)).ok())
+      .and_then(|task| task);
+    write!(com, "  CPU {} apic {:3} task ", cpu, local.apic_id()).ok();
+    match task {
+      Some(task) => write!(com, "{}", task).ok(),
+      None => write!(com, "(scheduler locked)").ok(),
+    };
+    writeln!(com, " switching {}", local.switching_tasks()).ok();
+  }
+}
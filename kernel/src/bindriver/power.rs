@@ -0,0 +1,142 @@
+use x86_64::instructions::port::Port;
+use crate::bindriver::acpi::{self, GenericAddress};
+
+// Shutdown tries the isa-debug-exit device when running in a virtual
+// machine, so QEMU exits with the given code, then ACPI S5. Reboot tries
+// the ACPI reset register, the keyboard controller and finally a triple fault.
+
+const PM1_CONTROL_SCI_ENABLE: u16 = 1 << 0;
+const PM1_CONTROL_SLEEP_TYPE_SHIFT: u16 = 10;
+const PM1_CONTROL_SLEEP_ENABLE: u16 = 1 << 13;
+const KBC_COMMAND: u16 = 0x64;
+const KBC_STATUS_INPUT_FULL: u8 = 1 << 1;
+const KBC_PULSE_RESET: u8 = 0xFE;
+// milliseconds to wait for each method to take effect
+const SETTLE_MS: u32 = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PowerError {
+  NoAcpi,
+  /// The DSDT has no usable \_S5 object
+  NoSleepType,
+  /// The firmware did not switch to ACPI mode
+  AcpiModeTimeout,
+  /// The FADT reset register is missing or in an unsupported address space
+  NoResetRegister,
+}
+
+fn settle() {
+  crate::bindriver::cpu::pit::wait_ms(SETTLE_MS);
+}
+
+/// Switches the firmware to ACPI mode if it is still in legacy mode
+fn enable_acpi_mode(fadt: &acpi::Fadt) -> Result<(), PowerError> {
+  let mut pm1a: Port<u16> = Port::new(fadt.pm1a_control_block as u16);
+  if unsafe { pm1a.read() } & PM1_CONTROL_SCI_ENABLE != 0 {
+    return Ok(());
+  }
+  if fadt.smi_command_port == 0 || fadt.acpi_enable == 0 {
+    // hardware reduced or always in ACPI mode
+    return Ok(());
+  }
+  let mut smi: Port<u8> = Port::new(fadt.smi_command_port as u16);
+  unsafe { smi.write(fadt.acpi_enable) };
+  for _ in 0..60 {
+    if unsafe { pm1a.read() } & PM1_CONTROL_SCI_ENABLE != 0 {
+      return Ok(());
+    }
+    settle();
+  }
+  Err(PowerError::AcpiModeTimeout)
+}
+
+/// Enters the ACPI S5 state, returns if the machine did not power off
+pub fn acpi_poweroff() -> Result<(), PowerError> {
+  let acpi = acpi::acpi().ok_or(PowerError::NoAcpi)?;
+  let fadt = acpi.fadt.as_ref().ok_or(PowerError::NoAcpi)?;
+  let (slp_typ_a, slp_typ_b) = acpi.s5_sleep_types().ok_or(PowerError::NoSleepType)?;
+  enable_acpi_mode(fadt)?;
+  unsafe {
+    let mut pm1a: Port<u16> = Port::new(fadt.pm1a_control_block as u16);
+    let value = pm1a.read() & !(0x7 << PM1_CONTROL_SLEEP_TYPE_SHIFT);
+    pm1a.write(value | (slp_typ_a as u16) << PM1_CONTROL_SLEEP_TYPE_SHIFT | PM1_CONTROL_SLEEP_ENABLE);
+    if fadt.pm1b_control_block != 0 {
+      let mut pm1b: Port<u16> = Port::new(fadt.pm1b_control_block as u16);
+      let value = pm1b.read() & !(0x7 << PM1_CONTROL_SLEEP_TYPE_SHIFT);
+      pm1b.write(value | (slp_typ_b as u16) << PM1_CONTROL_SLEEP_TYPE_SHIFT | PM1_CONTROL_SLEEP_ENABLE);
+    }
+  }
+  settle();
+  Ok(())
+}
+
+/// Writes the FADT reset value to the reset register, returns if the machine did not reset
+pub fn acpi_reset() -> Result<(), PowerError> {
+  let fadt = acpi::fadt().ok_or(PowerError::NoAcpi)?;
+  let register = fadt.reset_register.ok_or(PowerError::NoResetRegister)?;
+  match register.address_space {
+    GenericAddress::SYSTEM_IO => unsafe {
+      Port::<u8>::new(register.address as u16).write(fadt.reset_value)
+    },
+    GenericAddress::SYSTEM_MEMORY => unsafe {
+      let vaddr = crate::kinfo().get_pmo() + register.address;
+      core::ptr::write_volatile(vaddr.as_mut_ptr::<u8>(), fadt.reset_value)
+    },
+    _ => return Err(PowerError::NoResetRegister),
+  }
+  settle();
+  Ok(())
+}
+
+/// Pulses the reset line through the keyboard controller
+pub fn kbc_reset() {
+  let mut command: Port<u8> = Port::new(KBC_COMMAND);
+  unsafe {
+    for _ in 0..0x10000 {
+      if command.read() & KBC_STATUS_INPUT_FULL == 0 {
+        break;
+      }
+      core::sync::atomic::spin_loop_hint();
+    }
+    command.write(KBC_PULSE_RESET);
+  }
+  settle();
+}
+
+/// Resets the CPU by raising an exception without an IDT
+pub fn triple_fault() -> ! {
+  use x86_64::instructions::tables::{lidt, DescriptorTablePointer};
+  let idt = DescriptorTablePointer { limit: 0, base: 0 };
+  unsafe {
+    lidt(&idt);
+    asm!("int3" :::: "volatile");
+  }
+  hlt_cpu!();
+}
+
+/// Powers the machine off, under QEMU the code becomes the exit status.
+/// Halts the CPU if all methods fail
+pub fn shutdown(code: u32) -> ! {
+  x86_64::instructions::interrupts::disable();
+  info!("shutting down with code {}", code);
+  if crate::bindriver::cpu::has_hypervisor() {
+    crate::bindriver::cpu::qemu::exit_qemu_with(code);
+  }
+  if let Err(e) = acpi_poweroff() {
+    warn!("ACPI poweroff failed: {:?}", e);
+  }
+  error!("could not power off, halting");
+  hlt_cpu!();
+}
+
+/// Resets the machine
+pub fn reboot() -> ! {
+  x86_64::instructions::interrupts::disable();
+  info!("rebooting");
+  if let Err(e) = acpi_reset() {
+    warn!("ACPI reset failed: {:?}", e);
+  }
+  kbc_reset();
+  warn!("keyboard controller reset failed, triple faulting");
+  triple_fault();
+}
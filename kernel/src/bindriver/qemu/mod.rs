@@ -5,3 +5,60 @@ pub unsafe fn qemu_shutdown() {
     let mut port = Port::<u32>::new(0xf4);
     port.write(0);
 }
+
+const FW_CFG_SELECTOR: u16 = 0x510;
+const FW_CFG_DATA: u16 = 0x511;
+const FW_CFG_SIGNATURE: u16 = 0x0000;
+const FW_CFG_FILE_DIR: u16 = 0x0019;
+
+fn fw_cfg_select(key: u16) {
+    use x86_64::instructions::port::Port;
+
+    unsafe { Port::<u16>::new(FW_CFG_SELECTOR).write(key) }
+}
+
+fn fw_cfg_read(buf: &mut [u8]) {
+    use x86_64::instructions::port::Port;
+
+    let mut port = Port::<u8>::new(FW_CFG_DATA);
+    for b in buf.iter_mut() {
+        *b = unsafe { port.read() };
+    }
+}
+
+fn fw_cfg_read_be32() -> u32 {
+    let mut raw = [0u8; 4];
+    fw_cfg_read(&mut raw);
+    u32::from_be_bytes(raw)
+}
+
+/// Reads a file passed with `-fw_cfg name=<name>,...` into the buffer,
+/// returns the bytes read or None if not running under QEMU or the file
+/// does not exist
+pub fn read_fw_cfg_file(name: &str, buf: &mut [u8]) -> Option<usize> {
+    let mut signature = [0u8; 4];
+    fw_cfg_select(FW_CFG_SIGNATURE);
+    fw_cfg_read(&mut signature);
+    if &signature != b"QEMU" {
+        return None;
+    }
+
+    fw_cfg_select(FW_CFG_FILE_DIR);
+    let count = fw_cfg_read_be32();
+    for _ in 0..count {
+        // size (be32), select (be16), reserved (u16), name (56 bytes)
+        let mut entry = [0u8; 64];
+        fw_cfg_read(&mut entry);
+        let size = u32::from_be_bytes([entry[0], entry[1], entry[2], entry[3]]) as usize;
+        let select = u16::from_be_bytes([entry[4], entry[5]]);
+        let file = &entry[8..];
+        let len = file.iter().position(|&b| b == 0).unwrap_or(file.len());
+        if &file[..len] == name.as_bytes() {
+            let len = core::cmp::min(size, buf.len());
+            fw_cfg_select(select);
+            fw_cfg_read(&mut buf[..len]);
+            return Some(len);
+        }
+    }
+    None
+}
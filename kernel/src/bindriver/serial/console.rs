@@ -0,0 +1,146 @@
+use spin::Mutex;
+use crate::bindriver::serial::polled::Com;
+
+// COM1 input is received on IRQ4 into a line buffer. Carriage returns end
+// a line like newlines, backspace removes the last byte of the unfinished
+// line. Readers only get complete lines, or the unfinished line once the
+// buffer is full. Input is not echoed, the reader owns the console.
+
+const COM1: u16 = 0x3F8;
+pub const COM1_IRQ: u8 = 4;
+pub const INPUT_BUFFER_SIZE: usize = 1024;
+
+const BACKSPACE: u8 = 0x08;
+const DELETE: u8 = 0x7F;
+/// Ctrl-], enters the kernel monitor like a serial break
+pub const MONITOR_KEY: u8 = 0x1D;
+
+pub struct LineBuffer {
+  data: [u8; INPUT_BUFFER_SIZE],
+  start: usize,
+  len: usize,
+  // newlines in the buffer
+  lines: usize,
+  // bytes lost because the buffer was full
+  overrun: u64,
+}
+
+impl LineBuffer {
+  pub const fn new() -> LineBuffer {
+    LineBuffer { data: [0; INPUT_BUFFER_SIZE], start: 0, len: 0, lines: 0, overrun: 0 }
+  }
+  fn index(&self, offset: usize) -> usize {
+    (self.start + offset) % INPUT_BUFFER_SIZE
+  }
+  /// Adds a received byte
+  pub fn push(&mut self, byte: u8) {
+    match byte {
+      BACKSPACE | DELETE => {
+        if self.len > 0 && self.data[self.index(self.len - 1)] != b'\n' {
+          self.len -= 1;
+        }
+      },
+      _ => {
+        if self.len == INPUT_BUFFER_SIZE {
+          self.overrun += 1;
+          return;
+        }
+        let byte = if byte == b'\r' { b'\n' } else { byte };
+        let idx = self.index(self.len);
+        self.data[idx] = byte;
+        self.len += 1;
+        if byte == b'\n' {
+          self.lines += 1;
+        }
+      },
+    }
+  }
+  /// Returns true if a read would return data
+  pub fn readable(&self) -> bool {
+    self.lines > 0 || self.len == INPUT_BUFFER_SIZE
+  }
+  /// Moves complete lines into the buffer, a line longer than the buffer is
+  /// returned in parts. Returns the number of bytes read
+  pub fn read(&mut self, buf: &mut [u8]) -> usize {
+    if !self.readable() {
+      return 0;
+    }
+    let available = if self.lines > 0 {
+      // up to and including the last newline
+      (0..self.len).rev().find(|i| self.data[self.index(*i)] == b'\n').unwrap() + 1
+    } else {
+      self.len
+    };
+    let n = core::cmp::min(available, buf.len());
+    for slot in buf[..n].iter_mut() {
+      *slot = self.data[self.start];
+      if *slot == b'\n' {
+        self.lines -= 1;
+      }
+      self.start = self.index(1);
+      self.len -= 1;
+    }
+    n
+  }
+  pub fn overrun(&self) -> u64 {
+    self.overrun
+  }
+}
+
+static INPUT: Mutex<LineBuffer> = Mutex::new(LineBuffer::new());
+
+/// Moves all bytes waiting in the UART into the line buffer, called by the
+/// COM1 interrupt handler and by readers in case the interrupt is not delivered.
+/// With the monitor feature a break or the break key enters the kernel monitor
+pub fn receive() {
+  let mut com = Com::new(COM1);
+  let mut enter_monitor = false;
+  {
+    let mut input = INPUT.lock();
+    while let Some((byte, brk)) = com.try_read_break() {
+      if brk {
+        // a break is received as a NUL byte
+        enter_monitor = true;
+      } else if cfg!(feature = "monitor") && byte == MONITOR_KEY {
+        enter_monitor = true;
+      } else {
+        input.push(byte);
+      }
+    }
+  }
+  if enter_monitor {
+    #[cfg(feature = "monitor")]
+    crate::bindriver::monitor::enter(crate::bindriver::monitor::Reason::Break);
+  }
+}
+
+/// Enables the COM1 receive interrupt, requires the interrupt controller
+/// to be initialized. The UART itself raises it since it was set up by the logger
+pub fn init() {
+  // drop anything received while booting
+  receive();
+  crate::bindriver::cpu::interrupts::unmask_isa(COM1_IRQ);
+  debug!("console input on COM1");
+}
+
+/// Reads complete lines of console input into the buffer. If block is set
+/// waits until input is available, otherwise returns 0 if there is none.
+/// Returns the number of bytes read
+pub fn read(buf: &mut [u8], block: bool) -> usize {
+  use x86_64::instructions::interrupts;
+  loop {
+    let n = interrupts::without_interrupts(|| {
+      receive();
+      INPUT.lock().read(buf)
+    });
+    if n > 0 || !block || buf.is_empty() {
+      return n;
+    }
+    // wait for the next interrupt, the receive interrupt or the timer
+    if interrupts::are_enabled() {
+      x86_64::instructions::hlt();
+    } else {
+      unsafe { asm!("sti; hlt; cli" :::: "volatile") };
+    }
+  }
+}
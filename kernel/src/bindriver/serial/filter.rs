@@ -0,0 +1,186 @@
+use log::LevelFilter;
+use spin::RwLock;
+
+// A filter is a comma separated list of `level` and `target=level`
+// directives, like "info,vmem=debug,task/pid0=warn". Targets are module
+// paths without the crate name or `task/<name>` for messages logged by
+// tasks. The longest matching target wins, later directives override
+// earlier ones for the same target.
+// Filters are parsed once into a fixed size table as logging starts
+// before the kernel heap is available.
+
+/// Filter used unless the boot filter is passed through the QEMU firmware
+/// configuration, keeps the noisy memory management modules at info
+pub const DEFAULT_FILTER: &str = "trace,\
+  bindriver::cpu::idt=info,\
+  vmem::mapper=info,\
+  vmem::pagelist::pagelist_ng=info,\
+  common::kinfo=info,\
+  vmem::faulth=info";
+
+/// Firmware configuration file holding the boot filter, passed with
+/// `-fw_cfg name=opt/boringos/log,string=<filter>`
+pub const BOOT_FILTER_FILE: &str = "opt/boringos/log";
+
+pub const MAX_FILTER_LEN: usize = 512;
+pub const MAX_DIRECTIVES: usize = 32;
+
+const CRATE_PREFIX: &str = "boringos::";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterError {
+  UnknownLevel,
+  EmptyTarget,
+  TooLong,
+  TooManyDirectives,
+}
+
+#[derive(Clone, Copy)]
+struct Directive {
+  // target within the filter text
+  start: u16,
+  len: u16,
+  level: LevelFilter,
+}
+
+/// A parsed filter
+#[derive(Clone, Copy)]
+pub struct Filter {
+  text: [u8; MAX_FILTER_LEN],
+  directives: [Directive; MAX_DIRECTIVES],
+  count: usize,
+  default: LevelFilter,
+}
+
+impl Filter {
+  const fn empty() -> Filter {
+    Filter {
+      text: [0; MAX_FILTER_LEN],
+      directives: [Directive { start: 0, len: 0, level: LevelFilter::Off }; MAX_DIRECTIVES],
+      count: 0,
+      default: LevelFilter::Info,
+    }
+  }
+  pub fn parse(spec: &str) -> Result<Filter, FilterError> {
+    if spec.len() > MAX_FILTER_LEN {
+      return Err(FilterError::TooLong);
+    }
+    let mut filter = Filter::empty();
+    filter.text[..spec.len()].copy_from_slice(spec.as_bytes());
+    for directive in directives(spec) {
+      match directive? {
+        (None, level) => filter.default = level,
+        (Some(target), level) => {
+          if filter.count == MAX_DIRECTIVES {
+            return Err(FilterError::TooManyDirectives);
+          }
+          filter.directives[filter.count] = Directive {
+            start: (target.as_ptr() as usize - spec.as_ptr() as usize) as u16,
+            len: target.len() as u16,
+            level,
+          };
+          filter.count += 1;
+        },
+      }
+    }
+    Ok(filter)
+  }
+  fn target(&self, directive: &Directive) -> &str {
+    let start = directive.start as usize;
+    core::str::from_utf8(&self.text[start..start + directive.len as usize]).unwrap_or("")
+  }
+  /// Returns the level of the target under the filter
+  pub fn level(&self, target: &str) -> LevelFilter {
+    let target = target.trim_start_matches(CRATE_PREFIX);
+    let mut best: Option<(usize, LevelFilter)> = None;
+    for directive in self.directives[..self.count].iter() {
+      let prefix = self.target(directive);
+      if matches(prefix, target) && best.map(|(len, _)| prefix.len() >= len).unwrap_or(true) {
+        best = Some((prefix.len(), directive.level));
+      }
+    }
+    best.map(|(_, level)| level).unwrap_or(self.default)
+  }
+  /// Returns the most verbose level the filter enables, at least info
+  pub fn max_level(&self) -> LevelFilter {
+    self.directives[..self.count].iter()
+      .fold(core::cmp::max(LevelFilter::Info, self.default), |max, d| core::cmp::max(max, d.level))
+  }
+}
+
+static FILTER: RwLock<Filter> = RwLock::new(Filter::empty());
+
+/// Splits a filter into (target, level) directives, the default level has no target
+fn directives(spec: &str) -> impl Iterator<Item = Result<(Option<&str>, LevelFilter), FilterError>> {
+  spec.split(',').map(str::trim).filter(|d| !d.is_empty()).map(|directive| {
+    let (target, level) = match directive.find('=') {
+      None => (None, directive),
+      Some(idx) => {
+        let target = directive[..idx].trim().trim_start_matches(CRATE_PREFIX);
+        if target.is_empty() {
+          return Err(FilterError::EmptyTarget);
+        }
+        (Some(target), directive[idx + 1..].trim())
+      }
+    };
+    let level = level.parse().map_err(|_| FilterError::UnknownLevel)?;
+    Ok((target, level))
+  })
+}
+
+fn matches(prefix: &str, target: &str) -> bool {
+  target.starts_with(prefix) && {
+    let rest = &target[prefix.len()..];
+    rest.is_empty() || rest.starts_with("::") || rest.starts_with('/')
+  }
+}
+
+/// Checks the filter and returns the most verbose level it enables
+pub fn validate(spec: &str) -> Result<LevelFilter, FilterError> {
+  Filter::parse(spec).map(|filter| filter.max_level())
+}
+
+/// Installs the boot filter, from the QEMU firmware configuration or the
+/// default filter. The filter can be changed without rebuilding the kernel
+pub fn init() {
+  let mut buf = [0u8; MAX_FILTER_LEN];
+  let boot = crate::bindriver::qemu::read_fw_cfg_file(BOOT_FILTER_FILE, &mut buf)
+    .and_then(|len| core::str::from_utf8(&buf[..len]).ok())
+    .map(|spec| spec.trim_end_matches('\0').trim());
+  let filter = match boot.map(Filter::parse) {
+    None => Filter::parse(DEFAULT_FILTER).expect("default filter must be valid"),
+    Some(Ok(filter)) => filter,
+    Some(Err(e)) => {
+      install(Filter::parse(DEFAULT_FILTER).expect("default filter must be valid"));
+      warn!("invalid boot filter {:?}: {:?}, using the default filter", boot, e);
+      return;
+    },
+  };
+  install(filter);
+}
+
+/// Returns the active filter
+pub fn active() -> Filter {
+  *FILTER.read()
+}
+
+/// Replaces the active filter
+pub fn install(filter: Filter) {
+  *FILTER.write() = filter;
+  log::set_max_level(filter.max_level());
+}
+
+/// Parses and installs the filter, the active filter stays if it is invalid
+pub fn set(spec: &str) -> Result<(), FilterError> {
+  install(Filter::parse(spec)?);
+  Ok(())
+}
+
+/// Returns true if a message at the level is logged for the target,
+/// while the filter is replaced only info and more severe messages pass
+pub fn enabled(target: &str, level: log::Level) -> bool {
+  match FILTER.try_read() {
+    Some(filter) => level <= filter.level(target),
+    None => level <= LevelFilter::Info,
+  }
+}
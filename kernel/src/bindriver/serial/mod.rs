@@ -1,4 +1,4 @@
-use log::{Level, LevelFilter, Metadata, Record};
+use log::{Metadata, Record};
 use spin::Mutex;
 use uart_16550::SerialPort;
 
@@ -12,47 +12,52 @@ lazy_static! {
     };
 }
 
+pub mod console;
+pub mod filter;
+pub mod polled;
+
 pub fn init() {
     ::log::set_logger(&SERIAL1).expect("could not setup logging");
-    ::log::set_max_level(LevelFilter::Trace);
+    filter::init();
 }
 
 impl ::log::Log for SERIAL1 {
     fn enabled(&self, metadata: &Metadata) -> bool {
-        metadata.level() <= Level::Info ||
-        (metadata.level() <= Level::Trace && !(false
-            // put in blacklisted debug modules here
-            // modules listed here are only logged for Info Level or higher
-            // || metadata.target() == "slabmalloc"
-            // || metadata.target() == "boringos::vmem::pagelist"
-            // || metadata.target() == "boringos::vmem::pagetable"
-            || metadata.target() == "boringos::bindriver::cpu::idt"
-            || metadata.target() == "boringos::vmem::mapper"
-            || metadata.target() == "boringos::vmem::pagelist::pagelist_ng"
-            || metadata.target() == "boringos::common::kinfo"
-            || metadata.target() == "boringos::vmem::faulth"
-            // || metadata.target() == "boringos::vmem"
-            // || metadata.target() == "boringos::process_manager::state"
-            // || metadata.target() == "boringos::process_manager::memory"
-        ))
+        filter::enabled(metadata.target(), metadata.level())
     }
 
     fn log(&self, record: &Record) {
         if self.enabled(record.metadata()) {
             use core::fmt::Write;
+            let module = record.target().trim_start_matches("boringos::");
+            // the task as the scheduler sees it, like task_log in the kcalls
+            let task = crate::common::current_taskhandle()
+                .unwrap_or(crate::process_manager::TaskHandle::zero());
+            crate::common::klog::record(record.level(), module, task, *record.args());
+            #[cfg(feature = "vga")]
+            crate::bindriver::vga_buffer::log(record.level(), module, *record.args());
             unsafe { self.force_unlock() };
             self.try_lock()
                 .and_then(|mut fmt| {
-                    fmt.write_fmt(format_args!(
-                        "{:6} {:>30}~{:04} - {}\n",
-                        record.level(),
-                        record
-                            .module_path()
-                            .expect("need module path to log properly")
-                            .trim_start_matches("boringos::"),
-                        record.line().expect("need line to log properly"),
-                        record.args(),
-                    )).ok()
+                    if module.starts_with("task/") {
+                        // messages of tasks carry the task name and handle
+                        // instead of the kcall's line
+                        fmt.write_fmt(format_args!(
+                            "{:6} {:>30}@{} - {}\n",
+                            record.level(),
+                            module,
+                            task,
+                            record.args(),
+                        )).ok()
+                    } else {
+                        fmt.write_fmt(format_args!(
+                            "{:6} {:>30}~{:04} - {}\n",
+                            record.level(),
+                            module,
+                            record.line().expect("need line to log properly"),
+                            record.args(),
+                        )).ok()
+                    }
                 }).expect("serial did not print");
         }
     }
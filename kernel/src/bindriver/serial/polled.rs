@@ -0,0 +1,63 @@
+use x86_64::instructions::port::Port;
+
+const LINE_STATUS_DATA_READY: u8 = 1 << 0;
+const LINE_STATUS_BREAK: u8 = 1 << 4;
+const LINE_STATUS_TX_EMPTY: u8 = 1 << 5;
+
+/// Polled access to a 16550 UART, used by code running with interrupts disabled
+pub struct Com {
+  data: Port<u8>,
+  line_status: Port<u8>,
+}
+
+impl Com {
+  pub fn new(base: u16) -> Com {
+    Com { data: Port::new(base), line_status: Port::new(base + 5) }
+  }
+  pub fn try_read(&mut self) -> Option<u8> {
+    unsafe {
+      if self.line_status.read() & LINE_STATUS_DATA_READY != 0 {
+        Some(self.data.read())
+      } else {
+        None
+      }
+    }
+  }
+  pub fn read(&mut self) -> u8 {
+    loop {
+      if let Some(byte) = self.try_read() {
+        return byte;
+      }
+      core::sync::atomic::spin_loop_hint();
+    }
+  }
+  /// Reads the next byte and reports if a break was received before it,
+  /// reading the line status clears the break condition
+  pub fn try_read_break(&mut self) -> Option<(u8, bool)> {
+    unsafe {
+      let status = self.line_status.read();
+      if status & LINE_STATUS_DATA_READY != 0 {
+        Some((self.data.read(), status & LINE_STATUS_BREAK != 0))
+      } else {
+        None
+      }
+    }
+  }
+  pub fn write(&mut self, byte: u8) {
+    unsafe {
+      while self.line_status.read() & LINE_STATUS_TX_EMPTY == 0 {
+        core::sync::atomic::spin_loop_hint();
+      }
+      self.data.write(byte);
+    }
+  }
+}
+
+impl core::fmt::Write for Com {
+  fn write_str(&mut self, s: &str) -> core::fmt::Result {
+    for byte in s.bytes() {
+      self.write(byte);
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,190 @@
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+use volatile::Volatile;
+
+// VGA text mode console, a second log sink next to COM1. The buffer at
+// 0xb8000 is identity mapped by the bootloader and stays mapped as part
+// of the kernel image region.
+
+const BUFFER_ADDR: usize = 0xb8000;
+const BUFFER_HEIGHT: usize = 25;
+const BUFFER_WIDTH: usize = 80;
+
+/// Least severe level shown on screen, the screen is too small for debug output
+const MAX_LEVEL: log::Level = log::Level::Info;
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Color {
+  Black = 0,
+  Blue = 1,
+  Green = 2,
+  Cyan = 3,
+  Red = 4,
+  Magenta = 5,
+  Brown = 6,
+  LightGray = 7,
+  DarkGray = 8,
+  LightBlue = 9,
+  LightGreen = 10,
+  LightCyan = 11,
+  LightRed = 12,
+  Pink = 13,
+  Yellow = 14,
+  White = 15,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
+struct ColorCode(u8);
+
+impl ColorCode {
+  const fn new(foreground: Color, background: Color) -> ColorCode {
+    ColorCode((background as u8) << 4 | (foreground as u8))
+  }
+}
+
+const DEFAULT_COLOR: ColorCode = ColorCode::new(Color::LightGray, Color::Black);
+
+fn level_color(level: log::Level) -> ColorCode {
+  match level {
+    log::Level::Error => ColorCode::new(Color::LightRed, Color::Black),
+    log::Level::Warn => ColorCode::new(Color::Yellow, Color::Black),
+    log::Level::Info => ColorCode::new(Color::White, Color::Black),
+    log::Level::Debug => ColorCode::new(Color::LightGray, Color::Black),
+    log::Level::Trace => ColorCode::new(Color::DarkGray, Color::Black),
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+struct ScreenChar {
+  character: u8,
+  color: ColorCode,
+}
+
+#[repr(transparent)]
+struct Buffer {
+  chars: [[Volatile<ScreenChar>; BUFFER_WIDTH]; BUFFER_HEIGHT],
+}
+
+pub struct Writer {
+  column: usize,
+  color: ColorCode,
+  buffer: &'static mut Buffer,
+}
+
+impl Writer {
+  pub fn set_color(&mut self, foreground: Color, background: Color) {
+    self.color = ColorCode::new(foreground, background);
+  }
+  pub fn write_byte(&mut self, byte: u8) {
+    match byte {
+      b'\n' => self.new_line(),
+      byte => {
+        if self.column >= BUFFER_WIDTH {
+          self.new_line();
+        }
+        let row = BUFFER_HEIGHT - 1;
+        self.buffer.chars[row][self.column].write(ScreenChar { character: byte, color: self.color });
+        self.column += 1;
+      }
+    }
+  }
+  pub fn write_string(&mut self, s: &str) {
+    for byte in s.bytes() {
+      match byte {
+        // printable ASCII and newline, the code page has no UTF-8
+        0x20..=0x7e | b'\n' => self.write_byte(byte),
+        _ => self.write_byte(0xfe),
+      }
+    }
+  }
+  /// Scrolls the screen up by one line
+  fn new_line(&mut self) {
+    for row in 1..BUFFER_HEIGHT {
+      for col in 0..BUFFER_WIDTH {
+        let character = self.buffer.chars[row][col].read();
+        self.buffer.chars[row - 1][col].write(character);
+      }
+    }
+    self.clear_row(BUFFER_HEIGHT - 1);
+    self.column = 0;
+  }
+  fn clear_row(&mut self, row: usize) {
+    let blank = ScreenChar { character: b' ', color: self.color };
+    for col in 0..BUFFER_WIDTH {
+      self.buffer.chars[row][col].write(blank);
+    }
+  }
+  pub fn clear(&mut self) {
+    for row in 0..BUFFER_HEIGHT {
+      self.clear_row(row);
+    }
+    self.column = 0;
+  }
+}
+
+impl fmt::Write for Writer {
+  fn write_str(&mut self, s: &str) -> fmt::Result {
+    self.write_string(s);
+    Ok(())
+  }
+}
+
+lazy_static! {
+  pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
+    column: 0,
+    color: DEFAULT_COLOR,
+    buffer: unsafe { &mut *(BUFFER_ADDR as *mut Buffer) },
+  });
+}
+
+// set once the kernel panicked, only errors are shown from then on
+static PANICKED: AtomicBool = AtomicBool::new(false);
+
+/// Writes a log record in the colour of its level, records are dropped
+/// while another CPU or the interrupted code is writing
+pub fn log(level: log::Level, module: &str, args: fmt::Arguments) {
+  use core::fmt::Write;
+  let max = if PANICKED.load(Ordering::Relaxed) { log::Level::Error } else { MAX_LEVEL };
+  if level > max {
+    return;
+  }
+  if let Some(mut writer) = WRITER.try_lock() {
+    writer.color = level_color(level);
+    writer.write_fmt(format_args!("{:5} {}: {}\n", level, module, args)).ok();
+    writer.color = DEFAULT_COLOR;
+  }
+}
+
+/// Clears the screen for the panic message, unlocks the writer if the
+/// panicking code held it
+pub fn panic_screen() {
+  PANICKED.store(true, Ordering::SeqCst);
+  unsafe { WRITER.force_unlock() };
+  let mut writer = WRITER.lock();
+  writer.set_color(Color::White, Color::Red);
+  writer.clear();
+  writer.write_string("KERNEL PANIC\n");
+  writer.color = DEFAULT_COLOR;
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+  use core::fmt::Write;
+  x86_64::instructions::interrupts::without_interrupts(|| {
+    WRITER.lock().write_fmt(args).ok();
+  });
+}
+
+macro_rules! vga_print {
+  ($($arg:tt)*) => ($crate::bindriver::vga_buffer::_print(format_args!($($arg)*)));
+}
+
+macro_rules! vga_println {
+  () => (vga_print!("\n"));
+  ($($arg:tt)*) => (vga_print!("{}\n", format_args!($($arg)*)));
+}
@@ -0,0 +1,157 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+use crate::VirtAddr;
+
+// The symbol table is patched into the kernel ELF after linking by
+// mksyms.sh. It is text, one entry per line and sorted by address:
+//   KSYMS 1
+//   F <address> <size> <function>
+//   L <address> <line> <file>
+// and terminated by a NUL byte. An unpatched kernel only has the header.
+
+const KSYMS_SIZE: usize = 4 * 1024 * 1024;
+const KSYMS_HEADER: [u8; 8] = [b'K', b'S', b'Y', b'M', b'S', b' ', b'0', b'\n'];
+const MAX_FRAMES: usize = 64;
+
+#[repr(C)]
+pub struct SymbolTable {
+  header: [u8; 8],
+  data: [u8; KSYMS_SIZE - 8],
+}
+
+// must not be constant, the compiler would assume the table stays empty
+#[no_mangle]
+#[link_section = ".ksyms"]
+pub static mut KSYMS: SymbolTable = SymbolTable {
+  header: KSYMS_HEADER,
+  data: [0; KSYMS_SIZE - 8],
+};
+
+// length of the table text, usize::MAX until first used
+static TABLE_LEN: AtomicUsize = AtomicUsize::new(usize::max_value());
+
+fn table() -> &'static str {
+  let bytes = unsafe {
+    core::slice::from_raw_parts(&KSYMS as *const SymbolTable as *const u8, KSYMS_SIZE)
+  };
+  let mut len = TABLE_LEN.load(Ordering::Relaxed);
+  if len == usize::max_value() {
+    len = bytes.iter().position(|b| *b == 0).unwrap_or(KSYMS_SIZE);
+    TABLE_LEN.store(len, Ordering::Relaxed);
+  }
+  core::str::from_utf8(&bytes[..len]).unwrap_or("")
+}
+
+pub fn has_symbols() -> bool {
+  table().starts_with("KSYMS 1\n")
+}
+
+/// Location of an address inside a kernel function
+#[derive(Debug, Clone, Copy)]
+pub struct Symbol {
+  pub name: &'static str,
+  pub offset: u64,
+  pub file: Option<&'static str>,
+  pub line: u32,
+}
+
+impl core::fmt::Display for Symbol {
+  fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+    write!(f, "{}+{:#x}", self.name, self.offset)?;
+    match self.file {
+      Some(file) => write!(f, " ({}:{})", file, self.line),
+      None => Ok(()),
+    }
+  }
+}
+
+/// Splits "<kind> <hex address> <field> <rest>" entries
+fn entries(kind: char) -> impl Iterator<Item = (u64, &'static str, &'static str)> {
+  table().lines().skip(1).filter_map(move |line| {
+    let mut fields = line.splitn(4, ' ');
+    if fields.next()?.chars().next()? != kind {
+      return None;
+    }
+    let addr = u64::from_str_radix(fields.next()?, 16).ok()?;
+    Some((addr, fields.next()?, fields.next()?))
+  })
+}
+
+/// Resolves an address against the embedded symbol table
+pub fn resolve(addr: u64) -> Option<Symbol> {
+  let (start, name) = entries('F')
+    .take_while(|(start, _, _)| *start <= addr)
+    .filter(|(start, size, _)| {
+      let size = u64::from_str_radix(size, 16).unwrap_or(0);
+      size == 0 || addr < start + size
+    })
+    .last()
+    .map(|(start, _, name)| (start, name))?;
+  let line = entries('L')
+    .take_while(|(start, _, _)| *start <= addr)
+    .last()
+    .filter(|(line_addr, _, _)| *line_addr >= start);
+  Some(Symbol {
+    name,
+    offset: addr - start,
+    file: line.map(|(_, _, file)| file),
+    line: line.and_then(|(_, line, _)| line.parse().ok()).unwrap_or(0),
+  })
+}
+
+/// Returns true if the frame record at addr can be read without faulting
+fn readable(addr: u64) -> bool {
+  use crate::vmem::pagetable::translate_unlocked;
+  addr % 8 == 0
+    && VirtAddr::try_new(addr).is_ok()
+    && VirtAddr::try_new(addr + 8).is_ok()
+    && unsafe { translate_unlocked(VirtAddr::new(addr)).is_some() }
+    && unsafe { translate_unlocked(VirtAddr::new(addr + 8)).is_some() }
+}
+
+fn print_frame(idx: usize, addr: u64) {
+  // return addresses point behind the call, resolve the call itself
+  match resolve(addr.saturating_sub(1)) {
+    Some(sym) => error!("  #{:<2} {:#018x} {}", idx, addr, sym),
+    None => error!("  #{:<2} {:#018x} ??", idx, addr),
+  }
+}
+
+/// Prints the frames starting at the instruction pointer and following
+/// the frame pointer chain starting at rbp
+pub fn print_backtrace_from(rip: Option<u64>, mut rbp: u64) {
+  if !has_symbols() {
+    error!("Backtrace (kernel has no symbol table, use look_adr.sh):");
+  } else {
+    error!("Backtrace:");
+  }
+  let mut idx = 0;
+  if let Some(rip) = rip {
+    // the instruction pointer is not a return address
+    print_frame(idx, rip + 1);
+    idx += 1;
+  }
+  while idx < MAX_FRAMES && rbp != 0 && readable(rbp) {
+    let (next, ret) = unsafe {
+      let frame = rbp as *const u64;
+      (core::ptr::read_volatile(frame), core::ptr::read_volatile(frame.add(1)))
+    };
+    if ret == 0 {
+      break;
+    }
+    print_frame(idx, ret);
+    idx += 1;
+    // stacks grow down, a frame further down the chain is a corrupt chain
+    if next <= rbp {
+      break;
+    }
+    rbp = next;
+  }
+}
+
+/// Prints the frames of the caller
+#[inline(never)]
+pub fn print_backtrace() {
+  let rbp: u64;
+  unsafe { asm!("mov $0, rbp" : "=r"(rbp) ::: "intel", "volatile") };
+  print_backtrace_from(None, rbp);
+}
@@ -16,44 +16,150 @@ fn init_physical_memory_offset(boot_info: &'static bootloader::BootInfo) {
 
 fn init_allocator() {
   let start = vmem::KHEAP_ALLOC;
-  let size = vmem::KHEAP_END - vmem:: KHEAP_START;
+  // the allocator must end below the upper heap guard page
+  let size = vmem::KHEAP_END - vmem::KHEAP_ALLOC;
   debug!("initializing allocator from {:#018x} with {} pages, {} MiB", start, size / 4096, size / 4096 / 1024 / 1024);
   let vaddr = VirtAddr::new(start.try_into().unwrap());
-  vmem::mapper::map_new(vaddr, vmem::mapper::MapType::Data);
+  use vmem::pagelist::FrameOwner;
+  vmem::mapper::map_new(vaddr, vmem::mapper::MapType::Data, FrameOwner::KernelHeap);
   let flags = vmem::mapper::get_flags(vaddr)
     .expect("unmapped allocator page start");
   use x86_64::structures::paging::PageTableFlags;
   assert!(flags.contains(PageTableFlags::WRITABLE));
-  unsafe { ALLOCATOR.lock().init(start, size) };
+  #[cfg(feature = "hardening")]
+  vmem::install_heap_guards();
+  unsafe { ALLOCATOR.init(start, size) };
   debug!("kernel allocator initialized");
 }
 
-use bootloader::bootinfo::MemoryRegion;
-const MAX_MEMORY_MAP_SIZE: usize = 64;
+use bootloader::bootinfo::{MemoryRegion, MemoryRegionType};
+use vmem::regions::{self, RegionKind};
 
-type OptionalRegions<'a> = [Option<&'a MemoryRegion>; MAX_MEMORY_MAP_SIZE];
+/// Maps a bootloader region type to the kind of reservation it represents,
+/// None is returned for memory that can be handed to the page pool
+fn reserved_kind(ty: MemoryRegionType) -> Option<RegionKind> {
+  use bootloader::bootinfo::MemoryRegionType::*;
+  match ty {
+    Usable => None,
+    InUse => Some(RegionKind::Firmware),
+    Reserved => Some(RegionKind::Firmware),
+    AcpiReclaimable => Some(RegionKind::AcpiReclaimable),
+    AcpiNvs => Some(RegionKind::AcpiNvs),
+    BadMemory => Some(RegionKind::BadMemory),
+    Kernel => Some(RegionKind::Kernel),
+    KernelStack => Some(RegionKind::KernelStack),
+    PageTable => Some(RegionKind::PageTable),
+    Bootloader => Some(RegionKind::Bootloader),
+    FrameZero => Some(RegionKind::FrameZero),
+    Empty => Some(RegionKind::Firmware),
+    BootInfo => Some(RegionKind::BootInfo),
+    Package => Some(RegionKind::BootInfo),
+    UnknownUefi(_) => Some(RegionKind::Firmware),
+    UnknownBios(_) => Some(RegionKind::Firmware),
+  }
+}
+
+fn region_bounds(entry: &MemoryRegion) -> (PhysAddr, PhysAddr) {
+  (PhysAddr::new(entry.range.start_addr()), PhysAddr::new(entry.range.end_addr()))
+}
 
-fn collect_usable_memory(boot_info: &'static bootloader::BootInfo) -> OptionalRegions<'static> {
-  let mmap = &boot_info.memory_map;
-  let mut usable_entries: OptionalRegions = [None; MAX_MEMORY_MAP_SIZE];
-  for (idx, entry) in mmap.iter().enumerate() {
-    use bootloader::bootinfo::MemoryRegionType::*;
-    match entry.region_type {
-      Usable => {
-        let range = entry.range;
-        let size = (range.end_addr() - range.start_addr()) / 4096;
-        debug!(
-          "MMAPE {:#04x} is usable memory... {} KiBytes, {} Pages",
-          idx,
-          size * 4,
-          size
-        );
-        usable_entries[idx] = Some(entry)
+/// Records every region of the memory map that is not usable memory
+fn import_reserved_memory(boot_info: &'static bootloader::BootInfo) {
+  for entry in boot_info.memory_map.iter() {
+    if let Some(kind) = reserved_kind(entry.region_type) {
+      let (start, end) = region_bounds(entry);
+      regions::reserve(start, end, kind);
+    }
+  }
+  // application processors start in real mode from this page
+  let trampoline = PhysAddr::new(crate::bindriver::cpu::smp::TRAMPOLINE_ADDR as u64);
+  regions::reserve(trampoline, trampoline + vmem::PAGE_SIZE, RegionKind::KernelFixed);
+  let (boot_start, boot_end) = pager().boot_pages();
+  let boot_pa = vmem::mapper::translate(VirtAddr::new(boot_start.as_u64()))
+    .expect("boot pages are not mapped");
+  regions::reserve(boot_pa, boot_pa + (boot_end - boot_start), RegionKind::BootPages);
+}
+
+/// Adds the physical range to the page pool, skipping over any part of it
+/// that has been reserved
+fn add_usable_memory(start: PhysAddr, end: PhysAddr) -> u64 {
+  let mut start = start.align_up(vmem::PAGE_SIZE as u64);
+  let end = end.align_down(vmem::PAGE_SIZE as u64);
+  let mut total_added_pages = 0;
+  while start < end {
+    match regions::first_overlap(start, end) {
+      Some(region) if region.start <= start => {
+        start = region.end.align_up(vmem::PAGE_SIZE as u64);
+      },
+      Some(region) => {
+        total_added_pages += add_memory_range(start, region.start.align_down(vmem::PAGE_SIZE as u64));
+        start = region.end.align_up(vmem::PAGE_SIZE as u64);
+      },
+      None => {
+        total_added_pages += add_memory_range(start, end);
+        start = end;
       },
-      _ => (),
     }
   }
-  return usable_entries;
+  total_added_pages
+}
+
+fn add_memory_range(start: PhysAddr, end: PhysAddr) -> u64 {
+  if start >= end {
+    return 0;
+  }
+  let size = (end - start) / vmem::PAGE_SIZE as u64;
+  use crate::vmem::pagelist::pagelist_ng::PageMap;
+  let layout = PageManager::pagemap_layout();
+  let mut rem_pages: u64 = size;
+  let mut total_added_pages: u64 = 0;
+  while rem_pages > 0 {
+    use core::alloc::GlobalAlloc;
+    let ptr = unsafe{ALLOCATOR.alloc_zeroed(layout)};
+    let ptr: *mut PageMap = ptr as *mut PageMap;
+    let added_pages = unsafe { match pager().add_memory(
+      ptr,
+      start + (total_added_pages as u64 * 4096),
+        rem_pages.try_into().unwrap())
+      {
+        Ok(v) => v,
+        Err(pae) => panic!("could not add memory: {:?}", pae),
+      }
+    };
+    rem_pages -= added_pages;
+    total_added_pages += added_pages;
+    assert!((rem_pages as u64) < size, "rem_pages has overflown");
+  }
+  total_added_pages
+}
+
+fn import_usable_memory(boot_info: &'static bootloader::BootInfo) {
+  for (idx, entry) in boot_info.memory_map.iter().enumerate() {
+    if entry.region_type != MemoryRegionType::Usable {
+      continue;
+    }
+    let (start, end) = region_bounds(entry);
+    let size = (end - start) / 4096;
+    let added = add_usable_memory(start, end);
+    debug!(
+      "MMAPE {:#04x} is usable memory... {} KiBytes, {} Pages, {} added",
+      idx,
+      size * 4,
+      size,
+      added
+    );
+  }
+}
+
+/// Returns all reserved regions of the given kind to the page pool.
+/// The caller must ensure nothing references the memory anymore.
+pub fn reclaim(kind: RegionKind) -> u64 {
+  let mut reclaimed = 0;
+  for region in regions::take_reserved(kind) {
+    reclaimed += add_usable_memory(region.start, region.end);
+  }
+  debug!("reclaimed {} pages of {:?} memory", reclaimed, kind);
+  reclaimed
 }
 
 pub fn init_memory(boot_info: &'static bootloader::BootInfo) {
@@ -61,36 +167,13 @@ pub fn init_memory(boot_info: &'static bootloader::BootInfo) {
   {
     init_physical_memory_offset(boot_info);
     init_allocator();
-    for (idx, entry) in collect_usable_memory(boot_info).iter().enumerate() {
-      match entry {
-        None => (),
-        Some(entry) => {
-          let range = entry.range;
-          let size = (range.end_addr() - range.start_addr()) / 4096;
-          use crate::vmem::pagelist::pagelist_ng::PageMap;
-          use alloc::alloc::{Global};
-          let layout = PageManager::pagemap_layout();
-          let mut rem_pages: u64 = size;
-          let mut total_added_pages: u64 = 0;
-          while rem_pages > 0 { 
-            use core::alloc::GlobalAlloc;
-            let ptr = unsafe{ALLOCATOR.alloc_zeroed(layout)};
-            let ptr: *mut PageMap = ptr as *mut PageMap;
-            let added_pages = unsafe { match pager().add_memory(
-              ptr,
-              PhysAddr::new(range.start_addr() + (total_added_pages as u64 * 4096)),
-                rem_pages.try_into().unwrap()) 
-              {
-                Ok(v) => v,
-                Err(pae) => panic!("could not add memory: {:?}", pae),
-              }
-            };
-            rem_pages -= added_pages;
-            total_added_pages += added_pages;
-            assert!((rem_pages as u64) < size, "rem_pages has overflown");
-          }
-        }
-      }
-    }
+    import_reserved_memory(boot_info);
+    import_usable_memory(boot_info);
+    // the kernel runs on its own GDT, IDT and page pool at this point,
+    // nothing the bootloader left behind is referenced anymore
+    reclaim(RegionKind::Bootloader);
+    regions::print_reserved();
+    let total = pager().total_memory();
+    pager().set_watermarks(total / 16, total / 64);
   }
-}
\ No newline at end of file
+}
@@ -1,11 +1,4 @@
-
-use linked_list_allocator::Heap;
-use spin::Mutex;
-use alloc::alloc::{GlobalAlloc, AllocErr, Layout};
-use core::ops::Deref;
-use core::ptr::NonNull;
-
-use linked_list_allocator::LockedHeap;
+use crate::common::slabmalloc::SlabAllocator;
 
 #[global_allocator]
-pub static ALLOCATOR: LockedHeap = LockedHeap::empty();
\ No newline at end of file
+pub static ALLOCATOR: SlabAllocator = SlabAllocator::new();
@@ -1,41 +1,122 @@
 use alloc::rc::Rc;
 use core::cell::RefCell;
-use core::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
-use crate::process_manager::{Memory, MemoryUser, MemoryUserRef, TaskHandle};
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU8, Ordering};
+#[cfg(test)]
+use core::sync::atomic::AtomicU64;
+use crate::process_manager::{Memory, MemoryUser, MemoryUserRef, TaskException, TaskHandle};
 use crate::PhysAddr;
 use atomic::Atomic;
 use crate::common::*;
 use core::ptr::NonNull;
 
-pub struct KernelInfo {
+/// Number of CPUs the kernel keeps per-CPU data for
+pub const MAX_CPUS: usize = 8;
+
+/// Kernel information local to one CPU
+pub struct CpuLocal {
+  online: AtomicBool,
+  apic_id: AtomicU8,
   switching_tasks_int: AtomicBool,
-  mapping_task_image_int: AtomicBool,
   current_task_handle_int: Atomic<TaskHandle>,
   current_code_memory_ref_int: AtomicPtr<Rc<RefCell<MemoryUser>>>,
   current_data_memory_ref_int: AtomicPtr<Rc<RefCell<MemoryUser>>>,
   current_stack_memory_ref_int: AtomicPtr<Rc<RefCell<MemoryUser>>>,
-  zero_page_addr: OptAPtr,
-  physical_memory_offset: Atomic<VirtAddr>,
+  // test only, a production kernel never makes read-only pages writable
+  #[cfg(test)]
+  expected_fault_addr: AtomicU64,
+  // task the CPU should switch to next, 0 if there is none
+  mailbox: Atomic<TaskHandle>,
+  // exception raised by task code, waiting for the exception trampoline
+  exception: Atomic<Option<TaskException>>,
 }
-impl KernelInfo {
+
+impl CpuLocal {
   const fn new() -> Self {
-    KernelInfo {
+    CpuLocal {
+      online: AtomicBool::new(false),
+      apic_id: AtomicU8::new(0),
       switching_tasks_int: AtomicBool::new(false),
-      mapping_task_image_int: AtomicBool::new(false),
       current_task_handle_int: Atomic::new(TaskHandle::from_c(0)),
       current_code_memory_ref_int: AtomicPtr::new(0 as *mut Rc<RefCell<MemoryUser>>),
       current_data_memory_ref_int: AtomicPtr::new(0 as *mut Rc<RefCell<MemoryUser>>),
       current_stack_memory_ref_int: AtomicPtr::new(0 as *mut Rc<RefCell<MemoryUser>>),
+      #[cfg(test)]
+      expected_fault_addr: AtomicU64::new(0),
+      mailbox: Atomic::new(TaskHandle::from_c(0)),
+      exception: Atomic::new(None),
+    }
+  }
+  pub fn is_online(&self) -> bool {
+    self.online.load(Ordering::SeqCst)
+  }
+  pub fn apic_id(&self) -> u8 {
+    self.apic_id.load(Ordering::SeqCst)
+  }
+  pub fn set_online(&self, apic_id: u8) {
+    self.apic_id.store(apic_id, Ordering::SeqCst);
+    self.online.store(true, Ordering::SeqCst);
+  }
+  /// Asks the CPU to switch to the given task
+  pub fn post_task(&self, th: TaskHandle) -> Result<TaskHandle, TaskHandle> {
+    self.mailbox.compare_exchange(TaskHandle::from_c(0), th, Ordering::SeqCst, Ordering::SeqCst)
+  }
+  pub fn take_task(&self) -> Option<TaskHandle> {
+    let th = self.mailbox.swap(TaskHandle::from_c(0), Ordering::SeqCst);
+    if th.into_c() == 0 { None } else { Some(th) }
+  }
+  pub fn set_exception(&self, exception: TaskException) {
+    self.exception.store(Some(exception), Ordering::SeqCst)
+  }
+  pub fn take_exception(&self) -> Option<TaskException> {
+    self.exception.swap(None, Ordering::SeqCst)
+  }
+  pub fn current_task(&self) -> TaskHandle {
+    self.current_task_handle_int.load(Ordering::SeqCst)
+  }
+  pub fn switching_tasks(&self) -> bool {
+    self.switching_tasks_int.load(Ordering::SeqCst)
+  }
+  /// Page announced by expect_fault, 0 if none
+  #[cfg(test)]
+  pub fn expected_fault(&self) -> u64 {
+    self.expected_fault_addr.load(Ordering::SeqCst)
+  }
+}
+
+pub struct KernelInfo {
+  mapping_task_image_int: AtomicBool,
+  zero_page_addr: OptAPtr,
+  physical_memory_offset: Atomic<VirtAddr>,
+  cpus: [CpuLocal; MAX_CPUS],
+}
+impl KernelInfo {
+  const fn new() -> Self {
+    KernelInfo {
+      mapping_task_image_int: AtomicBool::new(false),
       zero_page_addr: OptAPtr::zero(),
       physical_memory_offset: Atomic::new(VirtAddr::zero()),
+      cpus: [
+        CpuLocal::new(), CpuLocal::new(), CpuLocal::new(), CpuLocal::new(),
+        CpuLocal::new(), CpuLocal::new(), CpuLocal::new(), CpuLocal::new(),
+      ],
     }
   }
+  /// Returns the data of the calling CPU
+  pub fn local(&self) -> &CpuLocal {
+    &self.cpus[crate::bindriver::cpu::smp::cpu_index()]
+  }
+  pub fn cpu(&self, index: usize) -> Option<&CpuLocal> {
+    self.cpus.get(index)
+  }
   pub fn get_pmo(&self) -> VirtAddr {
     self.physical_memory_offset.load(Ordering::SeqCst)
   }
   pub fn set_pmo(&mut self, pmo: VirtAddr) -> VirtAddr {
     self.physical_memory_offset.swap(pmo, Ordering::SeqCst)
   }
+  pub fn is_mapping_task_image(&self) -> bool {
+    self.mapping_task_image_int.load(Ordering::SeqCst)
+  }
   pub fn mapping_task_image(&mut self, v: Option<bool>) -> bool {
     if v.is_none() {
       self.mapping_task_image_int.load(Ordering::SeqCst)
@@ -50,59 +131,118 @@ impl KernelInfo {
     let v = self.zero_page_addr.get();
     if v.is_none() {
       info!("kernel has no zero page, allocating one");
-      let page = alloc_page().expect("must have zero page in kernel");
+      use crate::vmem::pagelist::FrameOwner;
+      let page = alloc_page_owned(FrameOwner::ZeroPage).expect("must have zero page in kernel");
+      crate::vmem::zero_frame(page);
       self.zero_page_addr.set(NonNull::new(page.as_u64() as *mut u8).unwrap());
       page
     } else {
       PhysAddr::new(v.unwrap().as_ptr() as u64)
     }
   }
+  /// Announces a protection violation on the page, the page fault handler
+  /// makes the page writable once instead of panicking
+  #[cfg(test)]
+  pub fn expect_fault(&self, addr: VirtAddr) {
+    let page = addr.align_down(crate::vmem::PAGE_SIZE as u64);
+    self.local().expected_fault_addr.store(page.as_u64(), Ordering::SeqCst);
+  }
+  #[cfg(test)]
+  pub fn take_expected_fault(&self, addr: VirtAddr) -> bool {
+    let page = addr.align_down(crate::vmem::PAGE_SIZE as u64).as_u64();
+    page != 0 && self.local().expected_fault_addr.compare_and_swap(page, 0, Ordering::SeqCst) == page
+  }
+  #[cfg(test)]
+  pub fn fault_expected(&self) -> bool {
+    self.local().expected_fault_addr.load(Ordering::SeqCst) != 0
+  }
   pub fn get_switching_tasks(&self) -> bool {
-    self.switching_tasks_int.load(Ordering::SeqCst)
+    self.local().switching_tasks_int.load(Ordering::SeqCst)
   }
   pub fn set_switching_tasks(&self, cur: bool, new: bool) -> bool {
-    self.switching_tasks_int.compare_and_swap(cur, new, Ordering::SeqCst)
+    self.local().switching_tasks_int.compare_and_swap(cur, new, Ordering::SeqCst)
   }
   pub fn get_current_task(&self) -> TaskHandle {
-    self.current_task_handle_int.load(Ordering::SeqCst)
+    self.local().current_task_handle_int.load(Ordering::SeqCst)
   }
   pub fn swap_current_task(&self, c: TaskHandle, v: TaskHandle) -> Result<TaskHandle, TaskHandle> {
     trace!("swapping current task to {}", v);
-    self.current_task_handle_int.
+    self.local().current_task_handle_int.
       compare_exchange(c, v, Ordering::SeqCst, Ordering::SeqCst)
   }
+  /// Makes the task the one running on the calling CPU
+  pub fn set_current_task(&self, th: TaskHandle) {
+    trace!("setting current task to {}", th);
+    self.local().current_task_handle_int.store(th, Ordering::SeqCst)
+  }
+  /// Returns true if the task is running on any CPU
+  pub fn is_current_task(&self, th: TaskHandle) -> bool {
+    self.cpus.iter().any(|local| local.current_task() == th)
+  }
+  /// Makes the memory of a task the active memory of the calling CPU, the
+  /// page fault handler and the memory kcalls act on it. Kernel tasks have
+  /// no task memory, their slots are cleared
+  pub fn set_task_memory(&self, code: &Memory, data: &Memory, stack: &Memory) {
+    let local = self.local();
+    local.current_code_memory_ref_int.store(memory_ref(code), Ordering::SeqCst);
+    local.current_data_memory_ref_int.store(memory_ref(data), Ordering::SeqCst);
+    local.current_stack_memory_ref_int.store(memory_ref(stack), Ordering::SeqCst);
+  }
   pub fn add_code_page(&self, p: PhysAddr) {
     trace!("adding {:?} to active code memory", p);
-    let ptr = self.current_code_memory_ref_int.load(Ordering::SeqCst);
+    let ptr = self.local().current_code_memory_ref_int.load(Ordering::SeqCst);
     let mur = MemoryUserRef::from(ptr);
     mur.add_page(p);
   }
   pub fn add_data_page(&self, p: PhysAddr) {
     trace!("adding {:?} to active data memory", p);
-    let ptr = self.current_data_memory_ref_int.load(Ordering::SeqCst);
+    let ptr = self.local().current_data_memory_ref_int.load(Ordering::SeqCst);
     let mur = MemoryUserRef::from(ptr);
     trace!("current data memory size: {}", self.get_data_memory_ref_size());
     mur.add_page(p);
     trace!("new data memory size: {}", self.get_data_memory_ref_size());
   }
+  /// Replaces a zero page of the active data memory with a fresh page,
+  /// the index is counted in pages from the start of the data memory
+  pub fn promote_data_page(&self, index: usize, p: PhysAddr) -> Option<PhysAddr> {
+    trace!("promoting data page {} to {:?}", index, p);
+    let ptr = self.local().current_data_memory_ref_int.load(Ordering::SeqCst);
+    let mur = MemoryUserRef::from(ptr);
+    mur.promote_page(index, p)
+  }
+  /// Replaces a page of the active data memory with the zero page and returns
+  /// the replaced page, the caller is responsible for releasing it
+  pub fn demote_data_page(&self, index: usize) -> Option<PhysAddr> {
+    trace!("demoting data page {}", index);
+    let ptr = self.local().current_data_memory_ref_int.load(Ordering::SeqCst);
+    let mur = MemoryUserRef::from(ptr);
+    mur.demote_page(index)
+  }
+  /// Drops trailing zero pages of the active data memory down to keep pages,
+  /// the pages of the task image are never dropped
+  pub fn truncate_data_zero_pages(&self, keep: usize) -> usize {
+    let ptr = self.local().current_data_memory_ref_int.load(Ordering::SeqCst);
+    let mur = MemoryUserRef::from(ptr);
+    mur.truncate_zero_pages(keep)
+  }
   pub fn add_stack_page(&self, p: PhysAddr) {
     trace!("adding {:?} to active stack memory", p);
-    let ptr = self.current_stack_memory_ref_int.load(Ordering::SeqCst);
+    let ptr = self.local().current_stack_memory_ref_int.load(Ordering::SeqCst);
     let mur = MemoryUserRef::from(ptr);
     mur.add_page(p);
   }
   pub fn get_code_memory_ref_size(&self) -> usize {
-    let ptr = self.current_code_memory_ref_int.load(Ordering::SeqCst);
+    let ptr = self.local().current_code_memory_ref_int.load(Ordering::SeqCst);
     let mur = MemoryUserRef::from(ptr);
     mur.page_count()
   }
   pub fn get_data_memory_ref_size(&self) -> usize {
-    let ptr = self.current_data_memory_ref_int.load(Ordering::SeqCst);
+    let ptr = self.local().current_data_memory_ref_int.load(Ordering::SeqCst);
     let mur = MemoryUserRef::from(ptr);
     mur.page_count()
   }
   pub fn get_stack_memory_ref_size(&self) -> usize {
-    let ptr = self.current_stack_memory_ref_int.load(Ordering::SeqCst);
+    let ptr = self.local().current_stack_memory_ref_int.load(Ordering::SeqCst);
     let mur = MemoryUserRef::from(ptr);
     mur.page_count()
   }
@@ -110,17 +250,17 @@ impl KernelInfo {
     trace!("setting new active memory: {:?}", v);
     match v {
       Memory::Code(s) => Memory::Code(MemoryUserRef::from(
-        self
+        self.local()
           .current_code_memory_ref_int
           .swap(s.clone().into(), Ordering::SeqCst),
       )),
       Memory::User(s) => Memory::User(MemoryUserRef::from(
-        self
+        self.local()
           .current_data_memory_ref_int
           .swap(s.clone().into(), Ordering::SeqCst),
       )),
       Memory::Stack(s) => Memory::Stack(MemoryUserRef::from(
-        self
+        self.local()
           .current_stack_memory_ref_int
           .swap(s.clone().into(), Ordering::SeqCst),
       )),
@@ -128,4 +268,11 @@ impl KernelInfo {
     }
   }
 }
+fn memory_ref(memory: &Memory) -> *mut Rc<RefCell<MemoryUser>> {
+  match memory {
+    Memory::Code(s) | Memory::User(s) | Memory::Stack(s) => (*s).into(),
+    _ => 0 as *mut Rc<RefCell<MemoryUser>>,
+  }
+}
+
 pub static KERNEL_INFO: KPut<KernelInfo> = KPut::new(KernelInfo::new());
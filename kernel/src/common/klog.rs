@@ -0,0 +1,201 @@
+use core::fmt::Write;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use crate::process_manager::TaskHandle;
+
+// The kernel log keeps the last LOG_SLOTS records next to the serial
+// output. Records are fixed size and addressed by their sequence number,
+// the slot of a record is its sequence number modulo LOG_SLOTS. Readers
+// keep a cursor holding the sequence number of the next record they want.
+
+pub const LOG_SLOTS: usize = 256;
+pub const LOG_MODULE_LEN: usize = 48;
+pub const LOG_MESSAGE_LEN: usize = 192;
+
+/// A log record as returned by bos_read_log, longer modules and messages are truncated
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct LogRecord {
+  pub seq: u64,
+  /// TSC at the time of logging
+  pub timestamp: u64,
+  /// task the scheduler runs on the logging CPU, 0 if there is none
+  pub task: u128,
+  /// 1 = error, 2 = warn, 3 = info, 4 = debug, 5 = trace
+  pub level: u8,
+  pub module_len: u8,
+  pub message_len: u16,
+  pub module: [u8; LOG_MODULE_LEN],
+  pub message: [u8; LOG_MESSAGE_LEN],
+}
+
+impl LogRecord {
+  pub const fn empty() -> LogRecord {
+    LogRecord {
+      seq: 0,
+      timestamp: 0,
+      task: 0,
+      level: 0,
+      module_len: 0,
+      message_len: 0,
+      module: [0; LOG_MODULE_LEN],
+      message: [0; LOG_MESSAGE_LEN],
+    }
+  }
+  pub fn level(&self) -> Option<log::Level> {
+    use log::Level::*;
+    [Error, Warn, Info, Debug, Trace].get((self.level as usize).checked_sub(1)?).cloned()
+  }
+  pub fn module(&self) -> &str {
+    truncated_str(&self.module[..self.module_len as usize])
+  }
+  pub fn message(&self) -> &str {
+    truncated_str(&self.message[..self.message_len as usize])
+  }
+}
+
+impl core::fmt::Debug for LogRecord {
+  fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+    f.debug_struct("LogRecord")
+      .field("seq", &self.seq)
+      .field("timestamp", &self.timestamp)
+      .field("task", &TaskHandle::from_c(self.task))
+      .field("level", &self.level())
+      .field("module", &self.module())
+      .field("message", &self.message())
+      .finish()
+  }
+}
+
+// truncating may split a character, drop the partial character
+fn truncated_str(bytes: &[u8]) -> &str {
+  match core::str::from_utf8(bytes) {
+    Ok(s) => s,
+    Err(e) => unsafe { core::str::from_utf8_unchecked(&bytes[..e.valid_up_to()]) },
+  }
+}
+
+/// Formats into a fixed buffer, output beyond the buffer is dropped
+struct Truncating<'a> {
+  buf: &'a mut [u8],
+  len: usize,
+}
+
+impl<'a> Write for Truncating<'a> {
+  fn write_str(&mut self, s: &str) -> core::fmt::Result {
+    let n = core::cmp::min(s.len(), self.buf.len() - self.len);
+    self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+    self.len += n;
+    Ok(())
+  }
+}
+
+struct Ring {
+  records: [LogRecord; LOG_SLOTS],
+  // sequence number of the next record
+  next: u64,
+}
+
+static RING: Mutex<Ring> = Mutex::new(Ring {
+  records: [LogRecord::empty(); LOG_SLOTS],
+  next: 0,
+});
+
+// records lost because the ring was locked, e.g. logging from an
+// interrupt handler while the interrupted code was logging
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Appends a record, called by the logger for every record it prints
+pub fn record(level: log::Level, module: &str, task: TaskHandle, args: core::fmt::Arguments) {
+  let mut rec = LogRecord::empty();
+  rec.timestamp = crate::bindriver::cpu::tsc::read();
+  rec.task = task.into_c();
+  rec.level = level as usize as u8;
+  let mut out = Truncating { buf: &mut rec.module, len: 0 };
+  out.write_str(module).ok();
+  rec.module_len = out.len as u8;
+  let mut out = Truncating { buf: &mut rec.message, len: 0 };
+  out.write_fmt(args).ok();
+  rec.message_len = out.len as u16;
+  match RING.try_lock() {
+    Some(mut ring) => {
+      rec.seq = ring.next;
+      let slot = (ring.next % LOG_SLOTS as u64) as usize;
+      ring.records[slot] = rec;
+      ring.next += 1;
+    },
+    None => { DROPPED.fetch_add(1, Ordering::Relaxed); },
+  }
+}
+
+/// Returns the number of records lost to lock contention
+pub fn dropped() -> u64 {
+  DROPPED.load(Ordering::Relaxed)
+}
+
+/// Copies the records starting at the cursor into the buffer and advances
+/// the cursor past them. A cursor pointing to overwritten records is moved
+/// to the oldest record still kept, gaps are visible in the sequence numbers.
+/// Returns the number of records copied
+pub fn read(buf: &mut [LogRecord], cursor: &mut u64) -> usize {
+  let ring = RING.lock();
+  let oldest = ring.next.saturating_sub(LOG_SLOTS as u64);
+  if *cursor < oldest {
+    *cursor = oldest;
+  }
+  let mut copied = 0;
+  for slot in buf.iter_mut() {
+    if *cursor >= ring.next {
+      break;
+    }
+    *slot = ring.records[(*cursor % LOG_SLOTS as u64) as usize];
+    *cursor += 1;
+    copied += 1;
+  }
+  copied
+}
+
+/// Returns the sequence number of the next record
+pub fn head() -> u64 {
+  RING.lock().next
+}
+
+// messages per second a task may log through the bos_log_* kcalls
+pub const TASK_LOG_RATE: u64 = 20;
+// messages a task may log at once after being quiet
+pub const TASK_LOG_BURST: u64 = 100;
+
+/// Token bucket limiting the messages a task logs
+#[derive(Debug, Clone)]
+pub struct RateLimit {
+  tokens: u64,
+  refilled_ms: u64,
+  suppressed: u64,
+}
+
+impl RateLimit {
+  pub fn new() -> RateLimit {
+    RateLimit {
+      tokens: TASK_LOG_BURST,
+      refilled_ms: crate::bindriver::cpu::tsc::now_ms(),
+      suppressed: 0,
+    }
+  }
+  /// Takes a token for a message at the given time. Returns the number of
+  /// messages suppressed since the last admitted one or None if the message
+  /// exceeds the limit
+  pub fn admit(&mut self, now_ms: u64) -> Option<u64> {
+    let refill = now_ms.saturating_sub(self.refilled_ms) * TASK_LOG_RATE / 1000;
+    if refill > 0 {
+      self.tokens = core::cmp::min(TASK_LOG_BURST, self.tokens + refill);
+      // keep the time not yet turned into tokens
+      self.refilled_ms += refill * 1000 / TASK_LOG_RATE;
+    }
+    if self.tokens == 0 {
+      self.suppressed += 1;
+      return None;
+    }
+    self.tokens -= 1;
+    Some(core::mem::replace(&mut self.suppressed, 0))
+  }
+}
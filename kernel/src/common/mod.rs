@@ -1,14 +1,18 @@
-use crate::process_manager::{Userspace, Task, TaskHandle};
+use crate::process_manager::{Userspace, Task, TaskHandle, CapError, Rights};
 use crate::vmem::PageManager;
-use core::cell::{Ref, RefMut};
+use spin::{MutexGuard, RwLockReadGuard};
 use crate::vmem::pagelist::{PagePoolAllocationError, PagePoolReleaseError};
+use crate::vmem::pagelist::{PagePoolReferenceError, FrameOwner};
 
 #[macro_use]
 mod macros;
 mod kinfo;
+pub mod backtrace;
+pub mod klog;
 mod katomic;
 mod kput;
 mod kheap;
+pub mod slabmalloc;
 pub mod kabm;
 pub mod init;
 
@@ -26,11 +30,31 @@ pub use kput::*;
 
 #[allow(dead_code)]
 
-pub fn userspace<'a>() -> Ref<'a, Userspace> {
-  use crate::USERSPACE;
-  unsafe {
-    (*(USERSPACE.as_mut().expect("userspace required"))).borrow()
+/// Shared borrow of the userspace, held by every CPU that runs tasks
+pub struct UserspaceRef<'a>(RwLockReadGuard<'a, Option<Userspace>>);
+
+impl<'a> core::ops::Deref for UserspaceRef<'a> {
+  type Target = Userspace;
+
+  fn deref(&self) -> &Userspace {
+    self.0.as_ref().expect("userspace required")
+  }
+}
+
+pub fn userspace<'a>() -> UserspaceRef<'a> {
+  let us = crate::USERSPACE.read();
+  assert!(us.is_some(), "userspace required");
+  UserspaceRef(us)
+}
+
+/// Returns the userspace if it is set up and not locked for writing,
+/// never waits for the lock
+pub fn try_userspace<'a>() -> Option<UserspaceRef<'a>> {
+  let us = crate::USERSPACE.try_read()?;
+  if us.is_none() {
+    return None;
   }
+  Some(UserspaceRef(us))
 }
 
 pub fn kinfo<'a>() -> &'a KernelInfo {
@@ -38,41 +62,50 @@ pub fn kinfo<'a>() -> &'a KernelInfo {
 }
 
 pub fn kinfo_mut<'a>() -> KPutGuard<'a, KernelInfo> {
-  KERNEL_INFO.try_write().expect("no lock on KINFO")
+  KERNEL_INFO.write()
 }
 
-pub fn with_current_task<T>(run: impl Fn(Option<Ref<Task>>) -> T) -> Result<T, ()> {
+pub fn with_current_task<T>(run: impl Fn(Option<MutexGuard<Task>>) -> T) -> Result<T, ()> {
   let handle = current_taskhandle()?;
   with_task(handle, run)
 }
 
-pub fn with_task<T>(th: TaskHandle, run: impl Fn(Option<Ref<Task>>) -> T) -> Result<T, ()> {
+pub fn with_task<T>(th: TaskHandle, run: impl Fn(Option<MutexGuard<Task>>) -> T) -> Result<T, ()> {
   userspace().in_scheduler(|sched| {
     let task = (sched).resolve_th(th);
     match task {
       None => run(None),
-      Some(t) => run(Some(t.borrow()))
+      Some(t) => run(Some(t.lock()))
     }
   })
 }
 
-pub fn with_current_task_mut<T>(run: impl FnMut(Option<RefMut<Task>>) -> T) -> Result<T, ()> {
+pub fn with_current_task_mut<T>(run: impl FnMut(Option<MutexGuard<Task>>) -> T) -> Result<T, ()> {
   let handle = current_taskhandle()?;
   with_task_mut(handle, run)
 }
 
-pub fn with_task_mut<T>(th: TaskHandle, mut run: impl FnMut(Option<RefMut<Task>>) -> T) -> Result<T, ()> {
-  userspace().in_scheduler_mut(|sched| {
-    let task = (sched).resolve_th(th);
+// tasks are locked individually, the scheduler is only read
+pub fn with_task_mut<T>(th: TaskHandle, mut run: impl FnMut(Option<MutexGuard<Task>>) -> T) -> Result<T, ()> {
+  let task = userspace().in_scheduler(|sched| sched.resolve_th(th))?;
+  match task {
+    None => Ok(run(None)),
+    Some(t) => Ok(run(Some(t.lock()))),
+  }
+}
+
+/// Checks that the running task holds the rights on the target task
+pub fn check_rights(target: TaskHandle, needed: Rights) -> Result<(), CapError> {
+  with_current_task(|task| {
     match task {
-      None => run(None),
-      Some(t) => run(Some(t.borrow_mut()))
+      None => Err(CapError::NoCapability(target)),
+      Some(task) => task.caps().check(target, needed),
     }
-  })
+  }).unwrap_or(Err(CapError::NoCapability(target)))
 }
 
 pub fn current_taskhandle() -> Result<TaskHandle, ()> {
-  userspace().in_scheduler(|sched| {
+  try_userspace().ok_or(())?.in_scheduler(|sched| {
     sched.current_task().clone()
   })
 }
@@ -85,6 +118,14 @@ pub fn alloc_page() -> Result<PhysAddr, PagePoolAllocationError> {
   unsafe { pager().alloc_page() }
 }
 
+pub fn alloc_page_owned(owner: FrameOwner) -> Result<PhysAddr, PagePoolAllocationError> {
+  unsafe { pager().alloc_page_owned(owner) }
+}
+
+pub fn ref_page(pa: PhysAddr) -> Result<u16, PagePoolReferenceError> {
+  unsafe { pager().ref_page(pa) }
+}
+
 pub fn release_page(pa: PhysAddr) -> Result<(), PagePoolReleaseError>{
   unsafe { pager().free_page(pa) }
 }
@@ -94,7 +135,17 @@ pub fn release_page(pa: PhysAddr) -> Result<(), PagePoolReleaseError>{
 pub extern "C" fn yield_to(t: u128) {
   let th = TaskHandle::from_c(t);
   debug!("Yielding to task {}", th);
+  crate::process_manager::dispatch_memory_pressure();
   let cur = userspace().in_scheduler_spin(|sched| sched.current_task());
   userspace().in_scheduler_mut_spin(|mut sched| sched.set_current_task(th));
-  userspace().in_scheduler_spin(|sched| sched.yield_to(cur, Some(th)));
+  // the scheduler must not stay locked while the next task runs
+  let tasks = userspace().in_scheduler_spin(|sched| sched.prepare_yield(cur, Some(th)));
+  match tasks {
+    Some((current, next)) => unsafe { crate::process_manager::switch_tasks(&current, &next) },
+    // nothing to switch or the task can not run, the caller keeps running
+    None => userspace().in_scheduler_mut_spin(|mut sched| sched.set_current_task(cur)),
+  }
+  // the task has been switched back in, deliver what queued up meanwhile
+  userspace().in_scheduler_spin(|sched| sched.reap_destroyed());
+  crate::process_manager::dispatch_signals(cur);
 }
\ No newline at end of file
@@ -0,0 +1,194 @@
+use alloc::alloc::{GlobalAlloc, Layout};
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use linked_list_allocator::LockedHeap;
+use spin::Mutex;
+use crate::vmem::PAGE_SIZE;
+
+/// Object sizes served from slabs, larger allocations go to the fallback heap
+pub const SIZE_CLASSES: [usize; 10] = [8, 16, 32, 64, 128, 256, 512, 1024, 2048, 4096];
+const NUM_CLASSES: usize = 10;
+/// Slabs are carved from the fallback heap and are never returned to it
+const SLAB_SIZE: usize = 4 * PAGE_SIZE;
+
+struct FreeObject {
+  next: Option<NonNull<FreeObject>>,
+}
+
+struct SizeClass {
+  size: usize,
+  free: Option<NonNull<FreeObject>>,
+  stats: SlabStats,
+}
+
+unsafe impl Send for SizeClass {}
+
+impl SizeClass {
+  const fn new(size: usize) -> SizeClass {
+    SizeClass {
+      size,
+      free: None,
+      stats: SlabStats { size, slabs: 0, in_use: 0, allocs: 0, frees: 0 },
+    }
+  }
+
+  unsafe fn refill(&mut self, fallback: &LockedHeap) -> bool {
+    let layout = Layout::from_size_align_unchecked(SLAB_SIZE, PAGE_SIZE);
+    let slab = fallback.alloc(layout);
+    if slab.is_null() {
+      return false;
+    }
+    trace!("new slab for {} byte objects @ {:#018x}", self.size, slab as usize);
+    for idx in (0..(SLAB_SIZE / self.size)).rev() {
+      let obj = slab.add(idx * self.size) as *mut FreeObject;
+      obj.write(FreeObject { next: self.free });
+      self.free = NonNull::new(obj);
+    }
+    self.stats.slabs += 1;
+    true
+  }
+
+  unsafe fn alloc(&mut self, fallback: &LockedHeap) -> *mut u8 {
+    if self.free.is_none() && !self.refill(fallback) {
+      return core::ptr::null_mut();
+    }
+    let obj = self.free.expect("slab refill left free list empty");
+    self.free = obj.as_ref().next;
+    self.stats.in_use += 1;
+    self.stats.allocs += 1;
+    obj.as_ptr() as *mut u8
+  }
+
+  unsafe fn dealloc(&mut self, ptr: *mut u8) {
+    let obj = ptr as *mut FreeObject;
+    obj.write(FreeObject { next: self.free });
+    self.free = NonNull::new(obj);
+    self.stats.in_use -= 1;
+    self.stats.frees += 1;
+  }
+}
+
+/// Allocation statistics of a single size class
+#[derive(Debug, Clone, Copy)]
+pub struct SlabStats {
+  pub size: usize,
+  pub slabs: usize,
+  pub in_use: usize,
+  pub allocs: u64,
+  pub frees: u64,
+}
+
+/// Size class allocator for small kernel objects, falls back to the
+/// linked list heap for large allocations and for the slabs themselves
+pub struct SlabAllocator {
+  classes: [Mutex<SizeClass>; NUM_CLASSES],
+  fallback: LockedHeap,
+  large_in_use: AtomicUsize,
+  large_allocs: AtomicU64,
+  large_frees: AtomicU64,
+}
+
+impl SlabAllocator {
+  pub const fn new() -> SlabAllocator {
+    SlabAllocator {
+      classes: [
+        Mutex::new(SizeClass::new(SIZE_CLASSES[0])),
+        Mutex::new(SizeClass::new(SIZE_CLASSES[1])),
+        Mutex::new(SizeClass::new(SIZE_CLASSES[2])),
+        Mutex::new(SizeClass::new(SIZE_CLASSES[3])),
+        Mutex::new(SizeClass::new(SIZE_CLASSES[4])),
+        Mutex::new(SizeClass::new(SIZE_CLASSES[5])),
+        Mutex::new(SizeClass::new(SIZE_CLASSES[6])),
+        Mutex::new(SizeClass::new(SIZE_CLASSES[7])),
+        Mutex::new(SizeClass::new(SIZE_CLASSES[8])),
+        Mutex::new(SizeClass::new(SIZE_CLASSES[9])),
+      ],
+      fallback: LockedHeap::empty(),
+      large_in_use: AtomicUsize::new(0),
+      large_allocs: AtomicU64::new(0),
+      large_frees: AtomicU64::new(0),
+    }
+  }
+
+  /// Initializes the fallback heap, must be called once before allocating
+  pub unsafe fn init(&self, start: usize, size: usize) {
+    self.fallback.lock().init(start, size)
+  }
+
+  fn class_index(layout: &Layout) -> Option<usize> {
+    let size = core::cmp::max(layout.size(), layout.align());
+    SIZE_CLASSES.iter().position(|class| *class >= size)
+  }
+
+  pub fn stats(&self) -> [SlabStats; NUM_CLASSES] {
+    let mut stats = [SizeClass::new(0).stats; NUM_CLASSES];
+    for (idx, class) in self.classes.iter().enumerate() {
+      stats[idx] = class.lock().stats;
+    }
+    stats
+  }
+
+  /// Returns the bytes in use, allocations and frees served by the fallback heap
+  pub fn large_stats(&self) -> (usize, u64, u64) {
+    (
+      self.large_in_use.load(Ordering::Relaxed),
+      self.large_allocs.load(Ordering::Relaxed),
+      self.large_frees.load(Ordering::Relaxed),
+    )
+  }
+
+  pub fn print_stats(&self) {
+    for stats in self.stats().iter() {
+      trace!("Slab {:>4} bytes: {} slabs, {} in use, {} allocs, {} frees",
+        stats.size, stats.slabs, stats.in_use, stats.allocs, stats.frees);
+    }
+    let (in_use, allocs, frees) = self.large_stats();
+    trace!("Large allocations: {} KiB in use, {} allocs, {} frees",
+      in_use / 1024, allocs, frees);
+  }
+}
+
+unsafe impl GlobalAlloc for SlabAllocator {
+  unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    match Self::class_index(&layout) {
+      Some(idx) => self.classes[idx].lock().alloc(&self.fallback),
+      None => {
+        let ptr = self.fallback.alloc(layout);
+        if !ptr.is_null() {
+          self.large_in_use.fetch_add(layout.size(), Ordering::Relaxed);
+          self.large_allocs.fetch_add(1, Ordering::Relaxed);
+        }
+        ptr
+      }
+    }
+  }
+
+  unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    match Self::class_index(&layout) {
+      Some(idx) => self.classes[idx].lock().dealloc(ptr),
+      None => {
+        self.large_in_use.fetch_sub(layout.size(), Ordering::Relaxed);
+        self.large_frees.fetch_add(1, Ordering::Relaxed);
+        self.fallback.dealloc(ptr, layout)
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  #[test_case]
+  fn check_slab_alloc_dealloc() {
+    use crate::common::ALLOCATOR;
+    let layout = Layout::new::<u64>();
+    let before = ALLOCATOR.stats()[0];
+    let ptr = unsafe { ALLOCATOR.alloc(layout) };
+    assert!(!ptr.is_null(), "slab allocation failed");
+    assert_eq!(ptr as usize % layout.align(), 0, "slab allocation misaligned");
+    assert_eq!(ALLOCATOR.stats()[0].in_use, before.in_use + 1);
+    unsafe { ALLOCATOR.dealloc(ptr, layout) };
+    assert_eq!(ALLOCATOR.stats()[0].in_use, before.in_use);
+    assert_eq!(ALLOCATOR.stats()[0].frees, before.frees + 1);
+  }
+}
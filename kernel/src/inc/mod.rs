@@ -1,2 +1,37 @@
 
-pub const PID0: &[u8] = include_bytes!("../../../initramdata/pid0");
\ No newline at end of file
+pub const PID0: &[u8] = include_bytes!("../../../initramdata/pid0");
+
+#[repr(C)]
+struct AlignedAs<Align, Bytes: ?Sized> {
+  _align: [Align; 0],
+  bytes: Bytes,
+}
+
+#[repr(align(4096))]
+struct Page;
+
+// include_bytes only guarantees byte alignment, the archive is page aligned
+// so the files in it are page aligned in memory as well
+static INITRAMFS_ALIGNED: &AlignedAs<Page, [u8]> = &AlignedAs {
+  _align: [],
+  bytes: *include_bytes!("../../../initramdata/initramfs.bin"),
+};
+
+/// The initramfs archive, it is part of the kernel image and read-only
+pub fn initramfs() -> &'static [u8] {
+  &INITRAMFS_ALIGNED.bytes
+}
+
+/// Validates the embedded initramfs and logs its contents
+pub fn init() {
+  match initramfs::Archive::new(initramfs()) {
+    Ok(archive) => {
+      info!("initramfs at {:#x}, {} bytes, {} files",
+        initramfs().as_ptr() as u64, initramfs().len(), archive.len());
+      for entry in archive.entries() {
+        debug!("initramfs: {:o} {:8} {}", entry.mode, entry.data.len(), entry.name);
+      }
+    }
+    Err(e) => error!("initramfs is invalid: {:?}", e),
+  }
+}
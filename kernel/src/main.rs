@@ -1,7 +1,7 @@
 #![feature(abi_x86_interrupt,alloc_error_handler,allocator_api,lang_items,
   const_raw_ptr_to_usize_cast,asm,naked_functions,integer_atomics,panic_info_message,
   const_fn,exclusive_range_pattern,try_trait,concat_idents,custom_test_frameworks, 
-  alloc_layout_extra)]
+  alloc_layout_extra,global_asm)]
 
 #![test_runner(crate::test::test_runner)]
 #![reexport_test_harness_main = "test_main"]
@@ -34,15 +34,14 @@ mod vmem;
 #[cfg(test)]
 mod test;
 
-use self::alloc::sync::Arc;
 use self::process_manager::Userspace;
 use self::vmem::PageManager;
-use core::cell::RefCell;
+use spin::RwLock;
 
 pub use crate::common::*;
 
 pub static PAGER: PageManager = PageManager::new();
-static mut USERSPACE: Option<Arc<RefCell<Userspace>>> = None;
+static USERSPACE: RwLock<Option<Userspace>> = RwLock::new(None);
 
 bootloader::entry_point!(kernel_main);
 
@@ -51,6 +50,17 @@ fn kernel_main(boot_info: &'static bootloader::BootInfo) -> ! {
   bindriver::init();
   info!("BoringOS v{}", version::VERSION);
   crate::common::init::init_memory(boot_info);
+  #[cfg(feature = "hardening")]
+  bindriver::cpu::gdt::protect_stacks();
+  bindriver::acpi::init();
+  // the ACPI tables have been copied to the heap
+  crate::common::init::reclaim(crate::vmem::regions::RegionKind::AcpiReclaimable);
+  bindriver::cpu::interrupts::init();
+  bindriver::serial::console::init();
+  #[cfg(feature = "gdbstub")]
+  bindriver::gdbstub::init();
+  bindriver::cpu::smp::init();
+  inc::init();
   pager().print_mem_summary();
   #[cfg(test)]
   {
@@ -61,7 +71,7 @@ fn kernel_main(boot_info: &'static bootloader::BootInfo) -> ! {
   #[cfg(not(test))]
   {
     {
-      unsafe { USERSPACE = Some(Arc::new(RefCell::new(Userspace::new()))) }
+      *USERSPACE.write() = Some(Userspace::new());
       let us = userspace();
       {
         us.in_scheduler_mut_spin(|mut sched| {
@@ -91,6 +101,7 @@ use core::panic::PanicInfo;
 
 pub fn coredump() -> ! {
   error!("Kernel Core Dumped");
+  crate::common::backtrace::print_backtrace();
   hlt_cpu!();
 }
 
@@ -98,6 +109,8 @@ pub fn coredump() -> ! {
 #[panic_handler]
 #[no_mangle]
 pub fn panic(info: &PanicInfo) -> ! {
+  #[cfg(feature = "vga")]
+  crate::bindriver::vga_buffer::panic_screen();
   match info.message() {
     Some(s) => error!("Panic occured: {}", s),
     None => error!("Panic had no message"),
@@ -106,6 +119,9 @@ pub fn panic(info: &PanicInfo) -> ! {
     Some(s) => error!("Panicked at {}~{}", s.file(), s.line()),
     None => error!("Panic had no stracktrace"),
   }
+  crate::common::backtrace::print_backtrace();
+  #[cfg(all(feature = "monitor", not(test)))]
+  crate::bindriver::monitor::enter(crate::bindriver::monitor::Reason::Panic);
   //#[cfg(test)]
   {
     use crate::bindriver::cpu::qemu::*;
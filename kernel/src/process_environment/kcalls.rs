@@ -1,51 +1,117 @@
 use crate::*;
+use crate::process_manager::{Rights, TaskHandle};
 
 pub fn bos_set_sig_handler(f: *mut u8) {
-  debug!("set signal handler here: {:?}", f)
+  debug!("setting signal handler to {:?}", f);
+  with_current_task_mut(|task| {
+    match task {
+      None => (),
+      Some(mut task) => { task.state_mut().set_signal_handler(f as usize); },
+    }
+  }).unwrap_or_default()
 }
 
 pub fn bos_sig_handle(sig: u64, id: u64, code: u64) {
   debug!("handle signal handling here")
 }
 
+// Logs a message of the calling task with the target task/<name>, the
+// logger adds the task handle. Messages over the task's rate limit are
+// dropped, the next admitted message reports how many were dropped
+fn task_log(level: log::Level, msg: core::fmt::Arguments) {
+  use crate::bindriver::cpu::tsc::now_ms;
+  let admitted = with_current_task_mut(|task| {
+    task.and_then(|mut task| {
+      let suppressed = task.log_limit_mut().admit(now_ms())?;
+      Some((alloc::format!("task/{}", task.name()), suppressed))
+    })
+  }).unwrap_or_else(|_| Some(("task/unknown".into(), 0)));
+  if let Some((target, suppressed)) = admitted {
+    if suppressed > 0 {
+      warn!(target: &target, "rate limit suppressed {} messages", suppressed);
+    }
+    log!(target: &target, level, "{}", msg);
+  }
+}
+
 pub fn bos_log_trace(msg: &str) {
-  trace!("{}", msg);
+  task_log(log::Level::Trace, format_args!("{}", msg));
 }
 
 pub fn bos_log_trace_fmt(msg: core::fmt::Arguments) {
-  debug!("{}", msg);
+  task_log(log::Level::Trace, msg);
 }
 
 pub fn bos_log_debug(msg: &str) {
-  debug!("{}", msg);
+  task_log(log::Level::Debug, format_args!("{}", msg));
 }
 
 pub fn bos_log_debug_fmt(msg: core::fmt::Arguments) {
-  debug!("{}", msg);
+  task_log(log::Level::Debug, msg);
 }
 
 pub fn bos_log_info(msg: &str) {
-  info!("{}", msg);
+  task_log(log::Level::Info, format_args!("{}", msg));
 }
 
 pub fn bos_log_info_fmt(msg: core::fmt::Arguments) {
-  info!("{}", msg);
+  task_log(log::Level::Info, msg);
 }
 
 pub fn bos_log_warn(msg: &str) {
-  warn!("{}", msg);
+  task_log(log::Level::Warn, format_args!("{}", msg));
 }
 
 pub fn bos_log_warn_fmt(msg: core::fmt::Arguments) {
-  warn!("{}", msg);
+  task_log(log::Level::Warn, msg);
 }
 
 pub fn bos_log_error(msg: &str) {
-  error!("{}", msg);
+  task_log(log::Level::Error, format_args!("{}", msg));
 }
 
 pub fn bos_log_error_fmt(msg: core::fmt::Arguments) {
-  error!("{}", msg);
+  task_log(log::Level::Error, msg);
+}
+
+// Replaces the kernel log filter, the filter is a comma separated list of
+// levels and target=level directives, e.g. "info,vmem=debug,task/pid0=warn"
+// Returns false if the filter could not be parsed, the old filter stays active
+// Requires the LOG right on the scheduler handle 0, returns false without it
+pub fn bos_set_log_filter(spec: &str) -> bool {
+  use crate::bindriver::serial::filter;
+  if let Err(e) = check_rights(TaskHandle::zero(), Rights::LOG) {
+    warn!("task may not change the log filter: {:?}", e);
+    return false;
+  }
+  match filter::set(spec) {
+    Ok(()) => {
+      info!("log filter set to {:?}", spec);
+      true
+    },
+    Err(e) => {
+      warn!("invalid log filter {:?}: {:?}", spec, e);
+      false
+    },
+  }
+}
+
+// bos_read_log copies kernel log records starting at the sequence number in
+// cursor into the buffer and advances the cursor past them. Start with a
+// cursor of 0 to read the whole log and keep the cursor to follow it.
+// If records were overwritten before they were read, the cursor skips ahead
+// to the oldest record kept.
+// Returns the number of records copied, 0 if there are no new records
+pub fn bos_read_log(buf: &mut [crate::common::klog::LogRecord], cursor: &mut u64) -> u64 {
+  crate::common::klog::read(buf, cursor) as u64
+}
+
+// bos_console_read reads lines typed on the serial console into the buffer,
+// a line longer than the buffer is returned over several calls.
+// If block is set the call waits for a complete line, otherwise it returns
+// immediately. Returns the number of bytes read
+pub fn bos_console_read(buf: &mut [u8], block: bool) -> u64 {
+  crate::bindriver::serial::console::read(buf, block) as u64
 }
 
 // bos_raise_page_limit raises the amount of memory the program may use
@@ -84,6 +150,148 @@ pub fn bos_get_page_count_nondata() -> u64 {
   + kinfo().get_stack_memory_ref_size() as u64
 }
 
+// bos_release_pages gives data pages of the task back to the kernel
+// The range starts at the page aligned address and must be inside the data memory.
+// Released pages read as zero afterwards, writing to them maps a new page.
+// Released pages at the end of the data memory are unmapped entirely unless
+// they belong to the task image.
+// Returns the number of pages released
+pub fn bos_release_pages(start: u64, pages: u64) -> u64 {
+  use crate::vmem::{DATA_START, PAGE_SIZE};
+  use crate::vmem::mapper::{remap, unmap, MapType};
+  let start = start as usize;
+  if start % PAGE_SIZE != 0 || start < DATA_START {
+    warn!("task tried to release pages at invalid address {:#018x}", start);
+    return 0;
+  }
+  let first = (start - DATA_START) / PAGE_SIZE;
+  let last = core::cmp::min(
+    first.saturating_add(pages as usize),
+    kinfo().get_data_memory_ref_size(),
+  );
+  let zero_page = kinfo().get_zero_page_addr();
+  let mut released = 0;
+  // start of the trailing run of pages released by this call, only those
+  // may be truncated, zero pages the task did not release stay mapped
+  let mut released_run = None;
+  for index in first..last {
+    if let Some(page) = kinfo().demote_data_page(index) {
+      let vaddr = VirtAddr::new((DATA_START + index * PAGE_SIZE) as u64);
+      remap(vaddr, zero_page, MapType::Zero);
+      match release_page(page) {
+        Ok(()) => {
+          released += 1;
+          released_run = released_run.or(Some(index));
+          continue;
+        },
+        Err(e) => {
+          // the page stays with the task, mapped and tracked as before
+          warn!("could not release page {:?}: {:?}", page, e);
+          remap(vaddr, page, MapType::Data);
+          kinfo().promote_data_page(index, page);
+        },
+      }
+    }
+    released_run = None;
+  }
+  let truncated = match released_run {
+    Some(keep) if last == kinfo().get_data_memory_ref_size() => kinfo().truncate_data_zero_pages(keep),
+    _ => 0,
+  };
+  if truncated > 0 {
+    let end = DATA_START + kinfo().get_data_memory_ref_size() * PAGE_SIZE;
+    unmap(VirtAddr::new(end as u64), truncated, MapType::Zero);
+  }
+  trace!("released {} pages, truncated {} pages", released, truncated);
+  crate::process_manager::dispatch_memory_pressure();
+  released
+}
+
+// bos_notify_mem_pressure registers the task for memory pressure signals
+// The signal is sent whenever free memory crosses the low or critical watermark
+pub fn bos_notify_mem_pressure(enable: bool) {
+  with_current_task_mut(|task| {
+    match task {
+      None => (),
+      Some(mut task) => task.set_notify_mem_pressure(enable),
+    }
+  }).unwrap_or_default()
+}
+
+// Returns the current memory pressure; 0 = normal, 1 = low, 2 = critical
+pub fn bos_get_mem_pressure() -> u8 {
+  pager().pressure() as u8
+}
+
+/// A mapping of the calling task as returned by bos_get_mappings
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MappingInfo {
+  pub start: u64,
+  /// exclusive end of the mapping
+  pub end: u64,
+  pub frame: u64,
+  pub flags: u64,
+  /// 0 = 4KiB, 1 = 2MiB, 2 = 1GiB
+  pub page_size: u64,
+}
+
+// bos_get_mappings writes the mappings of the task's code, data and stack memory
+// into the buffer, in ascending address order. Runs of contiguous pages with
+// identical flags are reported as one mapping.
+// Returns the total number of mappings, which may exceed the buffer length
+pub fn bos_get_mappings(buf: &mut [MappingInfo]) -> u64 {
+  use crate::vmem::{CODE_START, DATA_END, STACK_END, STACK_START, PAGE_SIZE};
+  use crate::vmem::walker::mappings;
+  let mut runs = mappings(
+    VirtAddr::new(CODE_START as u64), VirtAddr::new(DATA_END as u64));
+  runs.extend(mappings(
+    VirtAddr::new(STACK_END as u64), VirtAddr::new((STACK_START + PAGE_SIZE) as u64)));
+  for (slot, run) in buf.iter_mut().zip(runs.iter()) {
+    *slot = MappingInfo {
+      start: run.start.as_u64(),
+      // task memory ends far below the top of the address space
+      end: run.end as u64,
+      frame: run.frame.as_u64(),
+      flags: run.flags.bits(),
+      page_size: run.size as u64,
+    };
+  }
+  runs.len() as u64
+}
+
+// bos_get_acpi_table copies the nth ACPI table with the given signature,
+// including its header, into the buffer. The tables are copies made during
+// boot, writes to the buffer do not affect the kernel.
+// Returns the length of the table or 0 if there is no such table
+pub fn bos_get_acpi_table(signature: &str, index: u64, buf: &mut [u8]) -> u64 {
+  let table = crate::bindriver::acpi::acpi()
+    .and_then(|acpi| acpi.table(signature.as_bytes(), index as usize));
+  match table {
+    None => 0,
+    Some(table) => {
+      let data = table.bytes();
+      let len = core::cmp::min(data.len(), buf.len());
+      buf[..len].copy_from_slice(&data[..len]);
+      data.len() as u64
+    }
+  }
+}
+
+// bos_get_initramfs returns the initramfs archive embedded in the kernel.
+// The archive is part of the read-only kernel image that is mapped into every
+// task, it is page aligned and can be parsed with the initramfs crate
+pub fn bos_get_initramfs() -> &'static [u8] {
+  crate::inc::initramfs()
+}
+
+// bos_get_random fills the buffer with output of the kernel CSPRNG
+// Returns the number of bytes written
+pub fn bos_get_random(buf: &mut [u8]) -> u64 {
+  crate::bindriver::cpu::rng::fill(buf);
+  buf.len() as u64
+}
+
 // bos_promise_pages will allocate a number of pages to the program beyond
 // the currently allocated ones. The returned number is how many pages
 // the OS is able to actually promise.
@@ -106,9 +314,42 @@ pub fn bos_spawn_task() -> u128 {
   }
 }
 
+// bos_run_on starts a new task on an idle application processor, the task
+// runs there until it yields. Requires the YIELD right on the task
+// Returns false if the task was started before or the CPU is not idle
+pub fn bos_run_on(cpu: u64, th: u128) -> bool {
+  let th = TaskHandle::from_c(th);
+  if let Err(e) = check_rights(th, Rights::YIELD) {
+    warn!("task may not start {} on CPU {}: {:?}", th, cpu, e);
+    return false;
+  }
+  let claimed = with_task_mut(th, |task| {
+    task.map(|mut task| task.claim_start()).unwrap_or(false)
+  }).unwrap_or(false);
+  if !claimed {
+    warn!("task {} cannot be started on CPU {}", th, cpu);
+    return false;
+  }
+  if crate::bindriver::cpu::smp::run_on(cpu as usize, th).is_err() {
+    warn!("CPU {} cannot take task {}", cpu, th);
+    with_task_mut(th, |task| {
+      if let Some(mut task) = task {
+        task.status = crate::process_manager::Status::New;
+      }
+    }).ok();
+    return false;
+  }
+  true
+}
+
 /// Resets the state's memory and then copies the given code image into
 /// the task
+/// Requires the DESTROY right as the task loses its memory
 pub fn bos_set_codeimage(th: u128, code_img: &[u8]) -> Result<usize, ()> {
+  if let Err(e) = check_rights(th.into(), Rights::DESTROY) {
+    warn!("task may not set code image of {:x}: {:?}", th, e);
+    return Err(());
+  }
   with_task_mut(th.into(), |task| {
     match task {
       Some(mut task) => {
@@ -124,6 +365,10 @@ pub fn bos_set_codeimage(th: u128, code_img: &[u8]) -> Result<usize, ()> {
 }
 
 pub fn bos_yield(th: u128) {
+  if let Err(e) = check_rights(th.into(), Rights::YIELD) {
+    warn!("task may not yield to {:x}: {:?}", th, e);
+    return;
+  }
   yield_to(th)
 }
 
@@ -132,9 +377,58 @@ pub fn bos_yield(th: u128) {
 // the receiving task will be terminated when the signal handler
 // returns or the signal handler times out.
 pub fn bos_destroy_task(th: u128) {
+  if let Err(e) = check_rights(th.into(), Rights::DESTROY) {
+    warn!("task may not destroy {:x}: {:?}", th, e);
+    return;
+  }
   panic!("TODO:")
 }
 
+// bos_cap_share passes the capability for th to the receiving task,
+// limited to the given rights and to the rights the caller holds itself.
+// The caller must be able to signal the receiver.
+// Returns the rights passed on, 0 if nothing was shared
+pub fn bos_cap_share(receiver: u128, th: u128, rights: u64) -> u64 {
+  if let Err(e) = check_rights(receiver.into(), Rights::SIGNAL) {
+    warn!("task may not share capabilities with {:x}: {:?}", receiver, e);
+    return 0;
+  }
+  let held = with_current_task(|task| {
+    task.map(|task| task.caps().rights(th.into())).unwrap_or(Rights::empty())
+  }).unwrap_or(Rights::empty());
+  let shared = held & Rights::from_bits_truncate(rights);
+  if shared.is_empty() {
+    return 0;
+  }
+  with_task_mut(receiver.into(), |task| {
+    match task {
+      None => 0,
+      Some(mut task) => {
+        task.caps_mut().grant(th.into(), shared);
+        shared.bits()
+      }
+    }
+  }).unwrap_or_default()
+}
+
+// bos_cap_drop removes rights from the caller's capability for th
+// Returns the remaining rights
+pub fn bos_cap_drop(th: u128, rights: u64) -> u64 {
+  with_current_task_mut(|task| {
+    match task {
+      None => 0,
+      Some(mut task) => task.caps_mut().restrict(th.into(), Rights::from_bits_truncate(rights)).bits(),
+    }
+  }).unwrap_or_default()
+}
+
+// bos_cap_query returns the rights the caller holds on th
+pub fn bos_cap_query(th: u128) -> u64 {
+  with_current_task(|task| {
+    task.map(|task| task.caps().rights(th.into()).bits()).unwrap_or_default()
+  }).unwrap_or_default()
+}
+
 // returns the current task handle
 pub fn bos_own_th() -> u128 {
   userspace().in_scheduler_spin(|sched| {
@@ -162,4 +456,25 @@ pub fn bos_register_ipc(ptr: *mut u8) -> u64 {
 /// A process may use CPU timing to find out if a value is masked or not.
 pub fn bos_mask_ipc(symt: u16, sym: &str) -> bool {
   panic!("TODO:")
-}
\ No newline at end of file
+}
+
+// bos_shutdown powers the machine off, when running under QEMU the code is
+// passed to the isa-debug-exit device and QEMU exits with (code << 1) | 1.
+// Requires the POWER right on the scheduler handle 0, returns only without it
+pub fn bos_shutdown(code: u32) {
+  if let Err(e) = check_rights(TaskHandle::zero(), Rights::POWER) {
+    warn!("task may not shut down: {:?}", e);
+    return;
+  }
+  crate::bindriver::power::shutdown(code)
+}
+
+// bos_reboot resets the machine
+// Requires the POWER right on the scheduler handle 0, returns only without it
+pub fn bos_reboot() {
+  if let Err(e) = check_rights(TaskHandle::zero(), Rights::POWER) {
+    warn!("task may not reboot: {:?}", e);
+    return;
+  }
+  crate::bindriver::power::reboot()
+}
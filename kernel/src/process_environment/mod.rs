@@ -7,7 +7,7 @@
 
 use symrfp::SymbolType;
 
-mod kcalls;
+pub(crate) mod kcalls;
 
 // BOS only provides a base set of symbols, to extend this
 // list of syscalls, another process must wrap this syscall
@@ -35,12 +35,28 @@ pub extern fn symrf(sym_type: u16, sym_name: &str) -> *mut u8 {
             "bos_log_warn_fmt" => kcalls::bos_log_warn_fmt as *mut u8,
             "bos_log_error" => kcalls::bos_log_error as *mut u8,
             "bos_log_error_fmt" => kcalls::bos_log_error_fmt as *mut u8,
+            "bos_set_log_filter" => kcalls::bos_set_log_filter as *mut u8,
+            "bos_read_log" => kcalls::bos_read_log as *mut u8,
+            "bos_console_read" => kcalls::bos_console_read as *mut u8,
             "bos_raise_page_limit" => kcalls::bos_raise_page_limit as *mut u8,
             "bos_get_page_limit" => kcalls::bos_get_page_limit as *mut u8,
             "bos_get_page_count_data" => kcalls::bos_get_page_count_data as *mut u8,
             "bos_get_page_count_nondata" => kcalls::bos_get_page_count_nondata as *mut u8,
+            "bos_release_pages" => kcalls::bos_release_pages as *mut u8,
+            "bos_notify_mem_pressure" => kcalls::bos_notify_mem_pressure as *mut u8,
+            "bos_get_mem_pressure" => kcalls::bos_get_mem_pressure as *mut u8,
+            "bos_get_mappings" => kcalls::bos_get_mappings as *mut u8,
+            "bos_get_acpi_table" => kcalls::bos_get_acpi_table as *mut u8,
+            "bos_get_initramfs" => kcalls::bos_get_initramfs as *mut u8,
+            "bos_get_random" => kcalls::bos_get_random as *mut u8,
             "bos_yield" => kcalls::bos_yield as *mut u8,
             "bos_spawn_task" => kcalls::bos_spawn_task as *mut u8,
+            "bos_run_on" => kcalls::bos_run_on as *mut u8,
+            "bos_cap_share" => kcalls::bos_cap_share as *mut u8,
+            "bos_cap_drop" => kcalls::bos_cap_drop as *mut u8,
+            "bos_cap_query" => kcalls::bos_cap_query as *mut u8,
+            "bos_shutdown" => kcalls::bos_shutdown as *mut u8,
+            "bos_reboot" => kcalls::bos_reboot as *mut u8,
             _ => 0 as *mut u8,
           }
         },
@@ -0,0 +1,84 @@
+use crate::process_manager::TaskHandle;
+use alloc::collections::BTreeMap;
+
+bitflags::bitflags! {
+  /// Rights a task holds on another task
+  pub struct Rights: u64 {
+    const YIELD = 1 << 0;
+    const SIGNAL = 1 << 1;
+    const INSPECT = 1 << 2;
+    const DESTROY = 1 << 3;
+    const SHARE_MEMORY = 1 << 4;
+    /// Held on the scheduler handle 0, allows changing the kernel log filter
+    const LOG = 1 << 5;
+    /// Held on the scheduler handle 0, allows shutting down and rebooting
+    const POWER = 1 << 6;
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CapError {
+  /// The task holds no capability for the target
+  NoCapability(TaskHandle),
+  /// The capability lacks some of the required rights
+  MissingRights { target: TaskHandle, missing: Rights },
+}
+
+/// Capabilities of a task, indexed by the handle of the target task
+/// Knowing a task handle grants nothing, only entries in the table do.
+/// The scheduler handle 0 can always be yielded to.
+#[derive(Debug, Clone)]
+pub struct CapTable {
+  caps: BTreeMap<TaskHandle, Rights>,
+  // rights on tasks without an entry, only PID0 holds any
+  default: Rights,
+}
+
+impl CapTable {
+  pub fn new() -> CapTable {
+    CapTable { caps: BTreeMap::new(), default: Rights::empty() }
+  }
+  /// Table holding all rights on every task
+  pub fn root() -> CapTable {
+    CapTable { caps: BTreeMap::new(), default: Rights::all() }
+  }
+  pub fn rights(&self, target: TaskHandle) -> Rights {
+    let rights = self.caps.get(&target).cloned().unwrap_or(self.default);
+    if target.is_scheduler() {
+      rights | Rights::YIELD
+    } else {
+      rights
+    }
+  }
+  pub fn check(&self, target: TaskHandle, needed: Rights) -> Result<(), CapError> {
+    let rights = self.rights(target);
+    if rights.is_empty() && !needed.is_empty() {
+      return Err(CapError::NoCapability(target));
+    }
+    let missing = needed - rights;
+    if missing.is_empty() {
+      Ok(())
+    } else {
+      Err(CapError::MissingRights { target, missing })
+    }
+  }
+  /// Adds the rights to the capability for the target
+  pub fn grant(&mut self, target: TaskHandle, rights: Rights) {
+    let current = self.rights(target);
+    self.caps.insert(target, current | rights);
+  }
+  /// Removes the rights from the capability, returns the remaining rights
+  /// The capability is deleted once no rights remain.
+  pub fn restrict(&mut self, target: TaskHandle, rights: Rights) -> Rights {
+    let remaining = self.rights(target) - rights;
+    if remaining.is_empty() && self.default.is_empty() {
+      self.caps.remove(&target);
+    } else {
+      self.caps.insert(target, remaining);
+    }
+    remaining
+  }
+  pub fn iter(&self) -> impl Iterator<Item = (&TaskHandle, &Rights)> {
+    self.caps.iter()
+  }
+}
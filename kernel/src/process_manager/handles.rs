@@ -2,7 +2,7 @@
 use crate::process_manager::Task;
 use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
-use core::cell::RefCell;
+use spin::Mutex;
 
 #[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
 pub struct Handle(u128);
@@ -57,7 +57,7 @@ impl ::core::fmt::UpperHex for Handle {
 
 #[derive(Clone)]
 pub struct TaskHandleRegistry(BTreeMap<TaskHandle, 
-  Arc<RefCell<Task>>>);
+  Arc<Mutex<Task>>>);
 
 
 impl TaskHandleRegistry {
@@ -65,11 +65,17 @@ impl TaskHandleRegistry {
     TaskHandleRegistry(BTreeMap::new())
   }
   pub fn insert(&mut self, th: TaskHandle, t: Task) {
-    self.0.insert(th, Arc::new(RefCell::new(t)));
+    self.0.insert(th, Arc::new(Mutex::new(t)));
   }
-  pub fn resolve(&self, th: TaskHandle) -> Option<&Arc<RefCell<Task>>> {
+  pub fn remove(&mut self, th: TaskHandle) -> Option<Arc<Mutex<Task>>> {
+    self.0.remove(&th)
+  }
+  pub fn resolve(&self, th: TaskHandle) -> Option<&Arc<Mutex<Task>>> {
     self.0.get(&th)
   }
+  pub fn iter(&self) -> impl Iterator<Item = (&TaskHandle, &Arc<Mutex<Task>>)> {
+    self.0.iter()
+  }
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
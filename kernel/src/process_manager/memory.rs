@@ -3,6 +3,8 @@ use alloc::rc::Rc;
 use alloc::vec::Vec;
 use core::cell::RefCell;
 use crate::vmem::mapper::{map, map_zero, unmap, MapType};
+use crate::vmem::pagelist::FrameOwner;
+use x86_64::structures::paging::PageTableFlags;
 use crate::{PhysAddr, VirtAddr};
 use core::convert::TryInto;
 
@@ -35,11 +37,13 @@ impl MemoryUserRef {
   pub fn new_sized(n: u8) -> Self {
     let mut pages: Vec<PhysAddr> = Vec::new();
     if n > 0 { for _ in 0..n {
-      pages.push(crate::common::alloc_page().expect("could not spawn page for user memory"));
+      pages.push(crate::common::alloc_page_owned(FrameOwner::Task)
+        .expect("could not spawn page for user memory"));
     } }
     let data = Box::new(Rc::new(RefCell::new(MemoryUser {
       pages,
       first_page_offset: 0,
+      image_pages: 0,
     })));
     let ptr = Box::into_raw(data);
     assert!(ptr as usize != 0, "memory user reference null pointer");
@@ -58,6 +62,21 @@ impl MemoryUserRef {
   pub fn add_page(&self, pg: PhysAddr) {
     unsafe { (**self.internal_ref).borrow_mut() }.pages.push(pg)
   }
+  pub fn add_zero_pages(&self, n: usize) {
+    unsafe { (**self.internal_ref).borrow_mut() }.add_zero_pages(n)
+  }
+  pub fn promote_page(&self, index: usize, pg: PhysAddr) -> Option<PhysAddr> {
+    unsafe { (**self.internal_ref).borrow_mut() }.promote_page(index, pg)
+  }
+  pub fn demote_page(&self, index: usize) -> Option<PhysAddr> {
+    unsafe { (**self.internal_ref).borrow_mut() }.demote_page(index)
+  }
+  pub fn mark_image_end(&self) {
+    unsafe { (**self.internal_ref).borrow_mut() }.mark_image_end()
+  }
+  pub fn truncate_zero_pages(&self, keep: usize) -> usize {
+    unsafe { (**self.internal_ref).borrow_mut() }.truncate_zero_pages(keep)
+  }
   pub fn page_count(&self) -> usize {
     let mem = unsafe { (**self.internal_ref).borrow_mut() };
     mem.page_count()
@@ -109,6 +128,20 @@ impl core::fmt::Debug for MemoryKernelRef {
   }
 }
 
+#[derive(Debug, Clone)]
+pub enum MemoryConsistencyError {
+  /// A page recorded in the memory is not mapped
+  PageNotMapped(VirtAddr),
+  /// A page is mapped but not recorded in the memory
+  UnexpectedMapping(VirtAddr),
+  /// A page is mapped to a different frame than recorded
+  FrameMismatch { addr: VirtAddr, expected: PhysAddr, actual: PhysAddr },
+  /// A page is mapped with flags that do not match the memory type
+  FlagMismatch { addr: VirtAddr, expected: PageTableFlags, actual: PageTableFlags },
+  /// A page is not mapped with 4KiB pages
+  HugePage(VirtAddr),
+}
+
 #[derive(Clone, Debug)]
 pub enum Memory {
   NoMemory,
@@ -224,6 +257,31 @@ impl Memory {
       Memory::KernelStack(_) => panic!("kernel tried to set offset on kernel stack memory"),
     }
   }
+  /// Reserves pages at the end of the memory, backed by the shared zero page
+  pub fn add_zero_pages(&self, n: usize) {
+    match self {
+      Memory::User(s) => s.add_zero_pages(n),
+      _ => panic!("kernel tried to add zero pages to non-data memory"),
+    }
+  }
+  /// Marks all current pages as part of the task image, they are never truncated
+  pub fn mark_image_end(&self) {
+    match self {
+      Memory::User(s) => s.mark_image_end(),
+      _ => panic!("kernel tried to mark the image end of non-data memory"),
+    }
+  }
+  /// Cross-checks the recorded pages against the active page tables
+  /// The memory must be mapped, returns the number of checked pages
+  pub fn check_consistency(&self) -> Result<usize, MemoryConsistencyError> {
+    match self {
+      Memory::NoMemory => Ok(0),
+      Memory::User(s) => (*s).borrow().check_mapped(self.start_address(), MapType::Data),
+      Memory::Code(s) => (*s).borrow().check_mapped(self.start_address(), MapType::Code),
+      Memory::Stack(s) => (*s).borrow().check_mapped(self.start_address(), MapType::Stack),
+      Memory::KernelStack(_) => Ok(0),
+    }
+  }
   pub fn page_count(&self) -> usize {
     match self {
       Memory::NoMemory => 0,
@@ -240,11 +298,14 @@ pub struct MemoryUser {
   // pages to place the memory from the actual start of the section
   // bss memory relies on this
   first_page_offset: u32,
+  // pages of the loaded image including bss, the task expects them to exist
+  image_pages: usize,
 }
 
 impl core::fmt::Debug for MemoryUser {
   fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
-    write!(f, "MemoryUser {{ pages: {}, zpo: {} }}", self.pages.len(), self.first_page_offset)
+    write!(f, "MemoryUser {{ pages: {}, zpo: {}, image: {} }}",
+      self.pages.len(), self.first_page_offset, self.image_pages)
   }
 }
 
@@ -268,11 +329,27 @@ impl MemoryUser {
     }
     trace!("mapping user memory to {:?} ({:?})", base, t);
     let adj_base = base + (self.first_page_offset as usize) * crate::vmem::PAGE_SIZE;
-    map(
-      adj_base,
-      &self.pages,
-      t,
-    );
+    // zero pages are mapped read-only and promoted on the first write
+    // so the pages are mapped in runs of equal type
+    let zero_page = crate::kinfo().get_zero_page_addr();
+    let mut run_start = 0;
+    for idx in 1..=self.pages.len() {
+      let run_is_zero = self.pages[run_start] == zero_page;
+      if idx < self.pages.len() && (self.pages[idx] == zero_page) == run_is_zero {
+        continue;
+      }
+      let run_base = if t == MapType::Stack {
+        adj_base - run_start * crate::vmem::PAGE_SIZE
+      } else {
+        adj_base + run_start * crate::vmem::PAGE_SIZE
+      };
+      map(
+        run_base,
+        &self.pages[run_start..idx],
+        if run_is_zero { MapType::Zero } else { t },
+      );
+      run_start = idx;
+    }
   }
   fn unmap(&self, base: VirtAddr, t: MapType) {
     if self.pages.len() == 0 {
@@ -286,9 +363,127 @@ impl MemoryUser {
     let adj_base = base + (self.first_page_offset as usize) * crate::vmem::PAGE_SIZE;
     unmap(adj_base, self.pages.len(), t);
   }
+  /// Returns the virtual address of the page at the given index when mapped
+  /// to base, index counts the pre-mapped zero pages
+  fn page_addr(&self, base: VirtAddr, t: MapType, index: usize) -> VirtAddr {
+    use crate::vmem::PAGE_SIZE;
+    let offset = self.first_page_offset as usize;
+    if index < offset || t != MapType::Stack {
+      base + index * PAGE_SIZE
+    } else {
+      base + offset * PAGE_SIZE - (index - offset) * PAGE_SIZE
+    }
+  }
+  /// Returns the index of the page mapped at addr, the inverse of page_addr
+  fn page_index(&self, base: VirtAddr, t: MapType, addr: VirtAddr) -> Option<usize> {
+    use crate::vmem::PAGE_SIZE;
+    let offset = self.first_page_offset as usize;
+    let adj_base = base + offset * PAGE_SIZE;
+    let index = if t == MapType::Stack && addr <= adj_base {
+      offset + ((adj_base - addr) as usize / PAGE_SIZE)
+    } else if addr >= base {
+      (addr - base) as usize / PAGE_SIZE
+    } else {
+      return None;
+    };
+    if index < self.page_count() && self.page_addr(base, t, index) == addr {
+      Some(index)
+    } else {
+      None
+    }
+  }
+  fn check_mapped(&self, base: VirtAddr, t: MapType) -> Result<usize, MemoryConsistencyError> {
+    use crate::vmem::walker::{mappings, PageSize};
+    use crate::vmem::PAGE_SIZE;
+    let count = self.page_count();
+    if count == 0 {
+      return Ok(0);
+    }
+    let zero_page = crate::kinfo().get_zero_page_addr();
+    let offset = self.first_page_offset as usize;
+    let first = self.page_addr(base, t, 0);
+    let last = self.page_addr(base, t, count - 1);
+    let (low, high) = if first < last { (first, last) } else { (last, first) };
+    let mut seen = 0;
+    for run in mappings(low, high + PAGE_SIZE) {
+      if run.size != PageSize::Size4KiB {
+        return Err(MemoryConsistencyError::HugePage(run.start));
+      }
+      for n in 0..run.pages() {
+        let addr = run.start + n * PAGE_SIZE as u64;
+        let actual = run.frame + n * PAGE_SIZE as u64;
+        let index = self.page_index(base, t, addr)
+          .ok_or(MemoryConsistencyError::UnexpectedMapping(addr))?;
+        let expected = if index < offset { zero_page } else { self.pages[index - offset] };
+        if expected != actual {
+          return Err(MemoryConsistencyError::FrameMismatch { addr, expected, actual });
+        }
+        let expected_flags = if expected == zero_page { MapType::Zero.flags() } else { t.flags() };
+        if expected_flags != run.flags {
+          return Err(MemoryConsistencyError::FlagMismatch {
+            addr, expected: expected_flags, actual: run.flags,
+          });
+        }
+        seen += 1;
+      }
+    }
+    if seen != count {
+      let missing = (0..count)
+        .map(|index| self.page_addr(base, t, index))
+        .find(|addr| !crate::vmem::mapper::is_mapped(*addr))
+        .unwrap_or(first);
+      return Err(MemoryConsistencyError::PageNotMapped(missing));
+    }
+    Ok(count)
+  }
   pub fn set_offset(&mut self, offset: u32) {
     self.first_page_offset = offset
   }
+  /// Appends pages backed by the shared zero page
+  pub fn add_zero_pages(&mut self, n: usize) {
+    let zero_page = crate::kinfo().get_zero_page_addr();
+    for _ in 0..n {
+      self.pages.push(zero_page);
+    }
+  }
+  /// Replaces the zero page at the given index with the given page
+  /// Returns the zero page or None if the index is not backed by the zero page
+  pub fn promote_page(&mut self, index: usize, pg: PhysAddr) -> Option<PhysAddr> {
+    let zero_page = crate::kinfo().get_zero_page_addr();
+    let index = index.checked_sub(self.first_page_offset as usize)?;
+    let page = self.pages.get_mut(index)?;
+    if *page != zero_page {
+      return None;
+    }
+    *page = pg;
+    Some(zero_page)
+  }
+  /// Replaces the page at the given index with the zero page
+  /// Returns the replaced page or None if the index is already backed by the zero page
+  pub fn demote_page(&mut self, index: usize) -> Option<PhysAddr> {
+    let zero_page = crate::kinfo().get_zero_page_addr();
+    let index = index.checked_sub(self.first_page_offset as usize)?;
+    let page = self.pages.get_mut(index)?;
+    if *page == zero_page {
+      return None;
+    }
+    Some(core::mem::replace(page, zero_page))
+  }
+  pub fn mark_image_end(&mut self) {
+    self.image_pages = self.page_count();
+  }
+  /// Drops zero pages from the end of the memory, the memory keeps at least
+  /// keep pages and the image pages. Returns how many were dropped
+  pub fn truncate_zero_pages(&mut self, keep: usize) -> usize {
+    let zero_page = crate::kinfo().get_zero_page_addr();
+    let keep = core::cmp::max(keep, self.image_pages);
+    let mut n = 0;
+    while self.page_count() > keep && self.pages.last() == Some(&zero_page) {
+      self.pages.pop();
+      n += 1;
+    }
+    n
+  }
   pub fn offset(&self) -> u32 {
     self.first_page_offset
   }
@@ -1,18 +1,26 @@
+mod caps;
 mod handles;
 mod memory;
+mod signal;
 mod state;
 mod task;
 
 use alloc::sync::Arc;
-use core::cell::RefCell;
 use crate::process_manager::handles::TaskHandleRegistry;
+pub use crate::process_manager::caps::{CapError, CapTable, Rights};
 pub use crate::process_manager::handles::{Handle, TaskHandle};
 pub use crate::process_manager::memory::{
-  Memory, MemoryKernel, MemoryKernelRef, MemoryUser, MemoryUserRef,
+  Memory, MemoryConsistencyError, MemoryKernel, MemoryKernelRef, MemoryUser, MemoryUserRef,
+};
+pub use crate::process_manager::signal::{
+  Signal, TaskException, SIG_TASK_TERMINATED, dispatch_signals, dispatch_memory_pressure,
+  deliver_task_exception,
 };
 pub use crate::process_manager::state::State;
-pub use crate::process_manager::task::Task;
-use spin::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use crate::process_manager::task::{Status, Task};
+use spin::{Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use crate::bindriver::cpu::smp::cpu_index;
+use crate::common::MAX_CPUS;
 use core::mem::ManuallyDrop;
 
 #[derive(Clone)]
@@ -20,6 +28,11 @@ pub struct Userspace {
   scheduler: ManuallyDrop<Arc<RwLock<Scheduler>>>,
 }
 
+// the scheduler and every task are locked, the memory of a task is only
+// touched by the CPU running it
+unsafe impl Send for Userspace {}
+unsafe impl Sync for Userspace {}
+
 impl Userspace {
   pub fn new() -> Userspace {
     Userspace {
@@ -58,10 +71,20 @@ impl Userspace {
       })
       .expect("userspace enter needs lock acquire");
     self.in_scheduler_mut(|mut scheduler| {
-      scheduler.current_task = sched.1;
+      scheduler.set_current_task(sched.1);
     }).expect("require scheduler to enter userspace");
     unsafe { crate::process_manager::state::switch_to(sched.0, sched.1) }
   }
+  /// Enters the given task on the calling CPU, used by application processors
+  pub fn enter_task(&self, th: TaskHandle) -> ! {
+    debug!("entry into task {} on CPU {}", th, cpu_index());
+    let task = self.in_scheduler_mut_spin(|mut scheduler| {
+      assert!(!scheduler.is_running(th), "task {} is already running", th);
+      scheduler.set_current_task(th);
+      scheduler.resolve_th(th)
+    }).expect("entering task requires task to exist");
+    unsafe { crate::process_manager::state::switch_to(task, th) }
+  }
   pub fn yield_to(&self, th: Option<TaskHandle>) {
     use crate::common::yield_to;
     match th {
@@ -72,12 +95,13 @@ impl Userspace {
 }
 
 use alloc::string::String;
+use alloc::vec::Vec;
 
 #[derive(Clone)]
 pub struct Scheduler {
   treg: ManuallyDrop<Arc<RwLock<TaskHandleRegistry>>>,
   scheduler_thandle: TaskHandle,
-  current_task: TaskHandle,         //TODO: change for multi-CPU
+  current_task: [TaskHandle; MAX_CPUS],
   kernel_stack: Arc<RwLock<Memory>>, //TODO: handle multiple kernel stacks
 }
 
@@ -87,26 +111,54 @@ impl Scheduler {
     let s = Scheduler {
       treg: ManuallyDrop::new(Arc::new(RwLock::new(TaskHandleRegistry::new()))),
       scheduler_thandle: nulltask.me,
-      current_task: nulltask.me,
+      current_task: [nulltask.me; MAX_CPUS],
       kernel_stack: Arc::new(RwLock::new(Memory::new_kernelstack())),
     };
     s.insert_treg(nulltask);
     s
   }
+  /// Returns the task running on the calling CPU
   pub fn current_task(&self) -> TaskHandle {
-    self.current_task
+    self.current_task[cpu_index()]
+  }
+  /// Returns the task running on the given CPU
+  pub fn current_task_on(&self, cpu: usize) -> Option<TaskHandle> {
+    self.current_task.get(cpu).cloned()
   }
   pub fn set_current_task(&mut self, th: TaskHandle) {
-    self.current_task = th
+    self.current_task[cpu_index()] = th
+  }
+  /// Returns true if the task is running on any CPU
+  pub fn is_running(&self, th: TaskHandle) -> bool {
+    self.current_task.iter().any(|cur| *cur == th)
   }
   pub fn spawn_from_task(&mut self) -> Option<TaskHandle> {
     let task = self.resolve_th(self.current_task())?;
-    let new_task = (*task).borrow().spawn();
+    let mut task = task.lock();
+    let new_task = task.spawn();
     let new_task_th = new_task.me;
-    info!("new task spawned from {} to {}", (*task).borrow().me, new_task_th);
+    task.caps_mut().grant(new_task_th, Rights::all());
+    info!("new task spawned from {} to {}", task.me, new_task_th);
     Some(self.insert_treg(new_task))
   }
+  /// Registers a task running the given kernel function on a stack from
+  /// the kernel heap, it is supervised by the scheduler
+  #[cfg(test)]
+  pub fn new_kernelproc<S>(&mut self, name: S, entry: extern "C" fn() -> !) -> TaskHandle
+  where
+    S: Into<String>,
+  {
+    let th = TaskHandle::gen();
+    self.insert_treg(Task::new(State::new_kernelstate(entry), TaskHandle::zero(), name, th))
+  }
+  pub fn scheduler_task(&self) -> TaskHandle {
+    self.scheduler_thandle
+  }
+  /// Makes the task the scheduler, it holds all rights on every task
   pub fn register_scheduler(&mut self, th: TaskHandle) {
+    if let Some(task) = self.resolve_th(th) {
+      *task.lock().caps_mut() = CapTable::root();
+    }
     self.scheduler_thandle = th;
   }
   pub fn new_elfproc<S>(&mut self, name: S, f: &[u8]) -> Result<TaskHandle, ()>
@@ -123,26 +175,65 @@ impl Scheduler {
       Ok(th)
     }
   }
+  pub fn for_each_task(&self, mut run: impl FnMut(&mut Task)) {
+    for (_, task) in (*self.treg).read().iter() {
+      run(&mut task.lock());
+    }
+  }
+  /// Runs the closure for every task, tasks locked elsewhere are passed as
+  /// None. Does nothing if the registry is locked, usable from exception handlers
+  pub fn try_for_each_task(&self, mut run: impl FnMut(TaskHandle, Option<&Task>)) {
+    if let Some(treg) = (*self.treg).try_read() {
+      for (th, task) in treg.iter() {
+        match task.try_lock() {
+          Some(task) => run(*th, Some(&task)),
+          None => run(*th, None),
+        }
+      }
+    }
+  }
   fn insert_treg(&self, t: Task) -> TaskHandle{
     let me = t.me;
     (*self.treg).write().insert(me, t);
     me
   }
-  pub fn resolve_th(&self, th: TaskHandle) -> Option<Arc<RefCell<Task>>> {
+  pub fn resolve_th(&self, th: TaskHandle) -> Option<Arc<Mutex<Task>>> {
     (*self.treg)
       .read()
       .resolve(th)
       .and_then(|x| Some((*x).clone()))
   }
-  // yield_to will save the current process and task context and then
-  // call yield_stage2 with the given process handle
-  // this function will be called by the scheduler
-  pub fn yield_to(&self, cur: TaskHandle, th: Option<TaskHandle>) {
+  /// Drops destroyed tasks from the registry once no CPU runs them or
+  /// has their memory active, tasks locked elsewhere are kept until the next call
+  pub fn reap_destroyed(&self) {
+    use self::task::Status;
+    let mut treg = (*self.treg).write();
+    let destroyed: Vec<TaskHandle> = treg.iter()
+      .filter(|(th, _)| !self.is_running(**th) && !crate::kinfo().is_current_task(**th))
+      .filter(|(_, task)| match task.try_lock() {
+        Some(task) => match task.status() {
+          Status::Destroyed => true,
+          _ => false,
+        },
+        None => false,
+      })
+      .map(|(th, _)| *th)
+      .collect();
+    for th in destroyed {
+      debug!("removing destroyed task {}", th);
+      treg.remove(th);
+    }
+  }
+  // prepare_yield looks up the tasks for a switch from cur to th, the
+  // caller performs the switch with switch_tasks once the scheduler is
+  // unlocked. Returns None if there is nothing to switch or the next
+  // task can not run
+  pub fn prepare_yield(&self, cur: TaskHandle, th: Option<TaskHandle>)
+    -> Option<(Arc<Mutex<Task>>, Arc<Mutex<Task>>)> {
     match th {
       None => {
         let sched = self.scheduler_thandle;
-        self.yield_to(cur, Some(sched));
-        //unsafe { self.yield_stage2_sched_internal(sched) };
+        self.prepare_yield(cur, Some(sched))
       }
       Some(th_in) => {
         let th;
@@ -153,39 +244,66 @@ impl Scheduler {
         } else {
           th = th_in;
         }
-        assert_eq!(th, self.current_task, "caller must update current task");
+        assert_eq!(th, self.current_task(), "caller must update current task");
         if th == cur {
           // if the task is already running, do nothing and return
-          return;
+          return None;
         }
-        let current_task: Arc<RefCell<Task>>;
-        let next_task: Arc<RefCell<Task>>;
+        let current_task: Arc<Mutex<Task>>;
+        let next_task: Arc<Mutex<Task>>;
         {
           let treg = (*self.treg).read();
           current_task = (treg.resolve(cur).expect("need current task")).clone();
-          next_task = (treg.resolve(th).expect("need next task")).clone();
+          next_task = match treg.resolve(th) {
+            Some(task) => task.clone(),
+            None => {
+              warn!("refusing to yield to unknown task {}", th);
+              return None;
+            }
+          };
         }
         use self::task::Status;
-        let status;
-        {
-          let nt = next_task.borrow();
-          status = nt.status();
-          drop(nt);
-        }
+        let status = next_task.lock().status();
         debug!("Got next and current task, switching context");
         match status {
-          Status::New => {
-            debug!("Doing switch...");
-            trace!("grabbing current task");
-            let mut ct = current_task.borrow_mut();
-            trace!("grabbing next task");
-            let mut nt = next_task.borrow_mut();
-            trace!("executing switch");
-            ct.switch_to(&mut nt);
-          }
+          Status::New | Status::Runnable => Some((current_task, next_task)),
+          Status::Destroyed => {
+            warn!("refusing to yield to destroyed task {}", th);
+            None
+          },
           _ => panic!("TODO: implement resuming tasks"),
         }
       }
     }
   }
 }
+
+/// Saves the current task and continues the next one, returns when the
+/// current task is switched back in. Neither task is locked while the next
+/// task runs, the state of a task is only touched by the CPU running it
+pub unsafe fn switch_tasks(current: &Mutex<Task>, next: &Mutex<Task>) {
+  use self::task::Status;
+  let (next_state, fresh): (*mut State, bool) = {
+    let mut next = next.lock();
+    let fresh = match next.status {
+      Status::New => true,
+      _ => false,
+    };
+    next.status = Status::Running;
+    // the page fault handler and memory kcalls act on the running task
+    next.state().make_current(next.me);
+    (next.state_mut() as *mut State, fresh)
+  };
+  let (current_state, current_th): (*mut State, TaskHandle) = {
+    let mut current = current.lock();
+    // a terminated task switches away for the last time
+    if let Status::Destroyed = current.status {} else {
+      current.status = Status::Runnable;
+    }
+    (current.state_mut() as *mut State, current.me)
+  };
+  trace!("performing state switch");
+  (*current_state).switch_to(&mut *next_state, fresh);
+  trace!("returned from state restore");
+  (*current_state).make_current(current_th);
+}
@@ -0,0 +1,181 @@
+use crate::process_manager::TaskHandle;
+use crate::vmem::MemoryPressure;
+use crate::*;
+
+/// Signal handlers are registered by the task through bos_set_sig_handler
+/// and are called with the signal number, the signal argument and details
+pub type SignalHandler = extern "C" fn(u64, u64, u128) -> u64;
+
+pub const SIG_MEMORY_PRESSURE: u64 = 0x10;
+pub const SIG_EXCEPTION: u64 = 0x20;
+pub const SIG_TASK_TERMINATED: u64 = 0x21;
+
+/// A CPU exception raised by task code
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TaskException {
+  pub vector: u8,
+  pub error_code: u64,
+  /// faulting address for page faults, the instruction address otherwise
+  pub address: u64,
+  pub rip: u64,
+  pub rsp: u64,
+}
+
+impl TaskException {
+  /// Vector in the low byte, error code in the high 32 bits
+  pub fn info(&self) -> u64 {
+    self.vector as u64 | self.error_code << 32
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Signal {
+  /// Free memory crossed a watermark, carries the new pressure level
+  MemoryPressure(MemoryPressure),
+  /// The task raised a CPU exception, the handler returns the address
+  /// to continue at or 0 to terminate the task
+  Exception(TaskException),
+  /// A supervised task was terminated after an unhandled exception
+  TaskTerminated(TaskHandle, TaskException),
+}
+
+impl Signal {
+  pub fn number(&self) -> u64 {
+    match self {
+      Signal::MemoryPressure(_) => SIG_MEMORY_PRESSURE,
+      Signal::Exception(_) => SIG_EXCEPTION,
+      Signal::TaskTerminated(_, _) => SIG_TASK_TERMINATED,
+    }
+  }
+  pub fn argument(&self) -> u64 {
+    match self {
+      Signal::MemoryPressure(level) => *level as u64,
+      Signal::Exception(exception) => exception.info(),
+      Signal::TaskTerminated(_, exception) => exception.info(),
+    }
+  }
+  pub fn detail(&self) -> u128 {
+    match self {
+      Signal::MemoryPressure(_) => 0,
+      Signal::Exception(exception) => exception.address as u128,
+      Signal::TaskTerminated(th, _) => th.into_c(),
+    }
+  }
+}
+
+/// Runs the signal handler of the given task for all queued signals
+/// This must be called while the task is mapped and running
+pub fn dispatch_signals(th: TaskHandle) {
+  let pending = with_task_mut(th, |task| {
+    match task {
+      None => None,
+      Some(mut task) => {
+        let handler = task.state().signal_handler();
+        Some((handler, task.take_signals()))
+      }
+    }
+  });
+  let (handler, signals) = match pending {
+    Ok(Some(pending)) => pending,
+    _ => return,
+  };
+  if signals.is_empty() {
+    return;
+  }
+  if handler == 0 {
+    debug!("task {} has no signal handler, dropping {} signals", th, signals.len());
+    return;
+  }
+  let handler: SignalHandler = unsafe { core::mem::transmute(handler) };
+  for signal in signals {
+    trace!("delivering {:?} to {}", signal, th);
+    handler(signal.number(), signal.argument(), signal.detail());
+  }
+}
+
+/// Queues a memory pressure signal on all tasks that asked for it
+/// if the pressure level changed since the last call
+pub fn dispatch_memory_pressure() {
+  let level = match pager().take_pressure_event() {
+    None => return,
+    Some(level) => level,
+  };
+  warn!("memory pressure changed to {:?}, {} pages free", level, pager().free_memory());
+  userspace().in_scheduler_spin(|sched| {
+    sched.for_each_task(|task| {
+      if task.notify_mem_pressure() {
+        task.queue_signal(Signal::MemoryPressure(level));
+      }
+    })
+  });
+}
+
+/// Where the task continues after its exception handler returned
+#[repr(C)]
+pub struct ExceptionResume {
+  rip: u64,
+  rsp: u64,
+}
+
+/// Entered through the exception trampoline on the stack of the faulting task
+/// Runs the signal handler of the task; if there is none or it declines,
+/// the task is terminated and its supervisor notified.
+#[no_mangle]
+pub extern "C" fn deliver_task_exception() -> ExceptionResume {
+  let exception = kinfo().local().take_exception()
+    .expect("exception trampoline entered without exception");
+  let th = current_taskhandle().unwrap_or(TaskHandle::zero());
+  warn!("task {} raised exception {} (error {:#x}) at {:#018x}, address {:#018x}",
+    th, exception.vector, exception.error_code, exception.rip, exception.address);
+  let handler = with_task(th, |task| {
+    task.map(|task| task.state().signal_handler()).unwrap_or(0)
+  }).unwrap_or(0);
+  if handler != 0 {
+    let handler: SignalHandler = unsafe { core::mem::transmute(handler) };
+    let signal = Signal::Exception(exception);
+    let resume = handler(signal.number(), signal.argument(), signal.detail());
+    if resume != 0 {
+      debug!("task {} resumes at {:#018x}", th, resume);
+      return ExceptionResume { rip: resume, rsp: exception.rsp };
+    }
+  }
+  terminate_task(th, exception)
+}
+
+fn terminate_task(th: TaskHandle, exception: TaskException) -> ! {
+  use super::task::Status;
+  error!("terminating task {} after exception {}", th, exception.vector);
+  let supervisor = with_task_mut(th, |task| {
+    task.map(|mut task| {
+      task.status = Status::Destroyed;
+      task.supervisor
+    })
+  }).ok().and_then(|supervisor| supervisor);
+  let supervisor = supervisor.map(|supervisor| {
+    if supervisor.is_scheduler() {
+      userspace().in_scheduler_spin(|sched| sched.scheduler_task())
+    } else {
+      supervisor
+    }
+  });
+  if let Some(supervisor) = supervisor.filter(|supervisor| *supervisor != th) {
+    let queued = with_task_mut(supervisor, |task| {
+      task.map(|mut task| task.queue_signal(Signal::TaskTerminated(th, exception))).is_some()
+    }).unwrap_or(false);
+    if queued {
+      yield_to(supervisor.into_c());
+    }
+  }
+  // yielding returns only if the task can not run, the scheduler takes over
+  let scheduler = userspace().in_scheduler_spin(|sched| sched.scheduler_task());
+  if scheduler != th {
+    yield_to(scheduler.into_c());
+  }
+  error!("no task can run after {} terminated, CPU {} returns to the null task",
+    th, crate::bindriver::cpu::smp::cpu_index());
+  userspace().in_scheduler_mut_spin(|mut sched| sched.set_current_task(TaskHandle::zero()));
+  let none = super::Memory::new_nomemory();
+  kinfo().set_current_task(TaskHandle::zero());
+  kinfo().set_task_memory(&none, &none, &none);
+  crate::bindriver::cpu::smp::idle()
+}
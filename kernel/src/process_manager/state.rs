@@ -1,4 +1,4 @@
-use core::cell::RefCell;
+use spin::Mutex;
 use crate::VirtAddr;
 use crate::process_manager::TaskHandle;
 use crate::process_manager::memory::Memory;
@@ -30,6 +30,8 @@ pub struct State {
   signalrecv: usize, // Handle for Task Signals
   //TODO: make atomic
   killh: usize, // Run this handler when we kill the task
+  #[cfg(feature = "fpu")]
+  fpu: crate::bindriver::cpu::fpu::FpuState,
 }
 
 fn null_fn() {
@@ -67,15 +69,25 @@ impl State {
       data.load_at(crate::vmem::CODE_START.try_into().unwrap());
     }
 
-    {
+    let bss_pages = {
       use core::convert::TryInto;
       debug!("loading data memory");
       let data = loader.data();
+      let mem_pages = data.mem_pages();
       data.load_at(crate::vmem::DATA_START.try_into().unwrap());
-    }
+      mem_pages.saturating_sub(data_memory.page_count())
+    };
 
     code_memory.unmap();
     data_memory.unmap();
+    if bss_pages > 0 {
+      // bss is backed by the zero page until the task writes to it
+      debug!("reserving {} zero pages for bss", bss_pages);
+      data_memory.add_zero_pages(bss_pages);
+    }
+    // released image pages stay mapped to the zero page, the task may
+    // access them in any order
+    data_memory.mark_image_end();
     crate::kinfo_mut().mapping_task_image(Some(false));
     crate::kinfo_mut().set_memory_ref(&old_code_memory);
     crate::kinfo_mut().set_memory_ref(&old_data_memory);
@@ -93,6 +105,8 @@ impl State {
       signalrecv: 0,
       killh: 0,
       page_limit: DEFAULT_PAGE_LIMIT,
+      #[cfg(feature = "fpu")]
+      fpu: crate::bindriver::cpu::fpu::FpuState::new(),
     };
     Ok(s)
   }
@@ -110,6 +124,32 @@ impl State {
       signalrecv: 0,
       killh: 0,
       page_limit: DEFAULT_PAGE_LIMIT,
+      #[cfg(feature = "fpu")]
+      fpu: crate::bindriver::cpu::fpu::FpuState::new(),
+    }
+  }
+  /// A state running kernel code on a stack from the kernel heap, the
+  /// stack is never freed
+  #[cfg(test)]
+  pub fn new_kernelstate(entry: extern "C" fn() -> !) -> State {
+    const STACK_SIZE: usize = 4 * crate::vmem::PAGE_SIZE;
+    let stack = alloc::boxed::Box::into_raw(alloc::vec![0u8; STACK_SIZE].into_boxed_slice());
+    // entering a task moves the stack pointer up by 8 bytes
+    let top = (stack as *mut u8 as usize + STACK_SIZE - 64) & !0xF;
+    State {
+      active: false,
+      mode: CPUMode::Kernel,
+      rip: VirtAddr::new(entry as usize as u64),
+      stack: Memory::new_nomemory(),
+      data: Memory::new_nomemory(),
+      code: Memory::new_nomemory(),
+      rsp: top,
+      rbp: top,
+      signalrecv: 0,
+      killh: 0,
+      page_limit: DEFAULT_PAGE_LIMIT,
+      #[cfg(feature = "fpu")]
+      fpu: crate::bindriver::cpu::fpu::FpuState::new(),
     }
   }
   pub fn mode(&self) -> CPUMode {
@@ -143,6 +183,19 @@ impl State {
     trace!("unmapping code memory");
     self.code.unmap();
   }
+  /// Cross-checks stack, data and code memory against the page tables,
+  /// the state must be mapped
+  pub fn check_memory(&self) -> Result<usize, crate::process_manager::MemoryConsistencyError> {
+    Ok(self.stack.check_consistency()?
+      + self.data.check_consistency()?
+      + self.code.check_consistency()?)
+  }
+  /// Makes the task with this state the one running on the calling CPU
+  pub fn make_current(&self, th: TaskHandle) {
+    let kinfo = crate::kinfo();
+    kinfo.set_current_task(th);
+    kinfo.set_task_memory(&self.code, &self.data, &self.stack);
+  }
   pub fn rip(&self) -> u64 {
     self.rip.as_u64()
   }
@@ -159,8 +212,17 @@ impl State {
   pub fn page_limit(&self) -> u64 {
     self.page_limit as u64
   }
+  pub fn set_signal_handler(&mut self, f: usize) -> usize {
+    core::mem::replace(&mut self.signalrecv, f)
+  }
+  pub fn signal_handler(&self) -> usize {
+    self.signalrecv
+  }
+  /// Saves the registers of this state and continues the next state, a
+  /// fresh state has never run and is started at its entry point instead.
+  /// Returns when this state is switched back in
   #[inline(never)]
-  pub fn switch_to(&mut self, next: &mut State) {
+  pub fn switch_to(&mut self, next: &mut State, fresh: bool) {
     //todo: switch to kernel stack
     debug!("Switching context");
     unsafe {
@@ -168,6 +230,13 @@ impl State {
       next.active = true;
       self.unmap();
       next.map();
+      // the kernel does not use the FPU, the registers still hold the
+      // state of the outgoing task
+      #[cfg(feature = "fpu")]
+      {
+        self.fpu.save();
+        next.fpu.restore();
+      }
       debug!("Bye!");
       asm!(
       "
@@ -191,6 +260,11 @@ impl State {
       :::"memory": "intel", "volatile"
     );
       asm!("mov $0, rsp": "=r"(self.rsp) : : "memory": "intel", "volatile");
+      if fresh {
+        asm!("mov $0, rbp": "=r"(self.rbp) : : "memory": "intel", "volatile");
+        let symrfp = crate::process_environment::symrf as *mut u8;
+        enter(next.rip(), next.rsp(), next.rbp(), symrfp);
+      }
       asm!("mov rsp, $0": : "r"(next.rsp) : "memory" : "intel", "volatile");
       asm!("mov $0, rbp": "=r"(self.rbp) : : "memory": "intel", "volatile");
       asm!("mov rbp, $0": : "r"(next.rbp) : "memory" : "intel", "volatile");
@@ -222,31 +296,44 @@ impl State {
 
 use alloc::sync::Arc;
 
-pub unsafe fn switch_to(next_task: Arc<RefCell<crate::process_manager::Task>>, nt_handle: TaskHandle) -> ! {
-  if next_task.borrow().state_is_null() {
+pub unsafe fn switch_to(next_task: Arc<Mutex<crate::process_manager::Task>>, nt_handle: TaskHandle) -> ! {
+  if next_task.lock().state_is_null() {
     panic!("attempted to run null state");
   }
   {
-    let next_task = next_task.borrow();
+    let next_task = next_task.lock();
     let state = next_task.state();
-    let kinfo = crate::kinfo_mut();
+    // per-CPU data is atomic, other CPUs may hold the write lock
+    let kinfo = crate::kinfo();
     let set_switching_tasks = kinfo.set_switching_tasks(false, true);
     let current_task_handle = kinfo.swap_current_task(0.into(), nt_handle);
     let current_task_handle = current_task_handle.expect("could not swap process on kinfo init");
     assert_eq!(set_switching_tasks, false, "enter userspace only outside task switching");
     assert_eq!(current_task_handle.into_c(), 0, "enter userspace from no running tasks");
-    kinfo.set_memory_ref(&state.code);
-    kinfo.set_memory_ref(&state.stack);
-    kinfo.set_memory_ref(&state.data);
+    kinfo.set_task_memory(&state.code, &state.data, &state.stack);
   }
-  let rip = (next_task.borrow()).rip();
-  let rsp = (next_task.borrow()).rsp();
-  let rbp = (next_task.borrow()).rbp();
+  let (rip, rsp, rbp) = {
+    let next_task = next_task.lock();
+    (next_task.rip(), next_task.rsp(), next_task.rbp())
+  };
   let symrfp = crate::process_environment::symrf as *mut u8;
   trace!("symrfp at {:#018x}", symrfp as u64);
   trace!("mapping task memory");
-  next_task.borrow().map();
+  next_task.lock().map();
+  // start the task with its own, clean FPU state
+  #[cfg(feature = "fpu")]
+  next_task.lock().state_mut().fpu.restore();
+  #[cfg(debug_assertions)]
+  match next_task.lock().state().check_memory() {
+    Ok(pages) => trace!("task memory consistent, {} pages", pages),
+    Err(e) => error!("task memory inconsistent with page tables: {:?}", e),
+  }
   trace!("switch to task with rip = {:#018x}", rip);
+  enter(rip, rsp, rbp, symrfp)
+}
+
+// enter starts a task that has never run at its entry point
+unsafe fn enter(rip: u64, rsp: u64, rbp: u64, symrfp: *mut u8) -> ! {
   asm!(
     "
     mov rsp, $0
@@ -50,8 +50,10 @@ impl<'a> StateLoader<'a> for ElfLoader<'a> {
     let phs = self.elf.program_headers.clone();
     let mut first = None;
     let mut last = 0;
+    let mut mem_end = 0;
     for ph in phs {
       if ElfLoader::is_code(&ph) {
+        mem_end = core::cmp::max(mem_end, ph.vm_range().end - crate::vmem::CODE_START);
         if first.is_none() && ElfLoader::is_nonstd_data_start(&ph) {
           first = Some(ph.file_range().start as u64);
         }
@@ -64,15 +66,17 @@ impl<'a> StateLoader<'a> for ElfLoader<'a> {
         data.extend(&self.data[ph.file_range()]);
       }
     }
-    Section::new(first, data.into_boxed_slice())
+    Section::new(first, data.into_boxed_slice(), mem_end as u64)
   }
   fn data(&self) -> Section {
     let mut data = Vec::new();
     let phs = self.elf.program_headers.clone();
     let mut first = None;
     let mut last = 0;
+    let mut mem_end = 0;
     for ph in phs {
       if ElfLoader::is_data(&ph) {
+        mem_end = core::cmp::max(mem_end, ph.vm_range().end - crate::vmem::DATA_START);
         if first.is_none() && ElfLoader::is_nonstd_data_start(&ph) {
           trace!("skipping first {:#010x} bytes", ph.file_range().start);
           first = Some(ph.file_range().start as u64);
@@ -87,7 +91,7 @@ impl<'a> StateLoader<'a> for ElfLoader<'a> {
         data.extend(&self.data[ph.file_range()]);
       }
     }
-    Section::new(first, data.into_boxed_slice())
+    Section::new(first, data.into_boxed_slice(), mem_end as u64)
   }
   fn entry(&self) -> u64 {
     self.elf.entry
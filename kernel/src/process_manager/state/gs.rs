@@ -5,6 +5,8 @@ use alloc::boxed::Box;
 pub struct Section {
   start: Option<u64>,
   data: Box<[u8]>,
+  // size of the section in memory relative to the base, including bss
+  mem_size: u64,
 }
 
 pub trait StateLoader<'a> {
@@ -16,8 +18,16 @@ pub trait StateLoader<'a> {
 }
 
 impl Section {
-  pub fn new(start: Option<u64>, data: Box<[u8]>) -> Self {
-    Self{start, data}
+  pub fn new(start: Option<u64>, data: Box<[u8]>, mem_size: u64) -> Self {
+    Self{start, data, mem_size}
+  }
+  /// Returns the number of pages the section occupies in memory,
+  /// including zero-initialized memory not present in the image
+  pub fn mem_pages(&self) -> usize {
+    let loaded = self.start.unwrap_or(0) + self.data.len() as u64;
+    let size = if loaded > self.mem_size { loaded } else { self.mem_size };
+    let page_size = crate::vmem::PAGE_SIZE as u64;
+    ((size + page_size - 1) / page_size) as usize
   }
   fn pre_touch(base: u64, start: Option<u64>) {
     match start {
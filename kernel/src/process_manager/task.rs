@@ -1,5 +1,9 @@
 use crate::process_manager::TaskHandle;
 use crate::process_manager::state::State;
+use crate::process_manager::signal::Signal;
+use crate::process_manager::caps::{CapTable, Rights};
+use crate::common::klog::RateLimit;
+use alloc::collections::VecDeque;
 use crate::alloc::string::String;
 use crate::alloc::string::ToString;
 
@@ -11,6 +15,10 @@ pub struct Task {
   pub supervisor: TaskHandle,
   pub me: TaskHandle,
   name: String,
+  signals: VecDeque<Signal>,
+  notify_mem_pressure: bool,
+  caps: CapTable,
+  log_limit: RateLimit,
 }
 
 impl Task {
@@ -22,6 +30,10 @@ impl Task {
       supervisor: parent,
       me,
       name: name.into(),
+      signals: VecDeque::new(),
+      notify_mem_pressure: false,
+      caps: Task::initial_caps(me, parent),
+      log_limit: RateLimit::new(),
     }
   }
   pub fn new_task_from_elf<S>(f: &[u8], name: S, me: TaskHandle) -> Task where S: Into<String> {
@@ -33,6 +45,10 @@ impl Task {
       supervisor: TaskHandle::zero(),
       me,
       name: name.into(),
+      signals: VecDeque::new(),
+      notify_mem_pressure: false,
+      caps: Task::initial_caps(me, TaskHandle::zero()),
+      log_limit: RateLimit::new(),
     }
   }
   pub fn new_task<S>(image: &[u8], name: S, me: TaskHandle) -> Task where S: Into<String> {
@@ -46,19 +62,57 @@ impl Task {
       supervisor: TaskHandle::zero(),
       me: TaskHandle::zero(),
       name: "null()".to_string(),
+      signals: VecDeque::new(),
+      notify_mem_pressure: false,
+      caps: CapTable::new(),
+      log_limit: RateLimit::new(),
     }
   }
+  /// A task holds all rights on itself and may yield to and signal its parent
+  fn initial_caps(me: TaskHandle, parent: TaskHandle) -> CapTable {
+    let mut caps = CapTable::new();
+    caps.grant(me, Rights::all());
+    caps.grant(parent, Rights::YIELD | Rights::SIGNAL);
+    caps
+  }
   /// Copies the current task and state into a new, inactive task
+  /// The capabilities are not copied, the new task starts with its initial set.
   pub fn spawn(&self) -> Task {
+    let me = TaskHandle::gen();
     Task {
       state: self.state.clone(),
       status: Status::New,
       parent: self.me.clone(),
       supervisor: self.me.clone(),
-      me: TaskHandle::gen(),
+      me,
       name: self.name.clone(),
+      signals: VecDeque::new(),
+      notify_mem_pressure: self.notify_mem_pressure,
+      caps: Task::initial_caps(me, self.me),
+      log_limit: RateLimit::new(),
     }
   }
+  pub fn caps(&self) -> &CapTable {
+    &self.caps
+  }
+  pub fn caps_mut(&mut self) -> &mut CapTable {
+    &mut self.caps
+  }
+  pub fn log_limit_mut(&mut self) -> &mut RateLimit {
+    &mut self.log_limit
+  }
+  pub fn queue_signal(&mut self, sig: Signal) {
+    self.signals.push_back(sig)
+  }
+  pub fn take_signals(&mut self) -> VecDeque<Signal> {
+    core::mem::replace(&mut self.signals, VecDeque::new())
+  }
+  pub fn notify_mem_pressure(&self) -> bool {
+    self.notify_mem_pressure
+  }
+  pub fn set_notify_mem_pressure(&mut self, v: bool) {
+    self.notify_mem_pressure = v
+  }
   pub fn name(&self) -> String {
     self.name.clone()
   }
@@ -86,28 +140,26 @@ impl Task {
   pub fn status(&self) -> Status {
     self.status
   }
+  /// Marks a new task as running so only one CPU starts it,
+  /// returns false if the task has been started before
+  pub fn claim_start(&mut self) -> bool {
+    match self.status {
+      Status::New => {
+        self.status = Status::Running;
+        true
+      }
+      _ => false,
+    }
+  }
   pub fn map(&self) {
     self.state.map()
   }
   pub fn state_is_null(&self) -> bool {
     self.state.mode() == crate::process_manager::state::CPUMode::Null
   }
-  pub fn switch_to(&mut self, next: &mut Task) {
-    trace!("state switch imminent, hold onto your hooves");
-    if self.me == next.me {
-      trace!("yield to self, returning");
-      return;
-    }
-    trace!("updating task status");
-    self.status = Status::Runnable;
-    next.status = Status::Running;
-    trace!("performing state switch");
-    self.state.switch_to(next.state_mut());
-    trace!("returned from state restore");
-  }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Debug, Copy, Clone)]
 pub enum Status {
   New, // Task is new and not yet started
   Running,
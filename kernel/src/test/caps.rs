@@ -0,0 +1,18 @@
+#[test_case]
+fn test_cap_table_rights() {
+  use crate::process_manager::{CapError, CapTable, Rights, TaskHandle};
+  let target = TaskHandle::from_c(0x1234);
+  let mut caps = CapTable::new();
+  assert_eq!(caps.check(target, Rights::YIELD), Err(CapError::NoCapability(target)));
+  // the scheduler can always be yielded to, nothing else
+  assert!(caps.check(TaskHandle::zero(), Rights::YIELD).is_ok());
+  assert!(caps.check(TaskHandle::zero(), Rights::DESTROY).is_err());
+  caps.grant(target, Rights::YIELD | Rights::INSPECT);
+  assert!(caps.check(target, Rights::YIELD | Rights::INSPECT).is_ok());
+  assert_eq!(caps.check(target, Rights::YIELD | Rights::DESTROY),
+    Err(CapError::MissingRights { target, missing: Rights::DESTROY }));
+  assert_eq!(caps.restrict(target, Rights::INSPECT), Rights::YIELD);
+  assert_eq!(caps.restrict(target, Rights::YIELD), Rights::empty());
+  assert_eq!(caps.iter().count(), 0);
+  assert!(CapTable::root().check(target, Rights::all()).is_ok());
+}
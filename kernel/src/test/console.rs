@@ -0,0 +1,63 @@
+use crate::bindriver::serial::console::*;
+
+#[test_case]
+fn test_console_line_buffer() {
+  let mut input = LineBuffer::new();
+  let mut buf = [0u8; 16];
+  for byte in b"ls -x\x08l\rpartial" {
+    input.push(*byte);
+  }
+  assert_eq!(input.read(&mut buf), 6);
+  assert_eq!(&buf[..6], b"ls -l\n");
+  assert_eq!(input.read(&mut buf), 0, "unfinished line was returned");
+  input.push(b'\n');
+  assert_eq!(input.read(&mut buf[..4]), 4);
+  assert_eq!(&buf[..4], b"part");
+  assert_eq!(input.read(&mut buf), 4);
+  assert_eq!(&buf[..4], b"ial\n");
+}
+
+#[test_case]
+fn test_console_line_buffer_full() {
+  let mut input = LineBuffer::new();
+  for _ in 0..INPUT_BUFFER_SIZE + 1 {
+    input.push(b'a');
+  }
+  assert_eq!(input.overrun(), 1);
+  let mut buf = [0u8; INPUT_BUFFER_SIZE];
+  assert_eq!(input.read(&mut buf), INPUT_BUFFER_SIZE, "full buffer must be readable");
+}
+
+#[test_case]
+fn test_console_receive() {
+  use x86_64::instructions::interrupts::without_interrupts;
+  use x86_64::instructions::port::Port;
+  use crate::bindriver::serial::polled::Com;
+  // COM1 in loopback mode receives what it sends, nothing may log meanwhile
+  const COM1: u16 = 0x3F8;
+  const MODEM_CONTROL_LOOPBACK: u8 = 1 << 4;
+  let mut buf = [0u8; 16];
+  let n = without_interrupts(|| {
+    let mut com = Com::new(COM1);
+    let mut modem_control: Port<u8> = Port::new(COM1 + 4);
+    let mut line_status: Port<u8> = Port::new(COM1 + 5);
+    let old = unsafe { modem_control.read() };
+    unsafe { modem_control.write(old | MODEM_CONTROL_LOOPBACK) };
+    // end and drop whatever was typed before
+    let mut send = |bytes: &[u8]| for byte in bytes {
+      com.write(*byte);
+      for _ in 0..100_000 {
+        if unsafe { line_status.read() } & 1 != 0 {
+          break;
+        }
+      }
+      receive();
+    };
+    send(b"\n");
+    while read(&mut buf, false) > 0 {}
+    send(b"echo\x08o hi\r");
+    unsafe { modem_control.write(old) };
+    read(&mut buf, false)
+  });
+  assert_eq!(&buf[..n], b"echo hi\n");
+}
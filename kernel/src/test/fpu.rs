@@ -0,0 +1,62 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+
+// the kernel is built with soft-float, the SSE math is done in assembly
+fn add_xmm0(value: f64) {
+  unsafe {
+    asm!("movq xmm1, $0; addsd xmm0, xmm1" :: "r"(value.to_bits()) : "xmm1" : "intel", "volatile");
+  }
+}
+
+fn set_xmm0(value: f64) {
+  unsafe { asm!("movq xmm0, $0" :: "r"(value.to_bits()) :: "intel", "volatile") };
+}
+
+fn read_xmm0() -> f64 {
+  let bits: u64;
+  unsafe { asm!("movq $0, xmm0" : "=r"(bits) ::: "intel", "volatile") };
+  f64::from_bits(bits)
+}
+
+// handles of the two tasks, the first yields to the second and back
+static PEERS: Mutex<[u128; 2]> = Mutex::new([0; 2]);
+static SUMS: [AtomicU64; 2] = [AtomicU64::new(0), AtomicU64::new(0)];
+const STEPS: [[f64; 4]; 2] = [[1.5, 2.25, 3.0, 0.25], [100.0, -10.5, 0.125, 8.0]];
+
+fn accumulate(me: usize) {
+  let peer = PEERS.lock()[1 - me];
+  for step in STEPS[me].iter() {
+    add_xmm0(*step);
+    crate::common::yield_to(peer);
+  }
+  SUMS[me].store(read_xmm0().to_bits(), Ordering::SeqCst);
+}
+
+extern "C" fn first_task() -> ! {
+  accumulate(0);
+  let peer = PEERS.lock()[1];
+  crate::common::yield_to(peer);
+  hlt_cpu!();
+}
+
+extern "C" fn second_task() -> ! {
+  accumulate(1);
+  // back to the test, neither task is resumed again
+  crate::common::yield_to(0);
+  hlt_cpu!();
+}
+
+#[test_case]
+fn test_fpu_state_switch() {
+  // two tasks accumulate sums in xmm0 and yield to each other after every step
+  super::init_userspace();
+  let (first, second) = crate::userspace().in_scheduler_mut_spin(|mut sched| {
+    (sched.new_kernelproc("fpu0", first_task), sched.new_kernelproc("fpu1", second_task))
+  });
+  *PEERS.lock() = [first.into_c(), second.into_c()];
+  set_xmm0(42.0);
+  crate::common::yield_to(first.into_c());
+  assert_eq!(f64::from_bits(SUMS[0].load(Ordering::SeqCst)), 7.0, "first task lost its FPU state");
+  assert_eq!(f64::from_bits(SUMS[1].load(Ordering::SeqCst)), 97.625, "second task lost its FPU state");
+  assert_eq!(read_xmm0(), 42.0, "test lost its FPU state");
+}
@@ -0,0 +1,16 @@
+#[test_case]
+fn test_code_write_faults() {
+  use crate::vmem::mapper::{map_new, unmap, get_flags, MapType};
+  use crate::vmem::pagelist::FrameOwner;
+  use x86_64::structures::paging::PageTableFlags;
+  use crate::*;
+  let vaddr = VirtAddr::new(crate::vmem::CODE_START as u64);
+  let page = map_new(vaddr, MapType::Code, FrameOwner::Task);
+  let flags = get_flags(vaddr).expect("code page must be mapped");
+  assert!(!flags.contains(PageTableFlags::WRITABLE), "code mapped writable: {:?}", flags);
+  kinfo().expect_fault(vaddr);
+  unsafe { core::ptr::write_volatile(vaddr.as_mut_ptr::<u8>(), 0xCC) };
+  assert!(!kinfo().fault_expected(), "write to code memory did not fault");
+  unmap(vaddr, 1, MapType::Code);
+  release_page(page).expect("test page must be released");
+}
@@ -0,0 +1,46 @@
+use crate::common::klog::*;
+
+#[test_case]
+fn test_klog_records_and_follows() {
+  let mut cursor = head();
+  info!("klog test record {}", 1);
+  info!("klog test record {}", 2);
+  let mut buf = [LogRecord::empty(); 4];
+  let n = read(&mut buf, &mut cursor);
+  assert!(n >= 2, "expected at least two new records, got {}", n);
+  let ours: alloc::vec::Vec<&LogRecord> = buf[..n].iter()
+    .filter(|rec| rec.message().starts_with("klog test record"))
+    .collect();
+  assert_eq!(ours.len(), 2);
+  assert_eq!(ours[0].message(), "klog test record 1");
+  assert_eq!(ours[0].level(), Some(log::Level::Info));
+  assert_eq!(ours[0].module(), "test::klog");
+  assert!(ours[0].seq < ours[1].seq);
+  assert_eq!(read(&mut buf, &mut cursor), 0, "cursor did not advance");
+}
+
+#[test_case]
+fn test_klog_overrun_skips_to_oldest() {
+  let mut cursor = 0;
+  for i in 0..LOG_SLOTS + 1 {
+    info!("klog overrun {}", i);
+  }
+  let mut buf = [LogRecord::empty(); 1];
+  assert_eq!(read(&mut buf, &mut cursor), 1);
+  assert_eq!(buf[0].seq, head() - LOG_SLOTS as u64);
+}
+
+#[test_case]
+fn test_task_log_rate_limit() {
+  let mut limit = RateLimit::new();
+  let start = crate::bindriver::cpu::tsc::now_ms();
+  for _ in 0..TASK_LOG_BURST {
+    assert_eq!(limit.admit(start), Some(0));
+  }
+  assert_eq!(limit.admit(start), None);
+  assert_eq!(limit.admit(start), None);
+  // one token is refilled after 1000 / TASK_LOG_RATE ms
+  let later = start + 1000 / TASK_LOG_RATE;
+  assert_eq!(limit.admit(later), Some(2), "suppressed messages not reported");
+  assert_eq!(limit.admit(later), None);
+}
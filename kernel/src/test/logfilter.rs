@@ -0,0 +1,61 @@
+use crate::bindriver::serial::filter::*;
+use log::LevelFilter;
+
+#[test_case]
+fn test_log_filter_levels() {
+  let spec = "warn,vmem=debug,vmem::mapper=error,task/pid0=trace";
+  let filter = Filter::parse(spec).expect("filter must parse");
+  assert_eq!(validate(spec), Ok(LevelFilter::Trace));
+  assert_eq!(filter.level("boringos::common::kinfo"), LevelFilter::Warn);
+  assert_eq!(filter.level("boringos::vmem"), LevelFilter::Debug);
+  assert_eq!(filter.level("boringos::vmem::faulth"), LevelFilter::Debug);
+  assert_eq!(filter.level("boringos::vmem::mapper"), LevelFilter::Error);
+  assert_eq!(filter.level("boringos::vmemx"), LevelFilter::Warn, "prefix must end at a separator");
+  assert_eq!(filter.level("task/pid0"), LevelFilter::Trace);
+  assert_eq!(filter.level("task/pid1"), LevelFilter::Warn);
+}
+
+#[test_case]
+fn test_log_filter_invalid() {
+  assert_eq!(validate("info,vmem=loud"), Err(FilterError::UnknownLevel));
+  assert_eq!(validate("=debug"), Err(FilterError::EmptyTarget));
+  assert_eq!(validate(DEFAULT_FILTER), Ok(LevelFilter::Trace));
+  let long = "vmem=info,".repeat(MAX_DIRECTIVES + 1);
+  assert_eq!(validate(&long), Err(FilterError::TooManyDirectives));
+  assert_eq!(validate(&"a".repeat(MAX_FILTER_LEN + 1)), Err(FilterError::TooLong));
+}
+
+#[test_case]
+fn test_log_filter_requires_right() {
+  use crate::process_environment::kcalls::bos_set_log_filter;
+  use crate::process_manager::{Rights, TaskHandle};
+  super::init_userspace();
+  let restrict = |revoke: bool| crate::with_current_task_mut(|task| {
+    let mut task = task.expect("need current task");
+    if revoke {
+      task.caps_mut().restrict(TaskHandle::zero(), Rights::LOG);
+    } else {
+      task.caps_mut().grant(TaskHandle::zero(), Rights::LOG);
+    }
+  }).expect("scheduler must not be locked");
+  let max = log::max_level();
+  restrict(true);
+  assert!(!bos_set_log_filter("trace"), "filter changed without the LOG right");
+  assert_eq!(log::max_level(), max);
+  restrict(false);
+}
+
+#[test_case]
+fn test_log_filter_changes_enabled() {
+  use crate::process_environment::kcalls::bos_set_log_filter;
+  use log::Level;
+  super::init_userspace();
+  let saved = active();
+  assert!(bos_set_log_filter("warn,vmem=trace"), "root task holds the LOG right");
+  assert!(!enabled("boringos::common::kinfo", Level::Info));
+  assert!(enabled("boringos::common::kinfo", Level::Warn));
+  assert!(enabled("boringos::vmem::mapper", Level::Trace));
+  assert!(bos_set_log_filter("error"));
+  assert!(!enabled("boringos::vmem::mapper", Level::Warn));
+  install(saved);
+}
@@ -1,4 +1,32 @@
 mod pagemap_ng;
+mod walker;
+mod rng;
+mod caps;
+mod logfilter;
+mod klog;
+mod console;
+mod power;
+mod zeropage;
+mod release;
+mod smp;
+mod terminate;
+#[cfg(feature = "hardening")]
+mod hardening;
+#[cfg(feature = "fpu")]
+mod fpu;
+
+/// Installs the userspace for tests that run tasks, the code running the
+/// tests becomes the null task and holds the rights of the scheduler
+pub fn init_userspace() {
+    use crate::process_manager::{TaskHandle, Userspace};
+    {
+        let mut us = crate::USERSPACE.write();
+        if us.is_none() {
+            *us = Some(Userspace::new());
+        }
+    }
+    crate::userspace().in_scheduler_mut_spin(|mut sched| sched.register_scheduler(TaskHandle::zero()));
+}
 
 #[cfg(test)]
 pub fn test_runner(tests: &[&dyn Fn()]) {
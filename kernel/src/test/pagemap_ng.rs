@@ -1,3 +1,18 @@
+#[test_case]
+fn test_pagemap_refcount() {
+  use crate::vmem::pagelist::FrameOwner;
+  let page = crate::alloc_page_owned(FrameOwner::Task).expect("need page for test");
+  assert_eq!(crate::pager().page_refcount(page).unwrap(), 1);
+  assert_eq!(crate::pager().page_owner(page).unwrap(), FrameOwner::Task);
+  assert_eq!(crate::ref_page(page).unwrap(), 2);
+  crate::release_page(page).expect("first release must succeed");
+  assert_eq!(crate::pager().page_refcount(page).unwrap(), 1);
+  crate::release_page(page).expect("last release must succeed");
+  assert_eq!(crate::pager().page_refcount(page).unwrap(), 0);
+  assert_eq!(crate::pager().page_owner(page).unwrap(), FrameOwner::Free);
+  assert!(crate::release_page(page).is_err(), "double free must fail");
+}
+
 
 #[test_case]
 fn test_pagemap_noalloc() {
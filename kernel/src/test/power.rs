@@ -0,0 +1,31 @@
+use crate::bindriver::acpi::AcpiTable;
+use crate::bindriver::acpi::dsdt::s5_sleep_types;
+
+fn dsdt(aml: &[u8]) -> AcpiTable {
+  let mut data = alloc::vec![0u8; 36];
+  data[0..4].copy_from_slice(b"DSDT");
+  data.extend_from_slice(aml);
+  AcpiTable::from_bytes(data).unwrap()
+}
+
+#[test_case]
+fn test_s5_sleep_types() {
+  // Name (\_S5, Package (0x04) { 0x05, 0x05, Zero, Zero }) as emitted by iasl
+  let table = dsdt(&[0x08, 0x5C, b'_', b'S', b'5', b'_', 0x12, 0x08, 0x04,
+    0x0A, 0x05, 0x0A, 0x05, 0x00, 0x00]);
+  assert_eq!(s5_sleep_types(&table), Some((5, 5)));
+  // SeaBIOS style without the root prefix and with ZeroOp, OneOp elements
+  let table = dsdt(&[0x08, b'_', b'S', b'5', b'_', 0x12, 0x06, 0x04, 0x00, 0x01, 0x00, 0x00]);
+  assert_eq!(s5_sleep_types(&table), Some((0, 1)));
+  // a method reference to _S5_ is not the package
+  let table = dsdt(&[0x14, b'_', b'S', b'5', b'_', 0x12, 0x06, 0x04, 0x0A, 0x05]);
+  assert_eq!(s5_sleep_types(&table), None);
+  // Method (_S5_, 0) {} ahead of Name (_S5_, Package (0x04) { 0x07, 0x07, Zero, Zero })
+  let table = dsdt(&[0x14, 0x06, b'_', b'S', b'5', b'_', 0x00,
+    0x08, b'_', b'S', b'5', b'_', 0x12, 0x08, 0x04, 0x0A, 0x07, 0x0A, 0x07, 0x00, 0x00]);
+  assert_eq!(s5_sleep_types(&table), Some((7, 7)));
+  // Scope (\_S5_) { Name (_S5_, Package (0x02) { One, One }) }
+  let table = dsdt(&[0x10, 0x10, 0x5C, b'_', b'S', b'5', b'_',
+    0x08, b'_', b'S', b'5', b'_', 0x12, 0x04, 0x02, 0x01, 0x01]);
+  assert_eq!(s5_sleep_types(&table), Some((1, 1)));
+}
@@ -0,0 +1,37 @@
+use crate::process_environment::kcalls::bos_release_pages;
+use crate::process_manager::Memory;
+use crate::vmem::{DATA_START, PAGE_SIZE};
+use crate::vmem::mapper::{is_mapped, is_zero_mapped};
+use crate::*;
+
+#[test_case]
+fn test_release_keeps_bss() {
+  // two pages of image data, three pages of bss and one heap page
+  let data = Memory::new_usermemory();
+  data.add_zero_pages(3);
+  data.mark_image_end();
+  let old_data = kinfo_mut().set_memory_ref(&data);
+  data.map();
+  let heap = VirtAddr::new((DATA_START + 5 * PAGE_SIZE) as u64);
+  let heap_page = alloc_page_owned(crate::vmem::pagelist::FrameOwner::Task).expect("need page for test");
+  crate::vmem::mapper::map(heap, &[heap_page], crate::vmem::mapper::MapType::Data);
+  kinfo().add_data_page(heap_page);
+
+  assert_eq!(bos_release_pages((DATA_START + PAGE_SIZE) as u64, 1), 1);
+  assert_eq!(data.page_count(), 6, "releasing an image page must not truncate");
+  // releasing the heap page drops it but keeps the bss in front of it
+  assert_eq!(bos_release_pages(heap.as_u64(), 1), 1);
+  assert_eq!(data.page_count(), 5);
+  assert!(!is_mapped(heap), "released heap page is still mapped");
+  for index in 1..5 {
+    let vaddr = VirtAddr::new((DATA_START + index * PAGE_SIZE) as u64);
+    assert!(is_zero_mapped(vaddr), "page {} lost its zero page", index);
+    assert_eq!(unsafe { core::ptr::read_volatile(vaddr.as_ptr::<u64>()) }, 0);
+  }
+  // releasing everything never truncates below the image
+  assert_eq!(bos_release_pages(DATA_START as u64, 16), 1);
+  assert_eq!(data.page_count(), 5);
+  data.check_consistency().expect("released memory must stay consistent");
+  data.unmap();
+  kinfo_mut().set_memory_ref(&old_data);
+}
@@ -0,0 +1,31 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+use crate::bindriver::cpu::smp::{cpu_index, online_cpus};
+use crate::process_environment::kcalls::bos_run_on;
+
+static RAN_ON: AtomicUsize = AtomicUsize::new(usize::max_value());
+
+extern "C" fn report_cpu() -> ! {
+  RAN_ON.store(cpu_index(), Ordering::SeqCst);
+  hlt_cpu!();
+}
+
+#[test_case]
+fn test_run_on_application_processor() {
+  if online_cpus() < 2 {
+    warn!("only one CPU online, not testing bos_run_on");
+    return;
+  }
+  super::init_userspace();
+  let th = crate::userspace().in_scheduler_mut_spin(|mut sched| sched.new_kernelproc("smp", report_cpu));
+  assert!(!bos_run_on(cpu_index() as u64, th.into_c()), "task must not be posted to the calling CPU");
+  assert!(bos_run_on(1, th.into_c()), "idle CPU 1 must take the task");
+  assert!(!bos_run_on(2, th.into_c()), "a task must only be started once");
+  for _ in 0..1000 {
+    if RAN_ON.load(Ordering::SeqCst) != usize::max_value() {
+      break;
+    }
+    crate::bindriver::cpu::pit::wait_ms(1);
+  }
+  assert_eq!(RAN_ON.load(Ordering::SeqCst), 1, "task did not run on CPU 1");
+  assert!(crate::userspace().in_scheduler_spin(|sched| sched.is_running(th)));
+}
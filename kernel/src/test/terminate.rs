@@ -0,0 +1,65 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use crate::process_manager::TaskHandle;
+use crate::vmem::mapper::{map_new, unmap, update_flags, MapType};
+use crate::vmem::pagelist::FrameOwner;
+use crate::*;
+
+static SIGNAL: AtomicU64 = AtomicU64::new(0);
+static TERMINATED: Mutex<u128> = Mutex::new(0);
+
+extern "C" fn record_signal(signal: u64, _argument: u64, detail: u128) -> u64 {
+  SIGNAL.store(signal, Ordering::SeqCst);
+  *TERMINATED.lock() = detail;
+  0
+}
+
+// runs the closure with a task that starts on a code page holding ud2
+fn with_ud2_task(run: impl FnOnce(TaskHandle)) {
+  super::init_userspace();
+  let vaddr = VirtAddr::new(crate::vmem::CODE_START as u64);
+  let page = map_new(vaddr, MapType::Data, FrameOwner::Task);
+  unsafe { core::ptr::copy_nonoverlapping([0x0Fu8, 0x0B].as_ptr(), vaddr.as_mut_ptr::<u8>(), 2) };
+  update_flags(vaddr, MapType::Code);
+  let entry: extern "C" fn() -> ! = unsafe { core::mem::transmute(vaddr.as_u64() as usize) };
+  let th = userspace().in_scheduler_mut_spin(|mut sched| sched.new_kernelproc("ud2", entry));
+  let old_handler = with_task_mut(TaskHandle::zero(), |task| {
+    task.expect("need null task").state_mut().set_signal_handler(record_signal as usize)
+  }).expect("scheduler must not be locked");
+  SIGNAL.store(0, Ordering::SeqCst);
+  run(th);
+  with_task_mut(TaskHandle::zero(), |task| {
+    task.expect("need null task").state_mut().set_signal_handler(old_handler)
+  }).expect("scheduler must not be locked");
+  unmap(vaddr, 1, MapType::Code);
+  release_page(page).expect("test page must be released");
+}
+
+#[test_case]
+fn test_exception_terminates_task() {
+  with_ud2_task(|th| {
+    yield_to(th.into_c());
+    assert_eq!(SIGNAL.load(Ordering::SeqCst), crate::process_manager::SIG_TASK_TERMINATED);
+    assert_eq!(*TERMINATED.lock(), th.into_c(), "signal names the wrong task");
+    assert!(userspace().in_scheduler_spin(|sched| sched.resolve_th(th)).is_none(),
+      "destroyed task stays registered");
+    // yielding to the removed task does not switch away
+    yield_to(th.into_c());
+    assert_eq!(current_taskhandle(), Ok(TaskHandle::zero()));
+    assert_eq!(kinfo().get_current_task(), TaskHandle::zero());
+  });
+}
+
+#[test_case]
+fn test_terminated_task_without_supervisor() {
+  with_ud2_task(|th| {
+    // the supervisor is gone, the scheduler takes over without a signal
+    with_task_mut(th, |task| task.expect("need task").supervisor = TaskHandle::gen())
+      .expect("scheduler must not be locked");
+    yield_to(th.into_c());
+    assert_eq!(SIGNAL.load(Ordering::SeqCst), 0, "signal sent without supervisor");
+    assert!(userspace().in_scheduler_spin(|sched| sched.resolve_th(th)).is_none(),
+      "destroyed task stays registered");
+    assert_eq!(kinfo().get_current_task(), TaskHandle::zero());
+  });
+}
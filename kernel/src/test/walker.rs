@@ -0,0 +1,72 @@
+#[test_case]
+fn test_walker_runs() {
+  use crate::vmem::mapper::{map_new, unmap, MapType};
+  use crate::vmem::pagelist::FrameOwner;
+  use crate::vmem::walker::{mappings, PageSize};
+  use crate::vmem::PAGE_SIZE;
+  use crate::*;
+  let vaddr = VirtAddr::new(crate::vmem::TEMP_MAP as u64);
+  let first = map_new(vaddr, MapType::Data, FrameOwner::Kernel);
+  let second = map_new(vaddr + PAGE_SIZE, MapType::Data, FrameOwner::Kernel);
+  let runs = mappings(vaddr, vaddr + 2 * PAGE_SIZE);
+  assert_eq!(runs[0].start, vaddr);
+  assert_eq!(runs[0].frame, first);
+  assert_eq!(runs.iter().map(|run| run.pages()).sum::<u64>(), 2);
+  for run in runs.iter() {
+    assert_eq!(run.size, PageSize::Size4KiB);
+    assert_eq!(run.flags, MapType::Data.flags());
+  }
+  // runs are only merged if the frames are contiguous
+  assert_eq!(runs.len() == 1, second == first + PAGE_SIZE);
+  unmap(vaddr, 2, MapType::Data);
+  release_page(first).expect("test page must be released");
+  release_page(second).expect("test page must be released");
+}
+
+#[test_case]
+fn test_walker_top_of_address_space() {
+  use crate::vmem::walker::{LeafWalker, Mapping, PageSize, Walker};
+  use crate::vmem::KHEAP_START;
+  use alloc::boxed::Box;
+  use x86_64::structures::paging::{PageTable, PageTableFlags as Flags};
+  use crate::*;
+  // the tables live on the kernel heap, their "physical" addresses are
+  // offsets into it so the heap start serves as the physical memory offset
+  let pmo = VirtAddr::new(KHEAP_START as u64);
+  let addr = |table: &PageTable| PhysAddr::new(table as *const PageTable as u64 - KHEAP_START as u64);
+  let table = || Box::new(PageTable::new());
+  let (mut l4, mut l3, mut l2, mut l1, mut low_l3) = (table(), table(), table(), table(), table());
+  let present = Flags::PRESENT | Flags::WRITABLE;
+  l1[510].set_addr(PhysAddr::new(0x1000), present | Flags::ACCESSED);
+  l1[511].set_addr(PhysAddr::new(0x2000), present | Flags::DIRTY);
+  l2[511].set_addr(addr(&l1), present);
+  l3[511].set_addr(addr(&l2), present);
+  l4[511].set_addr(addr(&l3), present);
+  low_l3[511].set_addr(PhysAddr::new(0x4000_0000), present | Flags::HUGE_PAGE);
+  l4[255].set_addr(addr(&low_l3), present);
+
+  let runs: alloc::vec::Vec<Mapping> = Walker::new(unsafe {
+    LeafWalker::new(pmo, &l4, VirtAddr::new(0), 1 << 64)
+  }).collect();
+  assert_eq!(runs.len(), 2, "{:?}", runs);
+  // the last GiB of the lower half ends at the hole, not at its last byte
+  assert_eq!(runs[0].start, VirtAddr::new(0x0000_7fff_c000_0000));
+  assert_eq!(runs[0].end, 1 << 47);
+  assert_eq!(runs[0].size, PageSize::Size1GiB);
+  assert_eq!(runs[0].pages(), 1);
+  // the last two pages merge although the CPU set different access flags
+  assert_eq!(runs[1].start, VirtAddr::new(0xffff_ffff_ffff_e000));
+  assert_eq!(runs[1].end, 1 << 64);
+  assert_eq!(runs[1].frame, PhysAddr::new(0x1000));
+  assert_eq!(runs[1].flags, present);
+  assert_eq!(runs[1].size, PageSize::Size4KiB);
+  assert_eq!(runs[1].pages(), 2);
+
+  // a walk starting inside the last page only sees that page
+  let runs: alloc::vec::Vec<Mapping> = Walker::new(unsafe {
+    LeafWalker::new(pmo, &l4, VirtAddr::new(0xffff_ffff_ffff_f000), 1 << 64)
+  }).collect();
+  assert_eq!(runs.len(), 1);
+  assert_eq!(runs[0].frame, PhysAddr::new(0x2000));
+  assert_eq!(runs[0].pages(), 1);
+}
@@ -0,0 +1,24 @@
+use crate::process_manager::Memory;
+use crate::vmem::{DATA_START, PAGE_SIZE};
+use crate::vmem::mapper::{is_zero_mapped, translate};
+use crate::*;
+
+#[test_case]
+fn test_zero_page_promotion() {
+  // two pages of image data followed by two pages of bss
+  let data = Memory::new_usermemory();
+  data.add_zero_pages(2);
+  let old_data = kinfo_mut().set_memory_ref(&data);
+  data.map();
+  let vaddr = VirtAddr::new((DATA_START + 2 * PAGE_SIZE) as u64);
+  assert!(is_zero_mapped(vaddr), "bss must be backed by the zero page");
+  assert_eq!(unsafe { core::ptr::read_volatile(vaddr.as_ptr::<u64>()) }, 0);
+  unsafe { core::ptr::write_volatile(vaddr.as_mut_ptr::<u64>(), 42) };
+  let frame = translate(vaddr).expect("promoted page must be mapped");
+  assert_ne!(frame, kinfo().get_zero_page_addr(), "write did not replace the zero page");
+  assert_eq!(unsafe { core::ptr::read_volatile(vaddr.as_ptr::<u64>()) }, 42);
+  assert!(is_zero_mapped(vaddr + PAGE_SIZE), "promotion must not touch other bss pages");
+  data.check_consistency().expect("promoted page must be tracked by the memory");
+  data.unmap();
+  kinfo_mut().set_memory_ref(&old_data);
+}
@@ -1,5 +1,6 @@
 
 use crate::vmem::{mapper::map_new, mapper::get_flags, mapper::MapType, PAGE_SIZE};
+use crate::vmem::pagelist::FrameOwner;
 use crate::*;
 
 pub type PFHResult = Result<PFHOkResult, PFHErrResult>;
@@ -7,6 +8,11 @@ pub type PFHResult = Result<PFHOkResult, PFHErrResult>;
 #[derive(Debug)]
 pub enum PFHOkResult {
   Mapped,
+  /// The kernel announced the fault beforehand, the page was made writable
+  #[cfg(test)]
+  Expected,
+  /// A zero page was replaced with a fresh page
+  Promoted,
 }
 
 #[derive(Debug)]
@@ -51,13 +57,17 @@ pub fn handle(pfc: PageFaultContext) -> PFHResult {
   }*/
   debug!("Active flags: {:?}", flags);
 
+  if vmem::is_guard_page(pfc.fault_address()) {
+    panic!("access to guard page: {:?}", pfc);
+  }
+
   if !pfc.caused_by_protection_violation() {
       if pfc.is_kstack() {
           if pfc.caused_by_instruction_fetch() {
               panic!("kernel attempted to run instruction from stack: {:?}", pfc);
           }
           debug!("mapping kstack page to {:?}", pfc.page().start_address());
-          map_new(vaddr, MapType::Stack);
+          map_new(vaddr, MapType::Stack, FrameOwner::Kernel);
           //TODO: adjust kernel stack size
           debug!("mapped, returning...");
           return PFHOkResult::Mapped.into()
@@ -69,7 +79,7 @@ pub fn handle(pfc: PageFaultContext) -> PFHResult {
           panic!("fault on already mapped address: {:?}", pfc);
         }
         debug!("mapping kheap page to {:?}", pfc.page().start_address());
-        map_new(vaddr, MapType::Data);
+        map_new(vaddr, MapType::Data, FrameOwner::KernelHeap);
         return PFHOkResult::Mapped.into()
       } else if pfc.is_ustack() {
           if pfc.caused_by_instruction_fetch() {
@@ -97,6 +107,18 @@ pub fn handle(pfc: PageFaultContext) -> PFHResult {
           panic!("cannot map: {:?}", pfc);
       }
   } else {
+      #[cfg(test)]
+      {
+        if kinfo().take_expected_fault(vaddr) {
+          debug!("expected protection violation at {:?}", vaddr);
+          vmem::mapper::update_flags(vaddr, MapType::Data);
+          return PFHOkResult::Expected.into()
+        }
+      }
+      if pfc.caused_by_write() && pfc.is_udata() && vmem::mapper::is_zero_mapped(vaddr) {
+        trace!("write to zero page in data memory, promoting");
+        return handle_zero_promotion(pfc)
+      }
       if pfc.is_kstack() || pfc.is_ustack() {
         error!("protection violation in stack area");
       }
@@ -151,7 +173,7 @@ fn handle_new_umemory(pfc: PageFaultContext) -> PFHResult {
     );
     panic!("kernel touched code memory early, that's nasty");
   }
-  let new_page = map_new(pfc.fault_address(), MapType::Data);
+  let new_page = map_new(pfc.fault_address(), MapType::Data, FrameOwner::Task);
   trace!(
     "mapped new code or data memory, notifying kernel for page {:?}<->{:?}",
     new_page, pfc.fault_address()
@@ -175,14 +197,24 @@ fn handle_running_umemory(pfc: PageFaultContext) -> PFHResult {
         + (PAGE_SIZE
             * (kinfo().get_data_memory_ref_size()))
     ) as u64);
-  if expected_vaddr != pfc.fault_address() {
+  if expected_vaddr != pfc.page().start_address() {
     error!(
       "wanted task to touch {:?} but it touched {:?}",
       expected_vaddr, pfc.fault_address()
     );
     panic!("task touched data memory early, that's nasty");
   }
-  let new_page = map_new(pfc.fault_address(), MapType::Data);
+  if !pfc.caused_by_write() {
+    // reading untouched memory only needs the zero page, the page
+    // is promoted once the task writes to it. Mappings of the zero page
+    // take no reference, its owner tag pins it and it is never released
+    let zero_page = kinfo().get_zero_page_addr();
+    vmem::mapper::map(pfc.page().start_address(), &[zero_page], MapType::Zero);
+    trace!("mapped zero page for read on {:?}", pfc.fault_address());
+    kinfo_mut().add_data_page(zero_page);
+    return PFHOkResult::Mapped.into()
+  }
+  let new_page = map_new(pfc.fault_address(), MapType::Data, FrameOwner::Task);
   trace!(
     "mapped new data memory, notifying kernel for page {:?}<->{:?}",
     new_page, pfc.fault_address()
@@ -191,6 +223,20 @@ fn handle_running_umemory(pfc: PageFaultContext) -> PFHResult {
   PFHOkResult::Mapped.into()
 }
 
+fn handle_zero_promotion(pfc: PageFaultContext) -> PFHResult {
+  let vaddr = pfc.page().start_address();
+  let index = (vaddr.as_u64() as usize - crate::vmem::DATA_START) / PAGE_SIZE;
+  let new_page = alloc_page_owned(FrameOwner::Task).expect("no page left to promote zero page");
+  vmem::zero_frame(new_page);
+  vmem::mapper::remap(vaddr, new_page, MapType::Data);
+  match kinfo().promote_data_page(index, new_page) {
+    Some(_) => (),
+    None => panic!("zero page at {:?} is not tracked by task memory", vaddr),
+  }
+  trace!("promoted zero page at {:?} to {:?}", vaddr, new_page);
+  PFHOkResult::Promoted.into()
+}
+
 fn handle_ustack(pfc: PageFaultContext) -> PFHResult {
   trace!("checking if the task touched stack correctly");
   let stack_size_org = kinfo().get_stack_memory_ref_size() + 1 - 2;
@@ -223,12 +269,12 @@ fn handle_ustack(pfc: PageFaultContext) -> PFHResult {
     for x in 0..diff_pages {
       let tar_addr: VirtAddr = laststack_vaddr - x as usize * PAGE_SIZE;
       trace!("mapping user stack page to {:#018x}", tar_addr.as_u64());
-      let new_page = map_new(tar_addr, MapType::Stack);
+      let new_page = map_new(tar_addr, MapType::Stack, FrameOwner::Task);
       kinfo_mut().add_stack_page(new_page);
     }
   } else if diff_pages == 1 {
     trace!("mapping user stack page to {:?}", pfc.page().start_address());
-    let new_page = map_new(pfc.fault_address(), MapType::Stack);
+    let new_page = map_new(pfc.fault_address(), MapType::Stack, FrameOwner::Task);
     kinfo_mut().add_stack_page(new_page);
   } else {
     panic!("page fault on supposedly mapped stack");
@@ -2,14 +2,15 @@ use crate::process_manager::TaskHandle;
 use crate::vmem::pagetable::Page;
 use crate::vmem::PhysAddr;
 use crate::vmem::PAGE_SIZE;
+use crate::vmem::pagelist::FrameOwner;
 use crate::*;
 use x86_64::structures::paging::PageTableFlags;
 use x86_64::structures::paging::Size4KiB;
 use x86_64::structures::paging::mapper::MapperAllSizes;
 use x86_64::structures::paging::mapper::Mapper;
-use vmem::pagetable::{get_pagemap, get_pagemap_mut, get_pagetable};
+use vmem::pagetable::{get_pagemap, get_pagemap_mut};
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum MapType {
   Stack,               // Stack Page, No Execute
   Data,                // Data Page, No Execute
@@ -20,6 +21,7 @@ pub enum MapType {
   ShMem(TaskHandle),   // Memory shared to other process
   Guard,               // No Execute, No Read+Write
   Zero,                // Map No Execute, No RW Page, share page
+  Mmio,                // Device Memory, No Execute, No Cache
   Empty,               // No Flags
 }
 
@@ -28,16 +30,29 @@ impl MapType {
     let base_flags = match self {
       MapType::Stack => PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
       MapType::Data => PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+      #[cfg(not(feature = "hardening"))]
       MapType::UnsafeCode => PageTableFlags::WRITABLE,
+      #[cfg(feature = "hardening")]
+      MapType::UnsafeCode => panic!("W^X violation: unsafe code mappings are not allowed"),
+      #[cfg(not(feature = "hardening"))]
       MapType::Code => PageTableFlags::empty() | PageTableFlags::WRITABLE,
+      #[cfg(feature = "hardening")]
+      MapType::Code => PageTableFlags::empty(),
       MapType::ReadOnly => PageTableFlags::NO_EXECUTE,
       MapType::Managed(_) => PageTableFlags::NO_EXECUTE,
       MapType::ShMem(_) => PageTableFlags::NO_EXECUTE,
-      MapType::Guard => PageTableFlags::NO_EXECUTE,
+      // guard pages are kept in the page table but never present
+      MapType::Guard => return PageTableFlags::NO_EXECUTE,
       MapType::Zero => PageTableFlags::NO_EXECUTE,
+      MapType::Mmio => PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE
+        | PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH,
       MapType::Empty => return PageTableFlags::empty(),
     };
-    base_flags | PageTableFlags::PRESENT
+    let flags = base_flags | PageTableFlags::PRESENT;
+    #[cfg(feature = "hardening")]
+    assert!(!flags.contains(PageTableFlags::WRITABLE) || flags.contains(PageTableFlags::NO_EXECUTE),
+      "W^X violation: {:?} maps writable and executable memory", self);
+    flags
   }
 }
 
@@ -49,11 +64,11 @@ impl Default for MapType {
 
 use x86_64::structures::paging::PhysFrame;
 
-pub fn map_new(base_addr: VirtAddr, mt: MapType) -> PhysAddr {
-  trace!("mapping new page to {:?} ({:?})", base_addr, mt);
+pub fn map_new(base_addr: VirtAddr, mt: MapType, owner: FrameOwner) -> PhysAddr {
+  trace!("mapping new page to {:?} ({:?}, {:?})", base_addr, mt, owner);
   let pm = pager();
   let flags = mt.flags();
-  let frame = unsafe{ pm.alloc_page().expect("map new failed") };
+  let frame = unsafe{ pm.alloc_page_owned(owner).expect("map new failed") };
   let page: Page<Size4KiB> = Page::containing_address(base_addr);
   let pagepool = &mut pm.pagepool().clone();
   trace!("putting new page into pagetable");
@@ -67,38 +82,11 @@ pub fn map_new(base_addr: VirtAddr, mt: MapType) -> PhysAddr {
 }
 
 pub fn dump_pagetable() {
-  use x86_64::structures::paging::PageTable;
-  get_pagetable(|apt: &PageTable| {
-    for (idx, pte) in apt.iter().enumerate() {
-      if idx == 1 { continue }
-      if pte.flags().contains(PageTableFlags::PRESENT) {
-        trace!("{}: {:?}", idx, pte);
-        let entry = pte.addr().as_u64() as *mut PageTable;
-        let tablel3: &PageTable = unsafe{&*entry};
-        for (idx, pte) in tablel3.iter().enumerate() {
-          if pte.flags().contains(PageTableFlags::PRESENT) {
-            trace!(" - {}: {:?}", idx, pte);
-            let entry = pte.addr().as_u64() as *mut PageTable;
-            let tablel2: &PageTable = unsafe{&*entry};
-            for (idx, pte) in tablel2.iter().enumerate() {
-              if pte.flags().contains(PageTableFlags::PRESENT) {
-                trace!(" - - {}: {:?}", idx, pte);
-                let entry = pte.addr().as_u64() as *mut PageTable;
-                let tablel1: &PageTable = unsafe{&*entry};
-                for (idx, pte) in tablel1.iter().enumerate() {
-                  if pte.flags().contains(PageTableFlags::PRESENT) {
-                    trace!(" - - - {}: {:?}", idx, pte);
-                    let entry = pte.addr().as_u64() as *mut PageTable;
-                    let tablel1: &PageTable = unsafe{&*entry};
-                  }
-                }
-              }
-            }
-          }
-        }
-      }
-    }
-  })
+  for run in crate::vmem::walker::all_mappings() {
+    trace!("{:#018x}..{:#018x} -> {:#018x} {:>8} x {:?} {:?}",
+      run.start.as_u64(), run.end, run.frame.as_u64(),
+      run.pages(), run.size, run.flags);
+  }
 }
 
 pub fn map_zero(addr: VirtAddr, size: u32) {
@@ -110,10 +98,10 @@ pub fn map_zero(addr: VirtAddr, size: u32) {
   let pagepool = &mut pm.pagepool().clone();
   get_pagemap_mut(|apt| {
     let flags = MapType::Zero.flags();
-    let page: Page<Size4KiB> = Page::containing_address(addr);
     for x in 0..size {
       let addr = addr + x as usize * PAGE_SIZE;
       trace!("map zero: {:?}", addr);
+      let page: Page<Size4KiB> = Page::containing_address(addr);
       unsafe { apt.map_to(
         page,
         PhysFrame::containing_address(zero_page),
@@ -132,6 +120,21 @@ pub fn is_mapped(addr: VirtAddr) -> bool {
   })
 }
 
+pub fn translate(addr: VirtAddr) -> Option<PhysAddr> {
+  use x86_64::structures::paging::OffsetPageTable;
+  get_pagemap(|apt: &OffsetPageTable| {
+    apt.translate_addr(addr)
+  })
+}
+
+pub fn is_zero_mapped(addr: VirtAddr) -> bool {
+  let zero_page = kinfo().get_zero_page_addr();
+  match translate(addr) {
+    Some(pa) => PhysFrame::<Size4KiB>::containing_address(pa).start_address() == zero_page,
+    None => false,
+  }
+}
+
 use x86_64::structures::paging::mapper::TranslateResult;
 
 pub fn get_flags(addr: VirtAddr) -> Option<PageTableFlags> {
@@ -186,6 +189,23 @@ pub fn unmap(base_addr: VirtAddr, pl_size: usize, mt: MapType) {
   })
 }
 
+// replaces the frame behind an already mapped page, returns the previous frame
+// the previous frame is not released
+pub fn remap(addr: VirtAddr, pa: PhysAddr, mt: MapType) -> PhysAddr {
+  trace!("remapping memory at {:?} to {:?} ({:?})", addr, pa, mt);
+  let pm = pager();
+  let pagepool = &mut pm.pagepool().clone();
+  let flags = mt.flags();
+  get_pagemap_mut(|apt| {
+    let page: Page<Size4KiB> = Page::containing_address(addr);
+    let (old, flush) = apt.unmap(page).expect("remap requires mapped page");
+    flush.flush();
+    unsafe { apt.map_to(page, PhysFrame::containing_address(pa), flags, pagepool) }
+      .expect("must not fail remap").flush();
+    old.start_address()
+  })
+}
+
 pub fn update_flags(addr: VirtAddr, mt: MapType) {
   trace!("updating flags of memory at {:?} to {:?}", addr, mt);
   assert!(is_mapped(addr), "page must be mapped: {:?}", addr);
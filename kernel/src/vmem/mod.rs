@@ -2,9 +2,12 @@ pub mod pagelist;
 pub mod pagetable;
 pub mod mapper;
 pub mod faulth;
+pub mod regions;
+pub mod walker;
 
 use core::convert::TryInto;
 use core::option::NoneError;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use atomic::Atomic;
 use crate::common::{PhysAddr, PhysFrame};
 use crate::*;
@@ -16,6 +19,8 @@ const BOOT_MEMORY_PAGES: u16 = 16;
 pub const KSTACK_START: usize  = 0xffff_ff80_0000_0000;
 pub const KSTACK_END: usize    = 0xffff_ff70_0000_0000;
 pub const KSTACK_GUARD: usize  = 0xffff_ff70_0000_0000;
+pub const MMIO_END: usize      = 0xffff_ff6b_0000_0000;
+pub const MMIO_START: usize    = 0xffff_ff6a_0000_0000;
 pub const KHEAP_END: usize     = 0xffff_ff69_0000_0000;
 pub const KHEAP_ALLOC: usize   = 0xffff_ff61_1000_0000;
 pub const KHEAP_START: usize   = 0xffff_ff61_0000_0000;
@@ -31,7 +36,69 @@ pub const KERNEL_END: usize    = 0x0000_0000_0080_0000;
 pub const KERNEL_START: usize  = 0x0000_0000_0001_0000;
 pub const ZERO_ADDR: usize     = 0x0000_0000_0000_0000;
 pub const UGUARD_PAGE: usize   = 0xffff_ff00_0000_0000;
+pub const KHEAP_GUARD_LOW: usize  = KHEAP_START - PAGE_SIZE;
+pub const KHEAP_GUARD_HIGH: usize = KHEAP_END;
 
+const MAX_STACK_GUARDS: usize = 64;
+
+use spin::Mutex;
+
+// guard pages placed around kernel stacks and the heap at runtime, this must
+// not allocate as it is checked on every page fault
+static STACK_GUARDS: Mutex<([usize; MAX_STACK_GUARDS], usize)> = Mutex::new(([0; MAX_STACK_GUARDS], 0));
+
+/// Marks the page as guard page, the page stays in the page table
+/// but any access to it faults
+pub fn install_guard_page(addr: VirtAddr) {
+  debug!("installing guard page at {:?}", addr);
+  mapper::update_flags(addr, mapper::MapType::Guard);
+  let mut guards = STACK_GUARDS.lock();
+  let idx = guards.1;
+  assert!(idx < MAX_STACK_GUARDS, "too many guard pages");
+  guards.0[idx] = addr.as_u64() as usize;
+  guards.1 += 1;
+}
+
+/// Places guard pages directly below and above the kernel heap
+#[cfg(feature = "hardening")]
+pub fn install_heap_guards() {
+  let zero_page = crate::kinfo().get_zero_page_addr();
+  for guard in [KHEAP_GUARD_LOW, KHEAP_GUARD_HIGH].iter() {
+    let addr = VirtAddr::new(*guard as u64);
+    // install_guard_page takes a mapped page, the zero page is never written
+    mapper::map(addr, &[zero_page], mapper::MapType::ReadOnly);
+    install_guard_page(addr);
+  }
+}
+
+pub fn is_guard_page(addr: VirtAddr) -> bool {
+  let page = addr.align_down(PAGE_SIZE as u64).as_u64() as usize;
+  if page == KSTACK_GUARD {
+    return true
+  }
+  let guards = STACK_GUARDS.lock();
+  guards.0[..guards.1].iter().any(|guard| *guard == page)
+}
+
+
+static MMIO_NEXT: AtomicUsize = AtomicUsize::new(MMIO_START);
+
+/// Maps device memory into the MMIO window and returns the address of the
+/// first byte, the mapping is permanent
+pub fn map_mmio(pa: PhysAddr, size: usize) -> VirtAddr {
+  let offset = pa.as_u64() as usize % PAGE_SIZE;
+  let base = pa.align_down(PAGE_SIZE as u64);
+  let pages = (offset + size + PAGE_SIZE - 1) / PAGE_SIZE;
+  let vaddr = MMIO_NEXT.fetch_add(pages * PAGE_SIZE, Ordering::SeqCst);
+  assert!(vaddr + pages * PAGE_SIZE <= MMIO_END, "MMIO window exhausted");
+  let frames: alloc::vec::Vec<PhysAddr> = (0..pages)
+    .map(|x| base + x * PAGE_SIZE)
+    .collect();
+  let vaddr = VirtAddr::new(vaddr as u64);
+  debug!("mapping MMIO {:?} ({} pages) to {:?}", base, pages, vaddr);
+  mapper::map(vaddr, &frames, mapper::MapType::Mmio);
+  vaddr + offset
+}
 
 #[repr(align(4096))]
 #[derive(Copy, Clone)]
@@ -47,14 +114,37 @@ impl StaticPage {
   }
 }
 
+/// Overwrites the physical frame with zeroes using the physical memory mapping
+pub fn zero_frame(pa: PhysAddr) {
+  let vaddr = kinfo().get_pmo() + pa.as_u64();
+  unsafe { core::ptr::write_bytes(vaddr.as_mut_ptr::<u8>(), 0, PAGE_SIZE) };
+}
+
 use self::pagelist::PagePool;
 use self::pagelist::pagelist_ng::{PageMap, PageMapWrapper};
 use self::pagelist::{PagePoolReleaseError, PagePoolAllocationError, PagePoolAppendError};
+use self::pagelist::{PagePoolReferenceError, FrameOwner};
+
+/// Memory pressure levels derived from the free memory watermarks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum MemoryPressure {
+  /// Free memory is above the low watermark
+  Normal = 0,
+  /// Free memory dropped below the low watermark
+  Low = 1,
+  /// Free memory dropped below the critical watermark
+  Critical = 2,
+}
 
 #[repr(C)]
 #[repr(align(4096))]
 pub struct PageManager {
   pagepool: Atomic<Option<PageMapWrapper>>,
+  watermark_low: AtomicUsize,
+  watermark_critical: AtomicUsize,
+  pressure: Atomic<MemoryPressure>,
+  pressure_changed: AtomicBool,
 }
 
 // Memory allocated for bootstrapping
@@ -85,7 +175,15 @@ impl From<()> for InitError {
 }
 
 impl PageManager {
-  pub const fn new() -> PageManager { PageManager { pagepool: Atomic::new(None), } }
+  pub const fn new() -> PageManager {
+    PageManager {
+      pagepool: Atomic::new(None),
+      watermark_low: AtomicUsize::new(0),
+      watermark_critical: AtomicUsize::new(0),
+      pressure: Atomic::new(MemoryPressure::Normal),
+      pressure_changed: AtomicBool::new(false),
+    }
+  }
 
   pub unsafe fn init(&self, physical_memory_offset: VirtAddr) -> Result<(), InitError> {
     self.internal_init(physical_memory_offset)
@@ -115,7 +213,8 @@ impl PageManager {
     debug!("mapping first heap page");
     {
       let page = Page::containing_address(VirtAddr::new(KHEAP_START.try_into().unwrap()));
-      let frame = self.pagepool().allocate().expect("require page for initial heap");
+      let frame = self.pagepool().allocate_owned(FrameOwner::KernelHeap)
+        .expect("require page for initial heap");
       debug!("working on page {:#018x}", page.start_address().as_u64());
       let flags = vmem::mapper::MapType::Data.flags();
       debug!("mapping with flags {:?}", flags);
@@ -137,6 +236,12 @@ impl PageManager {
     self.pagepool.load(atomic::Ordering::Relaxed).expect("pagepool not installed but expected")
   }
 
+  /// Returns the physical range of the pages used to bootstrap the pagepool
+  pub fn boot_pages(&self) -> (PhysAddr, PhysAddr) {
+    let base = self.get_boot_base();
+    (base, base + BOOT_MEMORY_PAGES as usize * PAGE_SIZE)
+  }
+
   fn get_boot_base(&self) -> PhysAddr {
     unsafe{
       PhysAddr::new((&mut BOOT_PAGES[0].0[0] as *mut u8) as u64)
@@ -173,10 +278,26 @@ impl PageManager {
     );
   } 
 
+  pub fn print_owned_mem(&self) {
+    for owner in FrameOwner::ALL.iter() {
+      if *owner == FrameOwner::Free {
+        continue
+      }
+      let pages = self.owned_memory(*owner);
+      trace!("Memory owned by {:?}: {} KiB, {} Pages",
+        owner,
+        pages * 4096 / 1024,
+        pages
+      );
+    }
+  }
+
   pub fn print_mem_summary(&self) {
     self.print_total_mem();
     self.print_used_mem();
     self.print_free_mem();
+    self.print_owned_mem();
+    crate::common::ALLOCATOR.print_stats();
   }
 
   pub fn pagemap_layout() -> alloc::alloc::Layout {
@@ -197,11 +318,67 @@ impl PageManager {
   pub fn used_memory(&self) -> usize {
     self.pagepool().count_used()
   } 
+  pub fn owned_memory(&self, owner: FrameOwner) -> usize {
+    self.pagepool().count_owned(owner)
+  }
   pub unsafe fn alloc_page(&self) -> Result<PhysAddr, PagePoolAllocationError> {
-    self.pagepool().allocate().map(|x| x.start_address())
+    self.alloc_page_owned(FrameOwner::Kernel)
+  }
+  pub unsafe fn alloc_page_owned(&self, owner: FrameOwner) -> Result<PhysAddr, PagePoolAllocationError> {
+    let res = self.pagepool().allocate_owned(owner).map(|x| x.start_address());
+    self.update_pressure();
+    res
+  }
+  /// Adds a reference to an allocated page, the page is only freed once
+  /// every reference has been released through free_page
+  pub unsafe fn ref_page(&self, pa: PhysAddr) -> Result<u16, PagePoolReferenceError> {
+    self.pagepool().acquire(PhysFrame::containing_address(pa))
   }
   pub unsafe fn free_page(&self, pa: PhysAddr) -> Result<(), PagePoolReleaseError> {
-    self.pagepool().release(PhysFrame::containing_address(pa))
+    let res = self.pagepool().release(PhysFrame::containing_address(pa));
+    self.update_pressure();
+    res
+  }
+  /// Sets the free memory watermarks in pages, low must be above critical
+  pub fn set_watermarks(&self, low: usize, critical: usize) {
+    assert!(low >= critical, "low watermark must not be below critical watermark");
+    debug!("memory watermarks set to low={} critical={} pages", low, critical);
+    self.watermark_low.store(low, Ordering::SeqCst);
+    self.watermark_critical.store(critical, Ordering::SeqCst);
+    self.update_pressure();
+  }
+  pub fn watermarks(&self) -> (usize, usize) {
+    (self.watermark_low.load(Ordering::Relaxed), self.watermark_critical.load(Ordering::Relaxed))
+  }
+  pub fn pressure(&self) -> MemoryPressure {
+    self.pressure.load(Ordering::SeqCst)
+  }
+  /// Returns the new pressure level if it changed since the last call
+  pub fn take_pressure_event(&self) -> Option<MemoryPressure> {
+    if self.pressure_changed.swap(false, Ordering::SeqCst) {
+      Some(self.pressure())
+    } else {
+      None
+    }
+  }
+  fn update_pressure(&self) {
+    let free = self.free_memory();
+    let level = if free < self.watermark_critical.load(Ordering::Relaxed) {
+      MemoryPressure::Critical
+    } else if free < self.watermark_low.load(Ordering::Relaxed) {
+      MemoryPressure::Low
+    } else {
+      MemoryPressure::Normal
+    };
+    if self.pressure.swap(level, Ordering::SeqCst) != level {
+      self.pressure_changed.store(true, Ordering::SeqCst);
+    }
+  }
+  pub fn page_refcount(&self, pa: PhysAddr) -> Result<u16, PagePoolReferenceError> {
+    self.pagepool().refcount(PhysFrame::containing_address(pa))
+  }
+  pub fn page_owner(&self, pa: PhysAddr) -> Result<FrameOwner, PagePoolReferenceError> {
+    self.pagepool().owner(PhysFrame::containing_address(pa))
   }
 }
 
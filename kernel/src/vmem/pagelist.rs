@@ -10,6 +10,46 @@ use core::option::NoneError;
 
 pub type RelativeFrame = usize;
 
+/// Identifies the subsystem a physical frame was handed out to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum FrameOwner {
+  /// Frame is not allocated
+  Free = 0,
+  /// Frame was allocated without specifying an owner
+  Kernel = 1,
+  /// Frame backs the kernel heap
+  KernelHeap = 2,
+  /// Frame holds a page table
+  PageTable = 3,
+  /// Frame backs memory of a task
+  Task = 4,
+  /// Frame is the shared zero page, it is never released
+  ZeroPage = 5,
+}
+
+impl FrameOwner {
+  pub const ALL: [FrameOwner; 6] = [
+    FrameOwner::Free,
+    FrameOwner::Kernel,
+    FrameOwner::KernelHeap,
+    FrameOwner::PageTable,
+    FrameOwner::Task,
+    FrameOwner::ZeroPage,
+  ];
+
+  pub fn from_bits(v: u8) -> FrameOwner {
+    match v {
+      1 => FrameOwner::Kernel,
+      2 => FrameOwner::KernelHeap,
+      3 => FrameOwner::PageTable,
+      4 => FrameOwner::Task,
+      5 => FrameOwner::ZeroPage,
+      _ => FrameOwner::Free,
+    }
+  }
+}
+
 #[derive(Debug, Clone)]
 pub enum PagePoolAllocationError {
   /// No free page found
@@ -22,6 +62,18 @@ pub enum PagePoolReleaseError {
   PageAlreadyUnused,
   /// Page is not tracked in free memory
   PageUntracked,
+  /// Page is pinned and cannot be released
+  PagePinned,
+}
+
+#[derive(Debug, Clone)]
+pub enum PagePoolReferenceError {
+  /// Page is not allocated, references can only be taken on used pages
+  PageUnused,
+  /// Page is not tracked in free memory
+  PageUntracked,
+  /// Page has reached the maximum number of references
+  RefcountOverflow,
 }
 
 #[derive(Debug, Clone)]
@@ -67,16 +119,30 @@ pub trait PagePool {
     self.count_all() - self.count_free()
   } 
 
+  /// Returns the number of memory pages currently handed out to the given owner
+  fn count_owned(&self, owner: FrameOwner) -> usize;
+
   /// Outputs the page pool into the kernel debug log
   fn dump(&self);
 
   /// This function will allocate a memory page from it's internal pool if possible.
   /// If there is no memory available in the pool, None is returned. 
   /// The returned memory page must be zeroed.
-  fn allocate(&mut self) -> Result<PhysFrame, PagePoolAllocationError>;
-  /// Releases a memory page to be reused. If the page is pinned, a non-fatal error
-  /// must be returned.
+  fn allocate(&mut self) -> Result<PhysFrame, PagePoolAllocationError> {
+    self.allocate_owned(FrameOwner::Kernel)
+  }
+  /// Allocates a memory page like allocate() and tags it with the given owner.
+  /// The page starts out with a single reference.
+  fn allocate_owned(&mut self, owner: FrameOwner) -> Result<PhysFrame, PagePoolAllocationError>;
+  /// Adds a reference to an allocated memory page and returns the new reference count.
+  fn acquire(&mut self, pa: PhysFrame) -> Result<u16, PagePoolReferenceError>;
+  /// Drops a reference to a memory page, the page is reused once the last reference
+  /// has been dropped. If the page is pinned, a non-fatal error must be returned.
   fn release(&mut self, pa: PhysFrame) -> Result<(), PagePoolReleaseError>;
+  /// Returns the number of references held on a memory page, 0 if the page is free.
+  fn refcount(&self, pa: PhysFrame) -> Result<u16, PagePoolReferenceError>;
+  /// Returns the owner tag of a memory page
+  fn owner(&self, pa: PhysFrame) -> Result<FrameOwner, PagePoolReferenceError>;
 
   /// A section of memory specified by pa and sz is to be added to the page pool.
   /// The page pool must use the normal memory allocator for this operation.
@@ -91,7 +157,7 @@ use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, Size4KiB};
 unsafe impl FrameAllocator<Size4KiB> for dyn PagePool {
   fn allocate_frame(&mut self) -> Option<PhysFrame> {
     debug!("frame allocation request");
-    let alloc = self.allocate();
+    let alloc = self.allocate_owned(FrameOwner::PageTable);
     match alloc {
       Err(v) => { debug!("could not allocate frame: {:?}", v); None },
       Ok(alloc) => { debug!("allocated frame {:#018x}", alloc.start_address()); Some(alloc) }
@@ -1,8 +1,8 @@
 use crate::vmem::pagelist::{PhysAddr, PagePool, PagePoolAllocationError, PagePoolReleaseError};
-use crate::vmem::pagelist::PagePoolAppendError;
+use crate::vmem::pagelist::{PagePoolAppendError, PagePoolReferenceError, FrameOwner};
 use core::ptr::NonNull;
 use core::option::NoneError;
-use core::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+use core::sync::atomic::{AtomicU16, Ordering};
 use core::convert::TryFrom;
 use crate::vmem::{PAGE_SIZE, KHEAP_START};
 use crate::*;
@@ -10,9 +10,26 @@ use x86_64::structures::paging::PhysFrame;
 use x86_64::structures::paging::Size4KiB;
 use x86_64::structures::paging::{FrameAllocator, FrameDeallocator};
 
-const PAGES_PER_BLOCK: usize = 4067;
+const PAGES_PER_BLOCK: usize = 2033;
 const HEADER_MAGIC: u64 = 0xDEADC0FFEE;
 
+// Each frame entry stores the owner tag in the upper 3 bits and the
+// reference count in the lower 13 bits, an entry of 0 is a free frame
+const REFCOUNT_BITS: u16 = 13;
+const REFCOUNT_MASK: u16 = (1 << REFCOUNT_BITS) - 1;
+
+fn frame_entry(owner: FrameOwner, refs: u16) -> u16 {
+  ((owner as u16) << REFCOUNT_BITS) | (refs & REFCOUNT_MASK)
+}
+
+fn frame_entry_owner(entry: u16) -> FrameOwner {
+  FrameOwner::from_bits((entry >> REFCOUNT_BITS) as u8)
+}
+
+fn frame_entry_refs(entry: u16) -> u16 {
+  entry & REFCOUNT_MASK
+}
+
 #[repr(align(4096))]
 pub struct PageMap {
   header: u64,
@@ -22,7 +39,7 @@ pub struct PageMap {
   free_pages: AtomicU16,
   /// If set, the pagetable lock function is disabled
   disable_pt_lock: bool,
-  used: [AtomicBool; PAGES_PER_BLOCK],
+  frames: [AtomicU16; PAGES_PER_BLOCK],
 }
 
 panic_on_drop!(PageMap);
@@ -40,14 +57,14 @@ impl PageMap {
       next: None,
       disable_pt_lock: true,
       free_pages: AtomicU16::new(size),
-      used: unsafe{core::mem::MaybeUninit::zeroed().assume_init()},
+      frames: unsafe{core::mem::MaybeUninit::zeroed().assume_init()},
     };
     for x in 0..PAGES_PER_BLOCK {
-      page_map.used[x] = AtomicBool::new(false);
+      page_map.frames[x] = AtomicU16::new(0);
     }
 
     let mut pmw = PageMapWrapper(NonNull::new(&mut page_map as *mut PageMap).unwrap());
-    let page = pmw.allocate()?;
+    let page = pmw.allocate_owned(FrameOwner::KernelHeap)?;
     drop(PageMapWrapper);
 
     trace!("installing temporary pagepool");
@@ -95,12 +112,12 @@ impl PageMap {
       free_pages: AtomicU16::new(actual_size),
       disable_pt_lock: false,
       next: None,
-      used: unsafe{core::mem::MaybeUninit::zeroed().assume_init()},
+      frames: unsafe{core::mem::MaybeUninit::zeroed().assume_init()},
     };
     unsafe{core::ptr::write_volatile(page_map, pm)};
-    trace!("clearing pagemap used bitmap");
+    trace!("clearing pagemap frame table");
     for x in 0..PAGES_PER_BLOCK {
-      unsafe { (*page_map).used[x] = AtomicBool::new(false) };
+      unsafe { (*page_map).frames[x] = AtomicU16::new(0) };
     }
     unsafe{page_map.as_ref()}.map(|y| y.lock());
     (page_map, size - (actual_size as u64))
@@ -110,17 +127,29 @@ impl PageMap {
     assert_eq!(self.header, HEADER_MAGIC, "Header magic corrupted");
   }
 
+  /// Returns the index of the frame in this block or None if the frame
+  /// is tracked elsewhere
+  fn index_of(&self, pa: PhysFrame<Size4KiB>) -> Option<usize> {
+    let addr = pa.start_address();
+    if addr < self.start || addr >= self.start + (self.size as usize * PAGE_SIZE) {
+      return None
+    }
+    Some(((addr.as_u64() - self.start.as_u64()) as usize) / PAGE_SIZE)
+  }
+
   fn unlock(&self) {
     if !self.disable_pt_lock {
       let vaddr = VirtAddr::from_ptr(self as *const PageMap);
-      //crate::vmem::mapper::update_flags(vaddr, crate::vmem::mapper::MapType::Data);
+      #[cfg(feature = "hardening")]
+      crate::vmem::mapper::update_flags(vaddr, crate::vmem::mapper::MapType::Data);
     }
   }
 
   fn lock(&self) {
     if !self.disable_pt_lock {
       let vaddr = VirtAddr::from_ptr(self as *const PageMap);
-      //crate::vmem::mapper::update_flags(vaddr, crate::vmem::mapper::MapType::ReadOnly);
+      #[cfg(feature = "hardening")]
+      crate::vmem::mapper::update_flags(vaddr, crate::vmem::mapper::MapType::ReadOnly);
     }
   }
 }
@@ -199,6 +228,20 @@ impl PagePool for PageMapWrapper {
     } 
   } 
 
+  fn count_owned(&self, owner: FrameOwner) -> usize {
+    let mut val = 0;
+    for x in 0..(self.size as usize) {
+      let entry = self.frames[x].load(Ordering::Relaxed);
+      if frame_entry_refs(entry) > 0 && frame_entry_owner(entry) == owner {
+        val += 1;
+      }
+    }
+    val + match self.next {
+      Some(next) => next.count_owned(owner),
+      None => 0,
+    }
+  }
+
   fn dump(&self) {
     debug!("PagePool for {:?}", self.start);
     debug!("Size: {} pages, {} KB", self.size, self.size * 4);
@@ -206,17 +249,18 @@ impl PagePool for PageMapWrapper {
     debug!("Next PagePool: {:?}", self.next);
   }
 
-  fn allocate(&mut self) -> Result<PhysFrame, PagePoolAllocationError> {
+  fn allocate_owned(&mut self, owner: FrameOwner) -> Result<PhysFrame, PagePoolAllocationError> {
+    assert_ne!(owner, FrameOwner::Free, "cannot allocate a frame for the free owner");
     self.verify();
     self.unlock();
     for x in 0..self.size {
       let x = x as usize;
-      let prev = self.used[x].compare_and_swap(false, true, Ordering::SeqCst);
-      if !prev {
+      let prev = self.frames[x].compare_and_swap(0, frame_entry(owner, 1), Ordering::SeqCst);
+      if prev == 0 {
         let addr = self.start + (x * PAGE_SIZE);
-        trace!("free page from {:#018x} + {:#010x} = {:#018x}", 
+        trace!("free page from {:#018x} + {:#010x} = {:#018x} for {:?}", 
           self.start.as_u64(),
-          (x*PAGE_SIZE), addr);
+          (x*PAGE_SIZE), addr, owner);
         self.free_pages.fetch_sub(1, Ordering::SeqCst);
         self.lock();
         let addr = PhysFrame::from_start_address(addr)
@@ -227,32 +271,92 @@ impl PagePool for PageMapWrapper {
     self.lock();
     trace!("no page found, trying next block");
     match self.next {
-      Some(mut next) => next.allocate(),
+      Some(mut next) => next.allocate_owned(owner),
       None => Err(PagePoolAllocationError::NoPageFree),
     }
   }
 
+  fn acquire(&mut self, pa: PhysFrame<Size4KiB>) -> Result<u16, PagePoolReferenceError> {
+    self.verify();
+    let index = match self.index_of(pa) {
+      Some(index) => index,
+      None => return match self.next {
+        Some(mut next) => next.acquire(pa),
+        None => Err(PagePoolReferenceError::PageUntracked),
+      },
+    };
+    self.unlock();
+    let res = loop {
+      let entry = self.frames[index].load(Ordering::SeqCst);
+      let refs = frame_entry_refs(entry);
+      if refs == 0 {
+        break Err(PagePoolReferenceError::PageUnused);
+      }
+      if refs == REFCOUNT_MASK {
+        break Err(PagePoolReferenceError::RefcountOverflow);
+      }
+      if self.frames[index].compare_and_swap(entry, entry + 1, Ordering::SeqCst) == entry {
+        break Ok(refs + 1);
+      }
+    };
+    self.lock();
+    res
+  }
+
   fn release(&mut self, pa: PhysFrame<Size4KiB>) -> Result<(),PagePoolReleaseError> {
     self.verify();
     trace!("releasing memory {:?}", pa);
-    if pa.start_address() > (self.start + self.size as usize) {
-      return Err(PagePoolReleaseError::PageUntracked)
-    }
-    self.unlock();
-    let index = (pa.start_address().as_u64() - self.start.as_u64()) as usize;
-    let index = index / pa.size() as usize;
-    trace!("index rel to pagelist is {}", index);
-    let prev = self.used[index].compare_and_swap(true, false, Ordering::SeqCst);
-    if prev {
-      self.free_pages.fetch_add(1, Ordering::SeqCst);
-      self.lock();
-      Ok(())
-    } else {
-      self.lock();
-      match self.next {
+    let index = match self.index_of(pa) {
+      Some(index) => index,
+      None => return match self.next {
         Some(mut next) => next.release(pa),
-        None => Err(PagePoolReleaseError::PageAlreadyUnused),
+        None => Err(PagePoolReleaseError::PageUntracked),
+      },
+    };
+    trace!("index rel to pagelist is {}", index);
+    self.unlock();
+    let res = loop {
+      let entry = self.frames[index].load(Ordering::SeqCst);
+      let refs = frame_entry_refs(entry);
+      if refs == 0 {
+        break Err(PagePoolReleaseError::PageAlreadyUnused);
+      }
+      if refs == 1 && frame_entry_owner(entry) == FrameOwner::ZeroPage {
+        break Err(PagePoolReleaseError::PagePinned);
+      }
+      let new = if refs == 1 { 0 } else { entry - 1 };
+      if self.frames[index].compare_and_swap(entry, new, Ordering::SeqCst) == entry {
+        if new == 0 {
+          self.free_pages.fetch_add(1, Ordering::SeqCst);
+        } else {
+          trace!("page {:?} still has {} references", pa, refs - 1);
+        }
+        break Ok(());
       }
+    };
+    self.lock();
+    res
+  }
+
+  fn refcount(&self, pa: PhysFrame<Size4KiB>) -> Result<u16, PagePoolReferenceError> {
+    self.verify();
+    match self.index_of(pa) {
+      Some(index) => Ok(frame_entry_refs(self.frames[index].load(Ordering::SeqCst))),
+      None => match self.next {
+        Some(next) => next.refcount(pa),
+        None => Err(PagePoolReferenceError::PageUntracked),
+      },
+    }
+  }
+
+  fn owner(&self, pa: PhysFrame<Size4KiB>) -> Result<FrameOwner, PagePoolReferenceError> {
+    self.verify();
+    match self.index_of(pa) {
+      Some(index) => Ok(frame_entry_owner(self.frames[index].load(Ordering::SeqCst))),
+      None => match self.next {
+        Some(next) => next.owner(pa),
+        None => Err(PagePoolReferenceError::PageUntracked),
+      },
     }
   }
 
@@ -290,7 +394,7 @@ impl PagePool for PageMapWrapper {
 unsafe impl FrameAllocator<Size4KiB> for PageMapWrapper  {
   fn allocate_frame(&mut self) -> Option<PhysFrame> {
     trace!("allocating frame from pagemapper");
-    let pframe = self.allocate().unwrap();
+    let pframe = self.allocate_owned(FrameOwner::PageTable).unwrap();
     trace!("free frames remaining: {}", self.count_free());
     Some(pframe)
   }
@@ -40,12 +40,22 @@ pub fn get_pagemap<T, F>(run: F) -> T where F: for<'a> Fn(&'a Mapper) -> T {
   ret
 }
 
-pub fn get_pagetable<F>(run: F) where F: for<'a> Fn(&'a PageTable) {
+/// Translates the address without taking the page table lock, the result is
+/// unreliable while the tables are modified. Only meant for crash handling.
+pub unsafe fn translate_unlocked(addr: VirtAddr) -> Option<x86_64::PhysAddr> {
+  use x86_64::structures::paging::mapper::MapperAllSizes;
+  let physical_memory_offset = kinfo().get_pmo();
+  let level_4_table = active_level4_table(physical_memory_offset);
+  OffsetPageTable::new(level_4_table, physical_memory_offset).translate_addr(addr)
+}
+
+pub fn get_pagetable<T, F>(run: F) -> T where F: for<'a> Fn(&'a PageTable) -> T {
   let lock = LOCK.read();
   let physical_memory_offset = kinfo().get_pmo();
   let level_4_table = unsafe{active_level4_table(physical_memory_offset)};
-  run(&level_4_table);
+  let ret = run(&level_4_table);
   drop(lock);
+  ret
 }
 
 pub fn get_pagemap_mut<T, F>(mut run: F) -> T where F: for<'a> FnMut(&'a mut Mapper) -> T {
@@ -59,4 +69,109 @@ pub fn get_pagemap_mut<T, F>(mut run: F) -> T where F: for<'a> FnMut(&'a mut Map
 }
 
 
-pub use x86_64::structures::paging::{Page, PageTableFlags};
\ No newline at end of file
+pub use x86_64::structures::paging::{Page, PageTableFlags};
+const PML4_SPAN: u64 = 1 << 39;
+const PDPT_SPAN: u64 = 1 << 30;
+
+fn table_at<'a>(pa: PhysAddr) -> &'a mut PageTable {
+  let vaddr = kinfo().get_pmo() + pa.as_u64();
+  unsafe { &mut *vaddr.as_mut_ptr::<PageTable>() }
+}
+
+fn new_table() -> PhysAddr {
+  use crate::vmem::pagelist::FrameOwner;
+  let pa = crate::common::alloc_page_owned(FrameOwner::PageTable)
+    .expect("could not allocate page table");
+  crate::vmem::zero_frame(pa);
+  pa
+}
+
+fn clone_table(pa: PhysAddr) -> PhysAddr {
+  let new = new_table();
+  let src = table_at(pa);
+  let dst = table_at(new);
+  for (idx, entry) in src.iter().enumerate() {
+    dst[idx] = entry.clone();
+  }
+  new
+}
+
+fn canonical(addr: u64) -> u64 {
+  if addr & (1 << 47) != 0 { addr | 0xffff_0000_0000_0000 } else { addr }
+}
+
+/// Creates the page directories covering [start, end) without mapping anything.
+/// Page tables created later inside these directories are seen by every CPU.
+pub fn prefill_kernel_tables(start: VirtAddr, end: VirtAddr) {
+  let lock = LOCK.write();
+  let (l4_frame, _) = x86_64::registers::control::Cr3::read();
+  let l4 = table_at(l4_frame.start_address());
+  let mut addr = start.align_down(PDPT_SPAN);
+  while addr < end {
+    let l4_entry = &mut l4[addr.p4_index()];
+    if l4_entry.is_unused() {
+      l4_entry.set_addr(new_table(), PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+    }
+    let l3 = table_at(l4_entry.addr());
+    let l3_entry = &mut l3[addr.p3_index()];
+    if l3_entry.is_unused() {
+      l3_entry.set_addr(new_table(), PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+    }
+    addr += PDPT_SPAN;
+  }
+  drop(lock);
+}
+
+/// Replaces all entries of the table fully inside [start, end) with empty
+/// entries and recurses into tables only partially inside the range
+fn privatize(table_pa: PhysAddr, level: u64, base: u64, start: u64, end: u64) {
+  let shift = 39 - 9 * level;
+  let span = 1u64 << shift;
+  let table = table_at(table_pa);
+  for idx in 0..512 {
+    let entry_base = canonical(base + ((idx as u64) << shift));
+    let entry_end = entry_base.wrapping_add(span);
+    if entry_end <= start || entry_base >= end {
+      continue;
+    }
+    let entry = &mut table[idx];
+    if entry.is_unused() {
+      continue;
+    }
+    if entry_base >= start && entry_end <= end {
+      entry.set_unused();
+    } else if level < 3 && !entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+      let flags = entry.flags();
+      let private = clone_table(entry.addr());
+      entry.set_addr(private, flags);
+      privatize(private, level + 1, entry_base, start, end);
+    }
+  }
+}
+
+/// Creates a level 4 table for another CPU. Kernel memory is shared with the
+/// active table, task memory starts out unmapped so every CPU can map a
+/// different task. TLB shootdowns are not implemented, changes to existing
+/// kernel mappings are only visible once the other CPUs flush their TLB.
+pub fn clone_for_cpu() -> PhysFrame {
+  use crate::vmem::{CODE_START, DATA_END, STACK_END, STACK_START, UGUARD_PAGE, PAGE_SIZE};
+  let lock = LOCK.write();
+  let (l4_frame, _) = x86_64::registers::control::Cr3::read();
+  let l4 = clone_table(l4_frame.start_address());
+  // the physical memory mapping starts on a level 4 entry and may overlap
+  // the task code range, it must never be privatized
+  let pmo = kinfo().get_pmo().as_u64();
+  let ranges = [
+    (CODE_START as u64, core::cmp::min(DATA_END as u64, pmo)),
+    (core::cmp::max(CODE_START as u64, pmo.saturating_add(PML4_SPAN)), DATA_END as u64),
+    (UGUARD_PAGE as u64, (STACK_START + PAGE_SIZE) as u64),
+  ];
+  debug_assert!(STACK_END > UGUARD_PAGE);
+  for (start, end) in ranges.iter() {
+    if start < end {
+      privatize(l4, 0, 0, *start, *end);
+    }
+  }
+  drop(lock);
+  PhysFrame::containing_address(l4)
+}
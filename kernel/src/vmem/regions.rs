@@ -0,0 +1,99 @@
+use alloc::vec::Vec;
+use spin::RwLock;
+use crate::PhysAddr;
+
+/// Describes why a physical memory region is not part of the page pool
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionKind {
+  /// Reserved by firmware or hardware
+  Firmware,
+  /// ACPI tables, can be reclaimed once the tables were read
+  AcpiReclaimable,
+  /// ACPI non-volatile storage, never reclaimed
+  AcpiNvs,
+  /// Memory reported as defective
+  BadMemory,
+  /// Kernel image loaded by the bootloader
+  Kernel,
+  /// Stack the kernel was entered on
+  KernelStack,
+  /// Page tables created by the bootloader, these are the active page tables
+  PageTable,
+  /// Boot information passed by the bootloader
+  BootInfo,
+  /// Bootloader code and data, reclaimed once the kernel has taken over
+  Bootloader,
+  /// Static pages used to bootstrap the page pool
+  BootPages,
+  /// The first physical frame
+  FrameZero,
+  /// Reserved by the kernel for fixed use
+  KernelFixed,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ReservedRegion {
+  pub start: PhysAddr,
+  /// exclusive end of the region
+  pub end: PhysAddr,
+  pub kind: RegionKind,
+}
+
+impl ReservedRegion {
+  pub fn contains(&self, pa: PhysAddr) -> bool {
+    self.start <= pa && pa < self.end
+  }
+  pub fn overlaps(&self, start: PhysAddr, end: PhysAddr) -> bool {
+    self.start < end && start < self.end
+  }
+  pub fn pages(&self) -> u64 {
+    (self.end.as_u64() - self.start.as_u64()) / crate::vmem::PAGE_SIZE as u64
+  }
+}
+
+static RESERVED: RwLock<Vec<ReservedRegion>> = RwLock::new(Vec::new());
+
+/// Records a physical region as reserved, reserved regions are never added
+/// to the page pool unless they are reclaimed
+pub fn reserve(start: PhysAddr, end: PhysAddr, kind: RegionKind) {
+  trace!("reserving {:?}..{:?} as {:?}", start, end, kind);
+  if start >= end {
+    return
+  }
+  RESERVED.write().push(ReservedRegion { start, end, kind });
+}
+
+/// Returns all reserved regions, sorted by start address
+pub fn reserved_regions() -> Vec<ReservedRegion> {
+  let mut regions = RESERVED.read().clone();
+  regions.sort_by_key(|r| r.start);
+  regions
+}
+
+/// Returns the reserved region containing the address, if any
+pub fn find_reserved(pa: PhysAddr) -> Option<ReservedRegion> {
+  RESERVED.read().iter().find(|r| r.contains(pa)).cloned()
+}
+
+/// Returns the first reserved region overlapping the range, if any
+pub fn first_overlap(start: PhysAddr, end: PhysAddr) -> Option<ReservedRegion> {
+  RESERVED.read().iter()
+    .filter(|r| r.overlaps(start, end))
+    .min_by_key(|r| r.start)
+    .cloned()
+}
+
+/// Removes all reserved regions of the given kind and returns them
+pub fn take_reserved(kind: RegionKind) -> Vec<ReservedRegion> {
+  let mut reserved = RESERVED.write();
+  let taken = reserved.iter().filter(|r| r.kind == kind).cloned().collect();
+  reserved.retain(|r| r.kind != kind);
+  taken
+}
+
+pub fn print_reserved() {
+  for region in reserved_regions() {
+    debug!("reserved {:#018x}..{:#018x} {:>6} pages {:?}",
+      region.start.as_u64(), region.end.as_u64(), region.pages(), region.kind);
+  }
+}
@@ -0,0 +1,211 @@
+use x86_64::structures::paging::{PageTable, PageTableFlags};
+use crate::{PhysAddr, VirtAddr};
+
+/// Size of the pages backing a mapping
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum PageSize {
+  Size4KiB = 0,
+  Size2MiB = 1,
+  Size1GiB = 2,
+}
+
+impl PageSize {
+  pub fn bytes(&self) -> u64 {
+    match self {
+      PageSize::Size4KiB => 0x1000,
+      PageSize::Size2MiB => 0x20_0000,
+      PageSize::Size1GiB => 0x4000_0000,
+    }
+  }
+}
+
+/// A run of virtually and physically contiguous pages with identical flags
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mapping {
+  pub start: VirtAddr,
+  /// exclusive end of the mapping, 1 << 47 and 1 << 64 for mappings
+  /// reaching the end of the lower and upper half of the address space
+  pub end: u128,
+  /// frame backing the first page of the mapping
+  pub frame: PhysAddr,
+  pub flags: PageTableFlags,
+  pub size: PageSize,
+}
+
+impl Mapping {
+  pub fn bytes(&self) -> u64 {
+    // a mapping never crosses the hole between the halves, so it fits
+    (self.end - self.start.as_u64() as u128) as u64
+  }
+  pub fn pages(&self) -> u64 {
+    self.bytes() / self.size.bytes()
+  }
+  /// Returns true if the other mapping continues this mapping
+  fn continued_by(&self, other: &Mapping) -> bool {
+    self.end == other.start.as_u64() as u128
+      && self.size == other.size
+      && self.flags == other.flags
+      && self.frame + self.bytes() == other.frame
+  }
+}
+
+/// Flags the CPU sets on access, they are ignored when merging runs
+fn volatile_flags() -> PageTableFlags {
+  PageTableFlags::ACCESSED | PageTableFlags::DIRTY
+}
+
+/// Number of address bits covered by one entry on the given level,
+/// level 0 is the PML4
+fn level_shift(level: usize) -> u64 {
+  39 - 9 * level as u64
+}
+
+fn canonical(addr: u64) -> u64 {
+  if addr & (1 << 47) != 0 {
+    addr | 0xffff_0000_0000_0000
+  } else {
+    addr
+  }
+}
+
+/// Walks all present leaf entries of a page table hierarchy
+/// The walker reads the page tables through the physical memory map and
+/// must not be used while the tables are modified.
+pub struct LeafWalker {
+  pmo: VirtAddr,
+  tables: [*const PageTable; 4],
+  index: [usize; 4],
+  level: usize,
+  start: u64,
+  end: u128,
+}
+
+impl LeafWalker {
+  /// Walks the leaves of the given level 4 table inside [start, end),
+  /// the end is 1 << 64 to include the last page of the address space
+  pub unsafe fn new(pmo: VirtAddr, level_4_table: &PageTable, start: VirtAddr, end: u128) -> LeafWalker {
+    LeafWalker {
+      pmo,
+      tables: [level_4_table as *const PageTable, core::ptr::null(), core::ptr::null(), core::ptr::null()],
+      index: [0; 4],
+      level: 0,
+      start: start.as_u64(),
+      end,
+    }
+  }
+
+  fn entry_base(&self) -> u64 {
+    let mut addr = 0;
+    for level in 0..=self.level {
+      addr |= (self.index[level] as u64) << level_shift(level);
+    }
+    canonical(addr)
+  }
+
+  fn table(&self, pa: PhysAddr) -> *const PageTable {
+    (self.pmo + pa.as_u64()).as_ptr()
+  }
+}
+
+impl Iterator for LeafWalker {
+  type Item = Mapping;
+
+  fn next(&mut self) -> Option<Mapping> {
+    loop {
+      if self.index[self.level] >= 512 {
+        if self.level == 0 {
+          return None;
+        }
+        self.level -= 1;
+        self.index[self.level] += 1;
+        continue;
+      }
+      let base = self.entry_base();
+      // the end of an entry does not fit into u64 at the top of the address space
+      let end = base as u128 + (1u128 << level_shift(self.level));
+      if end <= self.start as u128 || base as u128 >= self.end {
+        self.index[self.level] += 1;
+        continue;
+      }
+      let entry = unsafe { &(*self.tables[self.level])[self.index[self.level]] };
+      let flags = entry.flags();
+      if !flags.contains(PageTableFlags::PRESENT) {
+        self.index[self.level] += 1;
+        continue;
+      }
+      let size = match self.level {
+        3 => Some(PageSize::Size4KiB),
+        2 if flags.contains(PageTableFlags::HUGE_PAGE) => Some(PageSize::Size2MiB),
+        1 if flags.contains(PageTableFlags::HUGE_PAGE) => Some(PageSize::Size1GiB),
+        _ => None,
+      };
+      match size {
+        Some(size) => {
+          self.index[self.level] += 1;
+          return Some(Mapping {
+            start: VirtAddr::new(base),
+            end,
+            frame: entry.addr(),
+            flags: flags - volatile_flags(),
+            size,
+          });
+        },
+        None => {
+          self.tables[self.level + 1] = self.table(entry.addr());
+          self.level += 1;
+          self.index[self.level] = 0;
+        },
+      }
+    }
+  }
+}
+
+/// Merges the leaves of a LeafWalker into contiguous runs
+pub struct Walker {
+  leaves: LeafWalker,
+  pending: Option<Mapping>,
+}
+
+impl Walker {
+  pub fn new(leaves: LeafWalker) -> Walker {
+    Walker { leaves, pending: None }
+  }
+}
+
+impl Iterator for Walker {
+  type Item = Mapping;
+
+  fn next(&mut self) -> Option<Mapping> {
+    let mut run = match self.pending.take() {
+      Some(run) => run,
+      None => self.leaves.next()?,
+    };
+    while let Some(next) = self.leaves.next() {
+      if run.continued_by(&next) {
+        run.end = next.end;
+      } else {
+        self.pending = Some(next);
+        break;
+      }
+    }
+    Some(run)
+  }
+}
+
+fn collect(start: VirtAddr, end: u128) -> alloc::vec::Vec<Mapping> {
+  let pmo = crate::kinfo().get_pmo();
+  crate::vmem::pagetable::get_pagetable(|apt: &PageTable| {
+    Walker::new(unsafe { LeafWalker::new(pmo, apt, start, end) }).collect()
+  })
+}
+
+/// Collects all mappings of the active page table inside [start, end)
+pub fn mappings(start: VirtAddr, end: VirtAddr) -> alloc::vec::Vec<Mapping> {
+  collect(start, end.as_u64() as u128)
+}
+
+/// Collects all mappings of the active page table
+pub fn all_mappings() -> alloc::vec::Vec<Mapping> {
+  collect(VirtAddr::new(0), 1 << 64)
+}
@@ -14,7 +14,11 @@ extern crate alloc;
 
 use symrfp::{SymbolType, get_symbol};
 
-extern "C" fn sighandler(sig: u64, id: u64) -> u64 {
+// path of the scheduler binary in the initramfs
+const SCHEDULER_PATH: &str = "sbin/scheduler";
+
+// exceptions are not recovered from, returning 0 terminates the task
+extern "C" fn sighandler(sig: u64, id: u64, _detail: u128) -> u64 {
   import_symbol!(bos_sig_handle, fn(u64, u64, u64));
   bos_sig_handle(sig, id, 0);
   0
@@ -64,12 +68,31 @@ fn task() -> Result<(), core::fmt::Error> {
     bos_log_debug("memory allocator ok!");
   }
   import_symbol!(bos_log_debug_fmt, fn(core::fmt::Arguments));
+  import_symbol!(bos_log_warn_fmt, fn(core::fmt::Arguments));
+  import_symbol!(bos_log_error_fmt, fn(core::fmt::Arguments));
   import_symbol!(bos_spawn_task, fn() -> u128);
   let scheduler_th = bos_spawn_task();
   bos_log_debug_fmt(format_args!("scheduler task handle: {:#018x}", scheduler_th));
   import_symbol!(bos_yield, fn(u128));
   sp.write_str("loaded smybols, setting up scheduler...\n")?;
-  //TODO: parse initramfs
+  import_symbol!(bos_get_initramfs, fn() -> &'static [u8]);
+  let initramfs = match initramfs::Archive::new(bos_get_initramfs()) {
+    Ok(archive) => archive,
+    Err(e) => {
+      bos_log_error_fmt(format_args!("could not parse initramfs: {:?}", e));
+      panic!()
+    }
+  };
+  bos_log_debug_fmt(format_args!("initramfs has {} files", initramfs.len()));
+  for entry in initramfs.entries() {
+    bos_log_debug_fmt(format_args!("  {:o} {:8} {}", entry.mode, entry.data.len(), entry.name));
+  }
+  match initramfs.find(SCHEDULER_PATH) {
+    Some(scheduler) if scheduler.is_file() => {
+      bos_log_debug_fmt(format_args!("found scheduler, {} bytes", scheduler.data.len()));
+    }
+    _ => bos_log_warn_fmt(format_args!("no scheduler at {} in initramfs", SCHEDULER_PATH)),
+  }
   //TODO: load scheduler binary
   //TODO: set scheduler
   //TODO: yield